@@ -1,3 +1,5 @@
 fn main() {
+    // Only the app has a Tauri config and capabilities to generate code for
+    #[cfg(feature = "gui")]
     tauri_build::build()
 }
@@ -0,0 +1,1037 @@
+use crate::bandwidth::Bandwidth;
+use crate::clipboard::{
+    Clipboard, ClipboardContent, ClipboardSyncMessage, LastClipboard, RgbaImage, SystemContent,
+};
+use crate::config::{generate_anime_name, load_config, save_config, AppConfig};
+use crate::confirmation::EventConfirmation;
+use crate::conflict::ConflictResolution;
+use crate::content::ContentType;
+use crate::discovery::{DiscoveryService, Peer};
+use crate::error::Error;
+use crate::events::EventSink;
+use crate::logging::{LogFilter, DEFAULT_LOG_FILTER};
+use crate::message::{LinkOpener, MessageBody, MessagePayload};
+use crate::network::{list_interfaces, start_network_watcher, NetworkInterface};
+use crate::progress::Direction;
+use crate::queue::{QueueItem, TransferQueue};
+use crate::server::{
+    start_server, Delivered, DeviceInfo, PartialUploads, ServerState, CONFIRMATION_TIMEOUT,
+};
+use crate::staging::StagedFiles;
+use crate::transfer::{
+    candidate_addresses, fetch_device_info, open_file, send_clipboard, send_file, send_message,
+    sync_clipboard, BroadcastSummary, FanOutSummary, FileSource, PeerTarget,
+};
+use crate::{clipboard, content, logging, staging, transfer};
+use futures::future;
+use log::{debug, error, info, warn};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tauri::ipc::InvokeBody;
+use tauri::{AppHandle, Emitter, Manager, RunEvent, State};
+use tauri_plugin_clipboard_manager::ClipboardExt;
+use tauri_plugin_opener::OpenerExt;
+
+impl EventSink for AppHandle {
+    fn emit_event(&self, event: &str, payload: serde_json::Value) -> Result<(), Error> {
+        self.emit(event, payload)
+            .map_err(|e| Error::Internal(format!("Failed to emit {}: {}", event, e)))
+    }
+}
+
+impl Clipboard for AppHandle {
+    fn read_raw(&self) -> Result<SystemContent, Error> {
+        // Images first, since copying one often puts a file name or URL
+        // next to it as text
+        if let Ok(image) = self.clipboard().read_image() {
+            return Ok(SystemContent::Image(RgbaImage {
+                width: image.width(),
+                height: image.height(),
+                rgba: image.rgba().to_vec(),
+            }));
+        }
+        match self.clipboard().read_text() {
+            Ok(text) if !text.is_empty() => Ok(SystemContent::Text(text)),
+            Ok(_) => Err(Error::NotFound("The clipboard is empty".to_string())),
+            Err(e) => Err(Error::NotFound(format!(
+                "Nothing to send on the clipboard: {}",
+                e
+            ))),
+        }
+    }
+
+    fn write(&self, content: &ClipboardContent) -> Result<(), Error> {
+        let written = match content {
+            ClipboardContent::Text { text } => self.clipboard().write_text(text.as_str()),
+            ClipboardContent::Image { png } => {
+                let image = RgbaImage::from_png(png)?;
+                self.clipboard()
+                    .write_image(&tauri::image::Image::new_owned(
+                        image.rgba,
+                        image.width,
+                        image.height,
+                    ))
+            }
+        };
+        written.map_err(|e| Error::Internal(format!("Failed to write clipboard: {}", e)))
+    }
+}
+
+impl LinkOpener for AppHandle {
+    fn open(&self, url: &str) -> Result<(), Error> {
+        self.opener()
+            .open_url(url, None::<&str>)
+            .map_err(|e| Error::Internal(format!("Failed to open link: {}", e)))
+    }
+}
+
+struct AppState {
+    config: Arc<Mutex<AppConfig>>,
+    confirmation: EventConfirmation,
+    bandwidth: Bandwidth,
+}
+
+// Learn more about Tauri commands at https://tauri.app/develop/calling-rust/
+#[tauri::command]
+fn greet(name: &str) -> String {
+    format!("Hello, {}! You've been greeted from Rust!", name)
+}
+
+#[tauri::command]
+fn get_settings(state: State<'_, AppState>) -> AppConfig {
+    state.config.lock().unwrap().clone()
+}
+
+#[tauri::command]
+fn save_settings(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    discovery: State<'_, DiscoveryService>,
+    queue: State<'_, TransferQueue>,
+    mut new_config: AppConfig,
+) -> Result<(), Error> {
+    let (old_alias, old_interfaces, device_id) = {
+        let config = state.config.lock().unwrap();
+        (
+            config.alias.clone(),
+            config.interfaces.clone(),
+            config.device_id.clone(),
+        )
+    };
+
+    // The device ID is not user-editable; keep it even if the frontend didn't send it back
+    new_config.device_id = device_id;
+
+    let mut config = state.config.lock().unwrap();
+    *config = new_config.clone();
+    save_config(&app, &new_config)?;
+    drop(config); // Release lock before doing heavy operations
+
+    queue.set_limits(new_config.queue_limits());
+    state.bandwidth.set_limits(new_config.bandwidth);
+
+    // If alias changed, re-register mDNS service and update discovery
+    if old_alias != new_config.alias {
+        info!(
+            "Alias changed from '{}' to '{}', re-registering service...",
+            old_alias, new_config.alias
+        );
+
+        discovery.register(&new_config, Duration::from_millis(1000))?;
+
+        // Update the discovery system with new alias
+        discovery.update_alias(new_config.alias.clone())?;
+
+        // Emit event to frontend to update UI
+        app.emit("alias-changed", new_config.alias.clone())
+            .map_err(|e| Error::Internal(e.to_string()))?;
+
+        info!("Service re-registered and discovery updated successfully");
+    } else if old_interfaces != new_config.interfaces {
+        info!(
+            "Interfaces changed from {:?} to {:?}, re-registering service...",
+            old_interfaces, new_config.interfaces
+        );
+        discovery.register(&new_config, Duration::from_millis(1000))?;
+    }
+
+    Ok(())
+}
+
+#[tauri::command]
+async fn send_file_to_peer(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    queue: State<'_, TransferQueue>,
+    peer_ip: String,
+    peer_port: u16,
+    file_path: String,
+    peer_addresses: Option<Vec<String>>,
+) -> Result<(), Error> {
+    let peer = queue_key(&peer_ip, peer_port);
+    let addresses = candidate_addresses(peer_ip, peer_addresses);
+    let file_name = queue_label(&file_path);
+    let retry = state.config.lock().unwrap().retry;
+    let throttle = state.bandwidth.throttle(Direction::Send, &peer);
+
+    // Waits for a free slot; the file is only opened once it's our turn
+    queue
+        .run(peer, file_name, async move {
+            let source = open_file_source(&app, &file_path).await?;
+            send_file(
+                Arc::new(app),
+                addresses,
+                peer_port,
+                source,
+                &retry,
+                throttle,
+            )
+            .await
+        })
+        .await
+}
+
+/// Send the same files to several peers. Each peer goes through the files
+/// at its own pace while all of them read each file from one open handle.
+/// Every upload is queued and retried like a single send. Failures for one
+/// peer don't stop the others; the summary lists how each file went for
+/// each peer.
+#[tauri::command]
+async fn send_files_to_peers(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    queue: State<'_, TransferQueue>,
+    file_paths: Vec<String>,
+    peers: Vec<PeerTarget>,
+) -> Result<FanOutSummary, Error> {
+    if peers.is_empty() {
+        return Err(Error::InvalidInput("No peers selected".to_string()));
+    }
+
+    let mut sources = Vec::new();
+    let mut unopened = Vec::new();
+    for file_path in file_paths {
+        match open_file_source(&app, &file_path).await {
+            Ok(source) => sources.push(source),
+            Err(e) => unopened.push((queue_label(&file_path), e)),
+        }
+    }
+
+    let retry = state.config.lock().unwrap().retry;
+    let mut summary = transfer::send_files_to_peers(
+        Arc::new(app.clone()),
+        &queue,
+        &peers,
+        sources,
+        &retry,
+        &state.bandwidth,
+    )
+    .await;
+    for (file_name, e) in unopened {
+        for peer in &peers {
+            summary.record(peer.key(), file_name.clone(), Err(e.clone()));
+        }
+    }
+
+    info!(
+        "Sent to {} peers: {} succeeded, {} failed",
+        peers.len(),
+        summary.succeeded,
+        summary.failed
+    );
+    Ok(summary)
+}
+
+/// Queue limits apply per peer, identified by its primary address.
+fn queue_key(peer_ip: &str, peer_port: u16) -> String {
+    format!("{}:{}", peer_ip, peer_port)
+}
+
+/// Name shown for a queued file until it is opened and its real name known.
+fn queue_label(file_path: &str) -> String {
+    file_path
+        .rsplit(['/', '\\'])
+        .next()
+        .filter(|name| !name.is_empty())
+        .unwrap_or(file_path)
+        .to_string()
+}
+
+/// Open a file path or, on Android, a `content://` URI for sending.
+async fn open_file_source(app: &AppHandle, file_path: &str) -> Result<FileSource, Error> {
+    // Handle Android content URIs differently
+    #[cfg(target_os = "android")]
+    if file_path.starts_with("content://") {
+        use tauri_plugin_android_fs::{AndroidFsExt, FileUri};
+        use tauri_plugin_fs::FilePath;
+
+        let api = app.android_fs_async();
+
+        // Convert String content URI to FileUri via FilePath
+        let url = url::Url::parse(file_path)
+            .map_err(|e| Error::InvalidInput(format!("Failed to parse content URI: {}", e)))?;
+        let fs_path = FilePath::Url(url);
+        let uri: FileUri = fs_path.into();
+
+        // Get file name from Android FS API
+        let name = api
+            .get_name(&uri)
+            .await
+            .map_err(|e| Error::Io(format!("Failed to get file name: {}", e)))?;
+        debug!(file = name.as_str(); "File name from Android FS");
+
+        // Get file size using metadata
+        let metadata = api
+            .get_metadata(&uri)
+            .await
+            .map_err(|e| Error::Io(format!("Failed to get file metadata: {}", e)))?;
+        let size = metadata.len();
+        debug!("File size: {}", size);
+
+        // Open file for reading using Android FS API (returns std::fs::File)
+        let std_file = api
+            .open_file_readable(&uri)
+            .await
+            .map_err(|e| Error::Io(format!("Failed to open file: {}", e)))?;
+
+        // Convert std::fs::File to tokio::fs::File for async operations
+        let file = tokio::fs::File::from_std(std_file);
+
+        return Ok(FileSource { name, size, file });
+    }
+
+    #[cfg(not(target_os = "android"))]
+    let _ = app;
+
+    open_file(file_path).await
+}
+
+/// Start handing a file over in chunks, for files the webview can read but
+/// the backend can't open by path. Returns the id for the other staging
+/// commands.
+#[tauri::command]
+async fn stage_file(staged: State<'_, StagedFiles>, file_name: String) -> Result<String, Error> {
+    staged.open(file_name).await
+}
+
+/// Append the raw request body to a staged file named by the `upload-id`
+/// header. Taking raw bytes keeps chunks from being serialized as JSON
+/// arrays.
+#[tauri::command]
+async fn append_staged_chunk(
+    staged: State<'_, StagedFiles>,
+    request: tauri::ipc::Request<'_>,
+) -> Result<u64, Error> {
+    let InvokeBody::Raw(chunk) = request.body() else {
+        return Err(Error::InvalidInput("Expected raw bytes".to_string()));
+    };
+    let id = request
+        .headers()
+        .get("upload-id")
+        .and_then(|id| id.to_str().ok())
+        .ok_or_else(|| Error::InvalidInput("Missing upload-id header".to_string()))?;
+    staged.append(id, chunk).await
+}
+
+#[tauri::command]
+async fn discard_staged_file(
+    staged: State<'_, StagedFiles>,
+    upload_id: String,
+) -> Result<(), Error> {
+    staged.discard(&upload_id).await;
+    Ok(())
+}
+
+/// Send a completely staged file to a peer and delete it afterwards.
+#[tauri::command]
+async fn send_staged_file(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    staged: State<'_, StagedFiles>,
+    upload_id: String,
+    peer_ip: String,
+    peer_port: u16,
+    peer_addresses: Option<Vec<String>>,
+) -> Result<(), Error> {
+    let (source, path) = staged.finish(&upload_id).await?;
+    // Whichever way sending ends, the file is closed by the time it is
+    // removed, which Windows insists on
+    let result = send_staged_source(app, &state, source, peer_ip, peer_port, peer_addresses).await;
+    staging::remove(&path).await;
+    result
+}
+
+async fn send_staged_source(
+    app: AppHandle,
+    state: &AppState,
+    mut source: FileSource,
+    peer_ip: String,
+    peer_port: u16,
+    peer_addresses: Option<Vec<String>>,
+) -> Result<(), Error> {
+    if !content::has_extension(&source.name) {
+        let detected = content::detect_file(&mut source.file)
+            .await
+            .map_err(|e| Error::io("Failed to read file", e))?;
+        source.name = infer_file_name(source.name, detected);
+    }
+
+    let peer = queue_key(&peer_ip, peer_port);
+    let addresses = candidate_addresses(peer_ip, peer_addresses);
+    let retry = state.config.lock().unwrap().retry;
+    let throttle = state.bandwidth.throttle(Direction::Send, &peer);
+    let queue = app.state::<TransferQueue>().inner().clone();
+    queue
+        .run(peer, source.name.clone(), async move {
+            send_file(
+                Arc::new(app),
+                addresses,
+                peer_port,
+                source,
+                &retry,
+                throttle,
+            )
+            .await
+        })
+        .await
+}
+
+/// A better name for files that arrive with a meaningless one, such as the
+/// `msf_1000285299` IDs of Android content URIs, based on what their content
+/// was detected as.
+fn infer_file_name(file_name: String, content: Option<ContentType>) -> String {
+    let meaningless = file_name.starts_with("msf_") || file_name.starts_with("document_");
+    let Some(content) = content.filter(|_| meaningless) else {
+        return file_name;
+    };
+
+    let ext = content.extension;
+    debug!(file = file_name.as_str(); "Inferred extension .{}", ext);
+    // For common types, use a generic but descriptive name
+    let inferred = match content.mime {
+        s if s.starts_with("image/") => format!("image.{}", ext),
+        s if s.starts_with("video/") => format!("video.{}", ext),
+        s if s.starts_with("audio/") => format!("audio.{}", ext),
+        "application/pdf" => format!("document.{}", ext),
+        content::APK_MIME => "app.apk".to_string(),
+        "application/zip" => format!("archive.{}", ext),
+        _ => format!("file.{}", ext),
+    };
+    debug!(file = inferred.as_str(); "Using inferred filename");
+    inferred
+}
+
+/// Send a message of any type to a peer, returning its ID for replies to
+/// refer to.
+#[tauri::command]
+async fn send_message_to_peer(
+    app: AppHandle,
+    peer_ip: String,
+    peer_port: u16,
+    message: MessageBody,
+    peer_addresses: Option<Vec<String>>,
+    state: State<'_, AppState>,
+) -> Result<String, Error> {
+    let (sender_alias, device_id, retry) = {
+        let config = state.config.lock().unwrap();
+        (config.alias.clone(), config.device_id.clone(), config.retry)
+    };
+    let message = MessagePayload {
+        device_id: Some(device_id),
+        ..MessagePayload::new(sender_alias, message)
+    };
+    let addresses = candidate_addresses(peer_ip, peer_addresses);
+    send_message(Arc::new(app), addresses, peer_port, &message, &retry).await?;
+    Ok(message.id)
+}
+
+/// Send a text message to every peer found on the network, or to the ones
+/// in `group`, all at once.
+#[tauri::command]
+async fn broadcast_text(
+    app: AppHandle,
+    text: String,
+    group: Option<String>,
+    state: State<'_, AppState>,
+    discovery: State<'_, DiscoveryService>,
+) -> Result<BroadcastSummary, Error> {
+    let (sender_alias, retry, members) = {
+        let config = state.config.lock().unwrap();
+        let members = match &group {
+            Some(name) => Some(config.peer_group(name)?.to_vec()),
+            None => None,
+        };
+        (config.alias.clone(), config.retry, members)
+    };
+
+    let peers: Vec<Peer> = discovery
+        .peers()
+        .into_iter()
+        .filter(|peer| match &members {
+            Some(members) => peer
+                .device_id
+                .as_ref()
+                .is_some_and(|id| members.contains(id)),
+            None => true,
+        })
+        .collect();
+    if peers.is_empty() {
+        return Err(Error::NotFound("No peers to broadcast to".to_string()));
+    }
+
+    let targets: Vec<PeerTarget> = peers
+        .iter()
+        .map(|peer| PeerTarget {
+            ip: peer.ip.clone(),
+            port: peer.port,
+            addresses: peer.addresses.clone(),
+        })
+        .collect();
+    let results =
+        transfer::broadcast_text(Arc::new(app), &targets, &text, &sender_alias, &retry).await;
+
+    let mut summary = BroadcastSummary::default();
+    for ((peer, target), result) in peers.iter().zip(&targets).zip(results) {
+        summary.record(target.key(), peer.alias.clone(), result);
+    }
+    info!(
+        "Broadcast to {} peers: {} delivered, {} failed",
+        peers.len(),
+        summary.delivered,
+        summary.failed
+    );
+    Ok(summary)
+}
+
+/// Send whatever text or image is on the local clipboard to a peer.
+#[tauri::command]
+async fn send_clipboard_to_peer(
+    app: AppHandle,
+    peer_ip: String,
+    peer_port: u16,
+    peer_addresses: Option<Vec<String>>,
+    state: State<'_, AppState>,
+) -> Result<(), Error> {
+    let content = Clipboard::read(&app)?;
+    let (sender_alias, device_id, retry) = {
+        let config = state.config.lock().unwrap();
+        (config.alias.clone(), config.device_id.clone(), config.retry)
+    };
+    info!(bytes = content.len(); "Sending clipboard to {}:{}", peer_ip, peer_port);
+    let addresses = candidate_addresses(peer_ip, peer_addresses);
+    send_clipboard(
+        Arc::new(app),
+        addresses,
+        peer_port,
+        content,
+        sender_alias,
+        device_id,
+        &retry,
+    )
+    .await
+}
+
+/// Push a local clipboard change to the sync devices that are around right
+/// now; ones that aren't miss it.
+async fn push_clipboard(app: &AppHandle, content: ClipboardContent, device_ids: Vec<String>) {
+    let message = {
+        let config = app.state::<AppState>().config.lock().unwrap().clone();
+        ClipboardSyncMessage {
+            device_id: config.device_id,
+            sender_alias: config.alias,
+            content,
+        }
+    };
+    let peers: Vec<Peer> = app
+        .state::<DiscoveryService>()
+        .peers()
+        .into_iter()
+        .filter(|peer| {
+            peer.device_id
+                .as_ref()
+                .is_some_and(|id| device_ids.contains(id))
+        })
+        .collect();
+    debug!(
+        bytes = message.content.len();
+        "Syncing clipboard to {} of {} devices", peers.len(), device_ids.len()
+    );
+
+    let message = &message;
+    future::join_all(peers.into_iter().map(|peer| async move {
+        let addresses = candidate_addresses(peer.ip, Some(peer.addresses));
+        if let Err(e) = sync_clipboard(addresses, peer.port, message).await {
+            warn!("Failed to sync clipboard to {}: {}", peer.alias, e);
+        }
+    }))
+    .await;
+}
+
+/// Put clipboard content a peer sent into the local clipboard.
+#[tauri::command]
+fn copy_to_clipboard(app: AppHandle, content: ClipboardContent) -> Result<(), Error> {
+    Clipboard::write(&app, &content)
+}
+
+#[tauri::command]
+fn refresh_peers(
+    state: State<'_, AppState>,
+    discovery: State<'_, DiscoveryService>,
+) -> Result<(), Error> {
+    info!("Refresh peers command called - re-registering service and refreshing discovery");
+
+    // Re-register the service to broadcast our presence again
+    // This ensures other clients can discover this client
+    let config = state.config.lock().unwrap().clone();
+    discovery.register(&config, Duration::from_millis(500))?;
+
+    // Refresh discovery to restart the browse daemon
+    discovery.refresh()?;
+
+    debug!("Service re-registered and discovery refreshed successfully");
+    Ok(())
+}
+
+#[tauri::command]
+fn get_transfer_queue(queue: State<'_, TransferQueue>) -> Vec<QueueItem> {
+    queue.items()
+}
+
+#[tauri::command]
+fn set_transfer_priority(
+    queue: State<'_, TransferQueue>,
+    id: String,
+    priority: i32,
+) -> Result<(), Error> {
+    queue.set_priority(&id, priority)
+}
+
+#[tauri::command]
+fn pause_transfer(queue: State<'_, TransferQueue>, id: String) -> Result<(), Error> {
+    queue.pause(&id)
+}
+
+#[tauri::command]
+fn resume_transfer(queue: State<'_, TransferQueue>, id: String) -> Result<(), Error> {
+    queue.resume(&id)
+}
+
+#[tauri::command]
+fn get_peers(discovery: State<'_, DiscoveryService>) -> Vec<Peer> {
+    discovery.peers()
+}
+
+#[tauri::command]
+async fn get_peer_info(peer_ip: String, peer_port: u16) -> Result<DeviceInfo, Error> {
+    fetch_device_info(&[peer_ip], peer_port).await
+}
+
+#[tauri::command]
+fn list_network_interfaces() -> Vec<NetworkInterface> {
+    list_interfaces()
+}
+
+#[tauri::command]
+fn generate_random_name() -> String {
+    generate_anime_name()
+}
+
+#[tauri::command]
+async fn scan_media_file(app: AppHandle, path: String) -> Result<(), Error> {
+    #[cfg(target_os = "android")]
+    {
+        use tauri_plugin_android_fs::{AndroidFsExt, FileUri};
+        use tauri_plugin_fs::FilePath;
+
+        // Use the Android FS plugin to scan the file
+        let api = app.android_fs_async();
+        let url = url::Url::parse(&path)
+            .map_err(|e| Error::InvalidInput(format!("Failed to parse content URI: {}", e)))?;
+        let fs_path = FilePath::Url(url);
+        let uri: FileUri = fs_path.into();
+        api.public_storage()
+            .scan(&uri)
+            .await
+            .map_err(|e| Error::Io(format!("Failed to scan media file: {}", e)))?;
+    }
+
+    #[cfg(not(target_os = "android"))]
+    {
+        let _ = app;
+        let _ = path;
+        // No-op on other platforms
+    }
+
+    Ok(())
+}
+
+#[tauri::command]
+fn respond_to_file_transfer(
+    state: State<'_, AppState>,
+    transfer_id: String,
+    accepted: bool,
+) -> Result<(), Error> {
+    state.confirmation.respond(&transfer_id, accepted)
+}
+
+#[tauri::command]
+fn respond_to_file_conflict(
+    state: State<'_, AppState>,
+    transfer_id: String,
+    resolution: ConflictResolution,
+) -> Result<(), Error> {
+    state
+        .confirmation
+        .respond_conflict(&transfer_id, resolution)
+}
+
+#[tauri::command]
+async fn get_file_name(app: AppHandle, file_path: String) -> Result<String, Error> {
+    // Handle Android content URIs like content://.../msf:1000285299
+    // or content://.../document/12345
+    if file_path.starts_with("content://") {
+        #[cfg(target_os = "android")]
+        {
+            use tauri_plugin_android_fs::{AndroidFsExt, FileUri};
+            use tauri_plugin_fs::FilePath;
+
+            // Use the Android FS plugin to get the file name from the URI
+            let api = app.android_fs_async();
+            let url = url::Url::parse(&file_path)
+                .map_err(|e| Error::InvalidInput(format!("Failed to parse content URI: {}", e)))?;
+            let fs_path = FilePath::Url(url);
+            let uri: FileUri = fs_path.into();
+            return match api.get_name(&uri).await {
+                Ok(name) => Ok(name),
+                Err(e) => {
+                    log::warn!("Failed to get name from Android FS API: {}", e);
+                    // Fallback: try to extract from URI
+                    if let Some(last_segment) = file_path.split('/').last() {
+                        // Remove any query parameters or fragments
+                        let clean_segment = last_segment.split('?').next().unwrap_or(last_segment);
+                        if clean_segment.contains('.') && !clean_segment.contains(':') {
+                            Ok(clean_segment.to_string())
+                        } else {
+                            Err(Error::NotFound(format!(
+                                "Failed to get filename from URI: {}",
+                                e
+                            )))
+                        }
+                    } else {
+                        Err(Error::NotFound(format!(
+                            "Failed to get filename from URI: {}",
+                            e
+                        )))
+                    }
+                }
+            };
+        }
+
+        #[cfg(not(target_os = "android"))]
+        {
+            // Only needed for the Android FS API
+            let _ = app;
+            return Err(Error::Unsupported(
+                "Content URIs are only supported on Android".to_string(),
+            ));
+        }
+    }
+
+    // Regular file path - extract filename
+    let path = std::path::Path::new(&file_path);
+    if let Some(file_name) = path.file_name() {
+        Ok(file_name.to_string_lossy().to_string())
+    } else {
+        Err(Error::InvalidInput(format!(
+            "Invalid file path: {}",
+            file_path
+        )))
+    }
+}
+
+#[tauri::command]
+fn open_file_location(file_path: String) -> Result<(), Error> {
+    #[cfg(target_os = "windows")]
+    {
+        use std::process::Command;
+
+        let path = std::path::Path::new(&file_path);
+
+        // Check if path exists and is a file
+        let is_file = path.exists() && path.is_file();
+
+        if is_file {
+            // Open folder and select the file
+            // Use /select, to open Explorer and highlight the file
+            let file_path_str = path
+                .to_str()
+                .ok_or_else(|| Error::InvalidInput("Invalid path encoding".to_string()))?;
+
+            Command::new("explorer.exe")
+                .args(&["/select,", file_path_str])
+                .spawn()
+                .map_err(|e| Error::io("Failed to open file location", e))?;
+        } else {
+            // If it's not a file or doesn't exist, try to open the directory
+            let dir_path = if path.is_dir() {
+                path
+            } else {
+                // Assume it's a file path and get parent directory
+                path.parent().ok_or_else(|| {
+                    Error::InvalidInput("Failed to get parent directory".to_string())
+                })?
+            };
+
+            let dir_str = dir_path
+                .to_str()
+                .ok_or_else(|| Error::InvalidInput("Invalid path encoding".to_string()))?;
+
+            // Just open the folder
+            Command::new("explorer.exe")
+                .arg(dir_str)
+                .spawn()
+                .map_err(|e| Error::io("Failed to open folder", e))?;
+        }
+
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    {
+        let _ = file_path;
+        Err(Error::Unsupported(
+            "open_file_location is only supported on Windows".to_string(),
+        ))
+    }
+}
+
+/// Save the kept logs to the Downloads directory for attaching to a bug
+/// report and return where they went.
+#[tauri::command]
+async fn export_logs(app: AppHandle, redact_file_names: bool) -> Result<String, Error> {
+    let log_dir = app
+        .path()
+        .app_log_dir()
+        .map_err(|e| Error::NotFound(format!("No log directory: {}", e)))?;
+    let logs = logging::export_logs(&log_dir, redact_file_names)?;
+    let file_name = format!(
+        "local-share-logs-{}.txt",
+        chrono::Local::now().format("%Y%m%d-%H%M%S")
+    );
+
+    #[cfg(target_os = "android")]
+    {
+        use tauri_plugin_android_fs::{AndroidFsExt, PublicGeneralPurposeDir};
+
+        app.android_fs_async()
+            .public_storage()
+            .write_new(
+                None,
+                PublicGeneralPurposeDir::Download,
+                &file_name,
+                Some("text/plain"),
+                logs.as_bytes(),
+            )
+            .await
+            .map_err(|e| Error::Io(format!("Failed to save logs: {}", e)))?;
+        Ok(file_name)
+    }
+
+    #[cfg(not(target_os = "android"))]
+    {
+        let dir = default_download_dir(&app);
+        std::fs::create_dir_all(&dir)
+            .map_err(|e| Error::io("Failed to create download directory", e))?;
+        let path = dir.join(file_name);
+        std::fs::write(&path, logs).map_err(|e| Error::io("Failed to save logs", e))?;
+        info!(path:? = path; "Exported logs");
+        Ok(path.to_string_lossy().to_string())
+    }
+}
+
+/// Get the proper Downloads directory for the platform
+fn default_download_dir(app: &AppHandle) -> PathBuf {
+    if cfg!(target_os = "android") {
+        // On Android, use the public Downloads directory
+        // This path is standard on Android
+        PathBuf::from("/storage/emulated/0/Downloads")
+    } else if cfg!(target_os = "windows") {
+        // On Windows, use the user's Downloads folder
+        app.path().download_dir().unwrap_or_else(|_| {
+            // Fallback: try to construct the path manually
+            if let Ok(user_profile) = std::env::var("USERPROFILE") {
+                PathBuf::from(user_profile).join("Downloads")
+            } else {
+                PathBuf::from("downloads")
+            }
+        })
+    } else {
+        // On other platforms (Linux, macOS), use the system download directory
+        app.path()
+            .download_dir()
+            .unwrap_or_else(|_| PathBuf::from("downloads"))
+    }
+}
+
+pub fn run() {
+    let builder = tauri::Builder::default();
+    // Received files are saved through MediaStore on Android
+    #[cfg(target_os = "android")]
+    let builder = builder.plugin(tauri_plugin_android_fs::init());
+
+    builder
+        .plugin(tauri_plugin_fs::init())
+        .plugin(tauri_plugin_dialog::init())
+        .plugin(tauri_plugin_opener::init())
+        .plugin(tauri_plugin_upload::init())
+        .plugin(tauri_plugin_http::init())
+        .plugin(tauri_plugin_clipboard_manager::init())
+        .setup(|app| {
+            // Log to a rotating file too, since stderr is invisible in release builds
+            let log_dir = app.path().app_log_dir().ok();
+            if let Err(e) =
+                logging::init(log_dir.as_deref(), LogFilter::from_env(DEFAULT_LOG_FILTER))
+            {
+                eprintln!("Failed to set up logging: {}", e);
+            }
+
+            let config = load_config(app.handle());
+            let port = config.port;
+
+            info!("Starting LocalShare Rust on port {}", port);
+            info!("Device alias: {}", config.alias);
+
+            // Register our service and start browsing for peers
+            let discovery = DiscoveryService::new(
+                Arc::new(app.handle().clone()),
+                tauri::async_runtime::handle().inner().clone(),
+            );
+            debug!("Starting discovery service...");
+            match discovery.start(&config) {
+                Ok(()) => info!("Service registered successfully"),
+                Err(e) => error!("Failed to register service: {}", e),
+            }
+            let peers = Arc::new(discovery.directory());
+            app.manage(discovery);
+
+            // Incoming files are confirmed by the user in the frontend
+            let confirmation = EventConfirmation::new(Arc::new(app.handle().clone()));
+
+            // Files the webview hands over in chunks are spooled here
+            let cache_dir = app
+                .path()
+                .app_cache_dir()
+                .unwrap_or_else(|_| std::env::temp_dir().join("local-share"));
+            app.manage(StagedFiles::new(cache_dir.join("staged")));
+
+            // Outgoing files wait here for a free slot
+            app.manage(TransferQueue::new(
+                Arc::new(app.handle().clone()),
+                tauri::async_runtime::handle().inner().clone(),
+                config.queue_limits(),
+            ));
+
+            // Shared by sends and the server so limit changes reach both
+            let bandwidth = Bandwidth::new(config.bandwidth);
+
+            let config = Arc::new(Mutex::new(config));
+
+            app.manage(AppState {
+                config: config.clone(),
+                confirmation: confirmation.clone(),
+                bandwidth: bandwidth.clone(),
+            });
+
+            // Re-advertise ourselves and re-bind browsing whenever interfaces or
+            // addresses change, e.g. Wi-Fi reconnects or a VPN comes up. Stops
+            // along with discovery.
+            let handle = app.handle().clone();
+            let watcher = start_network_watcher(move |interfaces| {
+                let config = handle.state::<AppState>().config.lock().unwrap().clone();
+                if let Err(e) = handle.state::<DiscoveryService>().restart(&config) {
+                    error!("Failed to restart discovery after network change: {}", e);
+                }
+                let _ = handle.emit("network-changed", interfaces);
+            });
+            app.state::<DiscoveryService>().watch_network(watcher);
+
+            // Push local clipboard changes to the sync devices while sync is on
+            let system_clipboard: Arc<dyn Clipboard> = Arc::new(app.handle().clone());
+            let last_clipboard = LastClipboard::default();
+            let handle = app.handle().clone();
+            tauri::async_runtime::spawn(clipboard::watch(
+                system_clipboard.clone(),
+                last_clipboard.clone(),
+                config.clone(),
+                move |content, device_ids| {
+                    let handle = handle.clone();
+                    async move { push_clipboard(&handle, content, device_ids).await }
+                },
+            ));
+
+            // Start HTTP Server
+            let server_state = ServerState {
+                events: Arc::new(app.handle().clone()),
+                confirmation: Arc::new(confirmation),
+                confirmation_timeout: CONFIRMATION_TIMEOUT,
+                download_dir: default_download_dir(app.handle()),
+                config,
+                partial_uploads: PartialUploads::default(),
+                delivered: Delivered::default(),
+                bandwidth,
+                clipboard: Some(system_clipboard),
+                last_clipboard,
+                link_opener: Some(Arc::new(app.handle().clone())),
+                peers,
+                #[cfg(target_os = "android")]
+                app_handle: app.handle().clone(),
+            };
+            tauri::async_runtime::spawn(async move {
+                debug!("Starting HTTP server...");
+                if let Err(e) = start_server(server_state, port).await {
+                    error!("HTTP server stopped: {}", e);
+                }
+            });
+
+            Ok(())
+        })
+        .invoke_handler(tauri::generate_handler![
+            greet,
+            get_settings,
+            save_settings,
+            send_file_to_peer,
+            send_files_to_peers,
+            stage_file,
+            append_staged_chunk,
+            discard_staged_file,
+            send_staged_file,
+            send_message_to_peer,
+            broadcast_text,
+            send_clipboard_to_peer,
+            copy_to_clipboard,
+            refresh_peers,
+            get_peers,
+            get_peer_info,
+            list_network_interfaces,
+            scan_media_file,
+            generate_random_name,
+            respond_to_file_transfer,
+            respond_to_file_conflict,
+            get_file_name,
+            open_file_location,
+            export_logs,
+            get_transfer_queue,
+            set_transfer_priority,
+            pause_transfer,
+            resume_transfer
+        ])
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|app, event| {
+            if let RunEvent::Exit = event {
+                // Send mDNS goodbyes so peers drop us right away
+                app.state::<DiscoveryService>().stop();
+            }
+        });
+}
@@ -0,0 +1,181 @@
+use crate::progress::Direction;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Longest a throttled transfer sleeps before looking at the rate again, so
+/// that raising a limit takes effect promptly
+const MAX_WAIT: Duration = Duration::from_millis(100);
+
+/// Rate limits in bytes per second; 0 means unlimited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BandwidthLimits {
+    /// All sends together
+    pub upload: u64,
+    /// All receives together
+    pub download: u64,
+    /// Sends to any single peer
+    pub upload_per_peer: u64,
+    /// Receives from any single peer
+    pub download_per_peer: u64,
+}
+
+impl BandwidthLimits {
+    fn per_peer(&self, direction: Direction) -> u64 {
+        match direction {
+            Direction::Send => self.upload_per_peer,
+            Direction::Receive => self.download_per_peer,
+        }
+    }
+}
+
+/// Token bucket allowing up to one second's worth of bytes in a burst.
+#[derive(Debug)]
+struct Bucket {
+    rate: u64,
+    /// Bytes that may go out right away; negative while a transfer waits
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate as f64).min(self.rate as f64);
+        self.refilled = now;
+    }
+}
+
+/// Limits the combined rate of everything that draws from it. The rate can
+/// change at any time and applies to transfers already waiting.
+#[derive(Debug, Clone)]
+pub struct RateLimiter(Arc<Mutex<Bucket>>);
+
+impl RateLimiter {
+    pub fn new(bytes_per_second: u64) -> Self {
+        Self(Arc::new(Mutex::new(Bucket {
+            rate: bytes_per_second,
+            tokens: bytes_per_second as f64,
+            refilled: Instant::now(),
+        })))
+    }
+
+    pub fn set_rate(&self, bytes_per_second: u64) {
+        let mut bucket = self.0.lock().unwrap();
+        bucket.refill();
+        bucket.rate = bytes_per_second;
+        bucket.tokens = bucket.tokens.min(bytes_per_second as f64);
+    }
+
+    /// Wait until `bytes` may be transferred. Bytes are taken up front, so a
+    /// chunk larger than the burst just waits longer instead of never fitting.
+    pub async fn acquire(&self, bytes: u64) {
+        {
+            let mut bucket = self.0.lock().unwrap();
+            if bucket.rate == 0 {
+                return;
+            }
+            bucket.refill();
+            bucket.tokens -= bytes as f64;
+        }
+
+        loop {
+            let wait = {
+                let mut bucket = self.0.lock().unwrap();
+                if bucket.rate == 0 {
+                    // The limit was lifted; forget the debt
+                    bucket.tokens = 0.0;
+                    return;
+                }
+                bucket.refill();
+                if bucket.tokens >= 0.0 {
+                    return;
+                }
+                Duration::from_secs_f64(-bucket.tokens / bucket.rate as f64).min(MAX_WAIT)
+            };
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/// The limiters one transfer draws from: the overall one for its direction
+/// and the one for its peer.
+#[derive(Debug, Clone)]
+pub struct Throttle {
+    total: RateLimiter,
+    peer: RateLimiter,
+}
+
+impl Throttle {
+    /// A throttle that never waits.
+    pub fn unlimited() -> Self {
+        Self {
+            total: RateLimiter::new(0),
+            peer: RateLimiter::new(0),
+        }
+    }
+
+    pub async fn acquire(&self, bytes: u64) {
+        self.peer.acquire(bytes).await;
+        self.total.acquire(bytes).await;
+    }
+}
+
+#[derive(Debug)]
+struct BandwidthState {
+    limits: BandwidthLimits,
+    upload: RateLimiter,
+    download: RateLimiter,
+    peers: HashMap<(Direction, String), RateLimiter>,
+}
+
+/// Shared upload and download limits. Every transfer takes a [`Throttle`]
+/// from here, so [`Bandwidth::set_limits`] slows down or speeds up running
+/// transfers without restarting them.
+#[derive(Debug, Clone)]
+pub struct Bandwidth(Arc<Mutex<BandwidthState>>);
+
+impl Bandwidth {
+    pub fn new(limits: BandwidthLimits) -> Self {
+        Self(Arc::new(Mutex::new(BandwidthState {
+            limits,
+            upload: RateLimiter::new(limits.upload),
+            download: RateLimiter::new(limits.download),
+            peers: HashMap::new(),
+        })))
+    }
+
+    pub fn set_limits(&self, limits: BandwidthLimits) {
+        let mut state = self.0.lock().unwrap();
+        state.limits = limits;
+        state.upload.set_rate(limits.upload);
+        state.download.set_rate(limits.download);
+        for ((direction, _), limiter) in &state.peers {
+            limiter.set_rate(limits.per_peer(*direction));
+        }
+    }
+
+    /// The throttle for a transfer in `direction` with the peer known as `peer`.
+    pub fn throttle(&self, direction: Direction, peer: &str) -> Throttle {
+        let mut state = self.0.lock().unwrap();
+        // Peers whose transfers have all finished don't need a limiter anymore
+        state
+            .peers
+            .retain(|_, limiter| Arc::strong_count(&limiter.0) > 1);
+
+        let rate = state.limits.per_peer(direction);
+        let peer = state
+            .peers
+            .entry((direction, peer.to_string()))
+            .or_insert_with(|| RateLimiter::new(rate))
+            .clone();
+        let total = match direction {
+            Direction::Send => state.upload.clone(),
+            Direction::Receive => state.download.clone(),
+        };
+        Throttle { total, peer }
+    }
+}
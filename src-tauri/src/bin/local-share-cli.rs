@@ -0,0 +1,514 @@
+//! Headless client for machines without a display, such as servers or CI
+//! runners. Speaks the same protocol as the app, so the two can exchange
+//! files and messages.
+
+use clap::{Parser, Subcommand};
+use local_share_lib::bandwidth::Bandwidth;
+use local_share_lib::clipboard::LastClipboard;
+use local_share_lib::config::{load_config_file, AppConfig};
+use local_share_lib::confirmation::{
+    AutoConfirmation, ChannelConfirmation, ConfirmationProvider, PendingConfirmation,
+};
+use local_share_lib::discovery::{DiscoveryService, Peer};
+use local_share_lib::error::Error;
+use local_share_lib::events::EventSink;
+use local_share_lib::logging::{self, LogFilter};
+use local_share_lib::progress::Direction;
+use local_share_lib::server::{
+    start_server, Delivered, PartialUploads, ServerState, CONFIRMATION_TIMEOUT,
+};
+use local_share_lib::transfer::{broadcast_text, open_file, send_file, send_text, PeerTarget};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::runtime::Handle;
+use tokio::sync::mpsc;
+
+/// Port assumed for peers given as a bare IP address
+const DEFAULT_PORT: u16 = 3030;
+
+#[derive(Parser)]
+#[command(
+    name = "local-share-cli",
+    version,
+    about = "Share files and messages over the local network without the GUI"
+)]
+struct Cli {
+    /// Settings file [default: ~/.config/local-share-cli/settings.json]
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+
+    /// Name shown to other devices, instead of the one in the settings file
+    #[arg(long, global = true)]
+    alias: Option<String>,
+
+    /// Seconds to spend looking for peers on the network
+    #[arg(long, global = true, default_value_t = 5)]
+    timeout: u64,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// List the devices found on the local network
+    ListPeers,
+    /// Send one or more files to a peer
+    Send {
+        /// Alias, device ID, IP or IP:port of the receiving device
+        peer: String,
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Send a text message to a peer
+    SendText {
+        /// Alias, device ID, IP or IP:port of the receiving device
+        peer: String,
+        text: String,
+    },
+    /// Send a text message to every peer on the network at once
+    Broadcast {
+        text: String,
+        /// Only send to the devices in this group from the settings file
+        #[arg(long)]
+        group: Option<String>,
+    },
+    /// Advertise this device and save incoming files until interrupted
+    Receive {
+        /// Directory to save received files in
+        #[arg(long)]
+        dir: PathBuf,
+        /// Accept every incoming file without asking
+        #[arg(long)]
+        auto_accept: bool,
+        /// Port to listen on, instead of the one in the settings file
+        #[arg(long)]
+        port: Option<u16>,
+    },
+}
+
+/// Prints transfer progress, received files and messages to the terminal.
+#[derive(Default)]
+struct TerminalSink {
+    /// Last printed percentage per transfer, so progress lines aren't repeated
+    progress: Mutex<HashMap<String, u64>>,
+}
+
+impl TerminalSink {
+    fn print_progress(&self, payload: &Value) {
+        let transfer_id = payload["transfer_id"].as_str().unwrap_or_default();
+        let current = payload["current_bytes"].as_u64().unwrap_or_default();
+        let total = match payload["total_bytes"].as_u64() {
+            Some(total) if total > 0 => total,
+            _ => return,
+        };
+
+        let percent = current * 100 / total;
+        let mut progress = self.progress.lock().unwrap();
+        if progress.get(transfer_id) == Some(&percent) {
+            return;
+        }
+        progress.insert(transfer_id.to_string(), percent);
+
+        let speed = payload["bytes_per_second"].as_u64().unwrap_or_default();
+        let eta = match payload["eta_ms"].as_u64() {
+            Some(eta) if percent < 100 => format!(", {}s left", eta.div_ceil(1000)),
+            _ => String::new(),
+        };
+        println!(
+            "{}: {}% ({} / {} bytes, {}/s{})",
+            transfer_id,
+            percent,
+            current,
+            total,
+            format_bytes(speed),
+            eta
+        );
+    }
+}
+
+/// `bytes` with a binary unit, e.g. `1.5 MiB`.
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+impl EventSink for TerminalSink {
+    fn emit_event(&self, event: &str, payload: Value) -> Result<(), Error> {
+        match event {
+            "transfer-progress" => self.print_progress(&payload),
+            "file-receive-complete" => {
+                let saved_as = payload["file_path"]
+                    .as_str()
+                    .or(payload["file_name"].as_str())
+                    .unwrap_or_default();
+                println!("Received {}", saved_as);
+            }
+            "transfer-summary" => println!(
+                "{} {} in {:.1}s ({}/s on average)",
+                if payload["direction"] == "send" {
+                    "Sent"
+                } else {
+                    "Received"
+                },
+                format_bytes(payload["bytes"].as_u64().unwrap_or_default()),
+                payload["elapsed_ms"].as_u64().unwrap_or_default() as f64 / 1000.0,
+                format_bytes(
+                    payload["average_bytes_per_second"]
+                        .as_u64()
+                        .unwrap_or_default()
+                )
+            ),
+            "file-transfer-rejected" => {
+                println!("Rejected {}", payload.as_str().unwrap_or_default())
+            }
+            "file-transfer-timeout" => println!(
+                "No answer in time for {}",
+                payload.as_str().unwrap_or_default()
+            ),
+            "file-receive-error" => {
+                println!("Failed to receive {}", payload.as_str().unwrap_or_default())
+            }
+            "file-receive-interrupted" => println!(
+                "Connection lost while receiving {}, waiting for the sender to resume",
+                payload.as_str().unwrap_or_default()
+            ),
+            "transfer-retrying" => println!(
+                "Retrying {} to {} (attempt {} of {}) in {} ms: {}",
+                payload["name"].as_str().unwrap_or_default(),
+                payload["peer"].as_str().unwrap_or_default(),
+                payload["attempt"],
+                payload["max_attempts"],
+                payload["delay_ms"],
+                payload["error"]["message"].as_str().unwrap_or_default()
+            ),
+            "message-received" => println!(
+                "Message from {}: {}",
+                payload["sender_alias"].as_str().unwrap_or_default(),
+                payload["content"].as_str().unwrap_or_default()
+            ),
+            // Peer list updates are only interesting to the GUI
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// Ask on stdin whether to accept each incoming file, one at a time.
+async fn prompt_for_confirmations(mut requests: mpsc::UnboundedReceiver<PendingConfirmation>) {
+    while let Some(pending) = requests.recv().await {
+        let request = &pending.request;
+        let size = request
+            .file_size
+            .map(|size| format!("{} bytes", size))
+            .unwrap_or_else(|| "unknown size".to_string());
+        let question = format!("Accept {} ({})? [y/N] ", request.file_name, size);
+
+        let accepted = tokio::task::spawn_blocking(move || {
+            print!("{}", question);
+            let _ = io::stdout().flush();
+
+            let mut answer = String::new();
+            io::stdin().lock().read_line(&mut answer).is_ok()
+                && matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+        })
+        .await
+        .unwrap_or(false);
+
+        pending.respond(accepted);
+    }
+}
+
+fn default_config_path() -> PathBuf {
+    let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("APPDATA").map(PathBuf::from))
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .unwrap_or_else(|| PathBuf::from("."));
+    config_dir.join("local-share-cli").join("settings.json")
+}
+
+/// Browse for `timeout` and return the peers found. With `stop_when`, returns
+/// as soon as a matching peer shows up.
+async fn discover_peers(
+    config: &AppConfig,
+    timeout: Duration,
+    stop_when: impl Fn(&Peer) -> bool,
+) -> Vec<Peer> {
+    let discovery = DiscoveryService::new(Arc::new(TerminalSink::default()), Handle::current());
+    discovery.start_browsing(config.alias.clone(), config.device_id.clone());
+
+    let deadline = Instant::now() + timeout;
+    while Instant::now() < deadline {
+        if discovery.peers().iter().any(&stop_when) {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+
+    let peers = discovery.peers();
+    // Joins the browse thread, keep it off the async workers
+    let _ = tokio::task::spawn_blocking(move || drop(discovery)).await;
+    peers
+}
+
+fn peer_matches(peer: &Peer, query: &str) -> bool {
+    peer.alias.eq_ignore_ascii_case(query)
+        || peer.device_id.as_deref() == Some(query)
+        || peer.addresses.iter().any(|addr| addr == query)
+}
+
+/// Turn the `peer` argument into the addresses and port to send to.
+async fn resolve_peer(
+    query: &str,
+    config: &AppConfig,
+    timeout: Duration,
+) -> Result<(Vec<String>, u16), Error> {
+    if let Ok(addr) = query.parse::<SocketAddr>() {
+        return Ok((vec![addr.ip().to_string()], addr.port()));
+    }
+    if let Ok(ip) = query.parse::<IpAddr>() {
+        return Ok((vec![ip.to_string()], DEFAULT_PORT));
+    }
+
+    eprintln!("Looking for {}...", query);
+    let peers = discover_peers(config, timeout, |peer| peer_matches(peer, query)).await;
+    peers
+        .into_iter()
+        .find(|peer| peer_matches(peer, query))
+        .map(|peer| (peer.addresses, peer.port))
+        .ok_or_else(|| Error::NotFound(format!("No peer named {} found on the network", query)))
+}
+
+async fn list_peers(config: &AppConfig, timeout: Duration) -> Result<(), Error> {
+    let mut peers = discover_peers(config, timeout, |_| false).await;
+    if peers.is_empty() {
+        println!("No peers found");
+        return Ok(());
+    }
+
+    peers.sort_by(|a, b| a.alias.cmp(&b.alias));
+    for peer in peers {
+        println!(
+            "{}\t{}\t{}:{}\t{}",
+            peer.alias,
+            peer.device_type.as_deref().unwrap_or("unknown"),
+            peer.ip,
+            peer.port,
+            peer.device_id.as_deref().unwrap_or("-")
+        );
+    }
+    Ok(())
+}
+
+async fn send(
+    config: &AppConfig,
+    timeout: Duration,
+    peer: &str,
+    files: Vec<PathBuf>,
+) -> Result<(), Error> {
+    let (addresses, port) = resolve_peer(peer, config, timeout).await?;
+    let events: Arc<dyn EventSink> = Arc::new(TerminalSink::default());
+    let bandwidth = Bandwidth::new(config.bandwidth);
+
+    for path in files {
+        let source = open_file(&path.to_string_lossy()).await?;
+        let name = source.name.clone();
+        send_file(
+            events.clone(),
+            addresses.clone(),
+            port,
+            source,
+            &config.retry,
+            bandwidth.throttle(Direction::Send, peer),
+        )
+        .await
+        .inspect_err(|_| eprintln!("Failed to send {}", name))?;
+        println!("Sent {}", name);
+    }
+    Ok(())
+}
+
+async fn broadcast(
+    config: &AppConfig,
+    timeout: Duration,
+    text: &str,
+    group: Option<&str>,
+) -> Result<(), Error> {
+    let members = group.map(|name| config.peer_group(name)).transpose()?;
+
+    eprintln!("Looking for peers...");
+    let peers: Vec<Peer> = discover_peers(config, timeout, |_| false)
+        .await
+        .into_iter()
+        .filter(|peer| match members {
+            Some(members) => peer
+                .device_id
+                .as_ref()
+                .is_some_and(|id| members.contains(id)),
+            None => true,
+        })
+        .collect();
+    if peers.is_empty() {
+        return Err(Error::NotFound("No peers to broadcast to".to_string()));
+    }
+
+    let targets: Vec<PeerTarget> = peers
+        .iter()
+        .map(|peer| PeerTarget {
+            ip: peer.ip.clone(),
+            port: peer.port,
+            addresses: peer.addresses.clone(),
+        })
+        .collect();
+    let events: Arc<dyn EventSink> = Arc::new(TerminalSink::default());
+    let results = broadcast_text(events, &targets, text, &config.alias, &config.retry).await;
+
+    let mut failed = 0;
+    for (peer, result) in peers.iter().zip(results) {
+        match result {
+            Ok(()) => println!("Delivered to {}", peer.alias),
+            Err(e) => {
+                failed += 1;
+                println!("Failed to reach {}: {}", peer.alias, e);
+            }
+        }
+    }
+    if failed > 0 {
+        return Err(Error::Network(format!(
+            "{} of {} peers didn't get the message",
+            failed,
+            peers.len()
+        )));
+    }
+    Ok(())
+}
+
+async fn receive(
+    mut config: AppConfig,
+    dir: PathBuf,
+    auto_accept: bool,
+    port: Option<u16>,
+) -> Result<(), Error> {
+    if let Some(port) = port {
+        config.port = port;
+    }
+
+    let events: Arc<dyn EventSink> = Arc::new(TerminalSink::default());
+    let confirmation: Arc<dyn ConfirmationProvider> = if auto_accept {
+        Arc::new(AutoConfirmation(true))
+    } else {
+        let (confirmation, requests) = ChannelConfirmation::new();
+        tokio::spawn(prompt_for_confirmations(requests));
+        Arc::new(confirmation)
+    };
+
+    // Only advertise; receiving doesn't need a peer list
+    let discovery = DiscoveryService::new(events.clone(), Handle::current());
+    discovery.register(&config, Duration::ZERO)?;
+
+    println!(
+        "Receiving as {} on port {}, saving to {}",
+        config.alias,
+        config.port,
+        dir.display()
+    );
+
+    let port = config.port;
+    let bandwidth = Bandwidth::new(config.bandwidth);
+    let state = ServerState {
+        events,
+        confirmation,
+        confirmation_timeout: CONFIRMATION_TIMEOUT,
+        download_dir: dir,
+        config: Arc::new(Mutex::new(config)),
+        partial_uploads: PartialUploads::default(),
+        delivered: Delivered::default(),
+        bandwidth,
+        clipboard: None,
+        last_clipboard: LastClipboard::default(),
+        link_opener: None,
+        // Neither links nor the clipboard are touched here
+        peers: Arc::new(discovery.directory()),
+    };
+
+    let result = tokio::select! {
+        result = start_server(state, port) => result,
+        _ = tokio::signal::ctrl_c() => Ok(()),
+    };
+
+    // Sends the mDNS goodbye
+    let _ = tokio::task::spawn_blocking(move || drop(discovery)).await;
+    result
+}
+
+async fn run(cli: Cli) -> Result<(), Error> {
+    let config_path = cli.config.unwrap_or_else(default_config_path);
+    let mut config = load_config_file(&config_path);
+    if let Some(alias) = cli.alias {
+        config.alias = alias;
+    }
+    let timeout = Duration::from_secs(cli.timeout);
+
+    match cli.command {
+        Command::ListPeers => list_peers(&config, timeout).await,
+        Command::Send { peer, files } => send(&config, timeout, &peer, files).await,
+        Command::SendText { peer, text } => {
+            let (addresses, port) = resolve_peer(&peer, &config, timeout).await?;
+            let events = Arc::new(TerminalSink::default());
+            send_text(
+                events,
+                addresses,
+                port,
+                text,
+                config.alias.clone(),
+                &config.retry,
+            )
+            .await?;
+            println!("Message sent");
+            Ok(())
+        }
+        Command::Broadcast { text, group } => {
+            broadcast(&config, timeout, &text, group.as_deref()).await
+        }
+        Command::Receive {
+            dir,
+            auto_accept,
+            port,
+        } => receive(config, dir, auto_accept, port).await,
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    // Terminal output is the interface here; diagnostics only on request
+    // through LOCAL_SHARE_LOG
+    if let Err(e) = logging::init(None, LogFilter::from_env("warn")) {
+        eprintln!("Failed to set up logging: {}", e);
+    }
+
+    match run(cli).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,344 @@
+use crate::config::AppConfig;
+use crate::error::Error;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::MissedTickBehavior;
+
+/// Largest clipboard content, in bytes, a peer may send; a screenshot of a
+/// large display as PNG stays well below it
+pub const MAX_CLIPBOARD_BYTES: usize = 32 * 1024 * 1024;
+
+/// Default for [`ClipboardSettings::sync_max_bytes`]
+const DEFAULT_SYNC_MAX_BYTES: usize = 4 * 1024 * 1024;
+
+/// How often the sync watcher looks for clipboard changes. System
+/// clipboards don't announce changes, so it has to poll.
+pub const SYNC_POLL_INTERVAL: Duration = Duration::from_millis(750);
+
+/// What is on a clipboard, as sent between peers.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ClipboardContent {
+    Text {
+        text: String,
+    },
+    /// An image, PNG-encoded so it travels compressed
+    Image {
+        #[serde(with = "base64_bytes")]
+        png: Vec<u8>,
+    },
+}
+
+impl ClipboardContent {
+    /// Encode an RGBA image as it comes from the system clipboard.
+    pub fn image(width: u32, height: u32, rgba: &[u8]) -> Result<Self, Error> {
+        let mut png = Vec::new();
+        let mut encoder = png::Encoder::new(&mut png, width, height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder
+            .write_header()
+            .and_then(|mut writer| writer.write_image_data(rgba))
+            .map_err(|e| Error::InvalidInput(format!("Failed to encode image: {}", e)))?;
+        Ok(Self::Image { png })
+    }
+
+    /// Size of the content as sent.
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Text { text } => text.len(),
+            Self::Image { png } => png.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// An image decoded to 8-bit RGBA, the form system clipboards take.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RgbaImage {
+    pub width: u32,
+    pub height: u32,
+    pub rgba: Vec<u8>,
+}
+
+impl RgbaImage {
+    /// Decode a PNG of any color type and bit depth.
+    pub fn from_png(png: &[u8]) -> Result<Self, Error> {
+        let invalid = |e: png::DecodingError| Error::InvalidInput(format!("Invalid image: {}", e));
+
+        let mut decoder = png::Decoder::new(png);
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info().map_err(invalid)?;
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buffer).map_err(invalid)?;
+        buffer.truncate(info.buffer_size());
+
+        let rgba = match info.color_type {
+            png::ColorType::Rgba => buffer,
+            png::ColorType::Rgb => buffer
+                .chunks_exact(3)
+                .flat_map(|p| [p[0], p[1], p[2], 0xFF])
+                .collect(),
+            png::ColorType::GrayscaleAlpha => buffer
+                .chunks_exact(2)
+                .flat_map(|p| [p[0], p[0], p[0], p[1]])
+                .collect(),
+            png::ColorType::Grayscale => buffer.iter().flat_map(|&g| [g, g, g, 0xFF]).collect(),
+            png::ColorType::Indexed => {
+                // Expanded to RGB(A) by the transformations above
+                return Err(Error::InvalidInput("Unexpected indexed image".to_string()));
+            }
+        };
+
+        Ok(Self {
+            width: info.width,
+            height: info.height,
+            rgba,
+        })
+    }
+}
+
+/// Content as a system clipboard holds it, with images not yet encoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SystemContent {
+    Text(String),
+    Image(RgbaImage),
+}
+
+impl SystemContent {
+    /// Decode content from a peer into the form the clipboard takes.
+    pub fn decode(content: &ClipboardContent) -> Result<Self, Error> {
+        match content {
+            ClipboardContent::Text { text } => Ok(Self::Text(text.clone())),
+            ClipboardContent::Image { png } => RgbaImage::from_png(png).map(Self::Image),
+        }
+    }
+
+    /// Encode for sending to a peer.
+    pub fn encode(&self) -> Result<ClipboardContent, Error> {
+        match self {
+            Self::Text(text) => Ok(ClipboardContent::Text { text: text.clone() }),
+            Self::Image(image) => ClipboardContent::image(image.width, image.height, &image.rgba),
+        }
+    }
+
+    /// SHA-256 of the kind, size and data, to tell contents apart without
+    /// encoding images.
+    fn fingerprint(&self) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        match self {
+            Self::Text(text) => {
+                hasher.update(b"text:");
+                hasher.update(text.as_bytes());
+            }
+            Self::Image(image) => {
+                hasher.update(b"image:");
+                hasher.update(image.width.to_le_bytes());
+                hasher.update(image.height.to_le_bytes());
+                hasher.update(&image.rgba);
+            }
+        }
+        hasher.finalize().into()
+    }
+}
+
+/// Body of `POST /clipboard`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClipboardMessage {
+    /// Stays the same across retries, so the receiver handles it once;
+    /// made up on arrival for older peers that don't send one
+    #[serde(default = "new_push_id")]
+    pub id: String,
+    pub sender_alias: String,
+    /// The sender's device ID; the content is only written to the clipboard
+    /// if that device was discovered where the push came from. Older peers
+    /// don't send one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device_id: Option<String>,
+    #[serde(flatten)]
+    pub content: ClipboardContent,
+}
+
+fn new_push_id() -> String {
+    uuid::Uuid::new_v4().to_string()
+}
+
+/// Body of `POST /clipboard/sync`. Only accepted from devices the receiver
+/// syncs with, which are told apart by their ID.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClipboardSyncMessage {
+    pub device_id: String,
+    pub sender_alias: String,
+    #[serde(flatten)]
+    pub content: ClipboardContent,
+}
+
+/// The local system clipboard. The app's goes through the clipboard
+/// manager plugin; the server only sees this trait.
+/// Calls may block, so async code makes them through `spawn_blocking`.
+pub trait Clipboard: Send + Sync {
+    /// The current content as the system holds it; an error if the
+    /// clipboard is empty or holds something other than text or an image.
+    fn read_raw(&self) -> Result<SystemContent, Error>;
+    fn write(&self, content: &ClipboardContent) -> Result<(), Error>;
+
+    /// The current content, ready to send.
+    fn read(&self) -> Result<ClipboardContent, Error> {
+        self.read_raw()?.encode()
+    }
+}
+
+/// Clipboard handling settings.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClipboardSettings {
+    /// Put what discovered peers send straight into the local clipboard
+    /// instead of only showing it
+    pub write_received: bool,
+    /// Keep the clipboard the same as on `sync_peers`: local changes are
+    /// pushed to them and theirs are taken over
+    pub sync: bool,
+    /// IDs of the devices to sync with. These are trusted to change the
+    /// clipboard without asking; sync from any other device is refused.
+    pub sync_peers: Vec<String>,
+    /// Larger contents are neither pushed nor taken over
+    pub sync_max_bytes: usize,
+}
+
+impl Default for ClipboardSettings {
+    fn default() -> Self {
+        Self {
+            write_received: false,
+            sync: false,
+            sync_peers: Vec::new(),
+            sync_max_bytes: DEFAULT_SYNC_MAX_BYTES,
+        }
+    }
+}
+
+impl ClipboardSettings {
+    /// Whether `device_id` may change the clipboard through sync.
+    pub fn syncs_with(&self, device_id: &str) -> bool {
+        self.sync && self.sync_peers.iter().any(|peer| peer == device_id)
+    }
+}
+
+/// The content last seen on or written to the local clipboard, shared by
+/// the sync watcher and the server. Content written from a peer is recorded
+/// here, so the watcher doesn't take it for a local change and send it
+/// back.
+#[derive(Clone, Default)]
+pub struct LastClipboard(Arc<Mutex<Option<[u8; 32]>>>);
+
+impl LastClipboard {
+    /// The clipboard's content if it changed since it was last seen or
+    /// written. Images are only encoded once they turn out to be new.
+    pub fn read_changed(
+        &self,
+        clipboard: &dyn Clipboard,
+    ) -> Result<Option<ClipboardContent>, Error> {
+        let content = {
+            // Held while reading so a write from a peer can't slip in between
+            let mut last = self.0.lock().unwrap();
+            let content = clipboard.read_raw()?;
+            let fingerprint = content.fingerprint();
+            if last.replace(fingerprint) == Some(fingerprint) {
+                return Ok(None);
+            }
+            content
+        };
+        content.encode().map(Some)
+    }
+
+    /// Write content from a peer to the clipboard, unless it is already
+    /// there; returns whether it was written.
+    pub fn write(
+        &self,
+        clipboard: &dyn Clipboard,
+        content: &ClipboardContent,
+    ) -> Result<bool, Error> {
+        let incoming = SystemContent::decode(content)?.fingerprint();
+        let mut last = self.0.lock().unwrap();
+        if *last == Some(incoming) {
+            return Ok(false);
+        }
+        clipboard.write(content)?;
+        // What is read back can differ from what was written, e.g. alpha
+        // may be premultiplied, and that is what the watcher will compare
+        let written = clipboard
+            .read_raw()
+            .map(|written| written.fingerprint())
+            .unwrap_or(incoming);
+        *last = Some(written);
+        Ok(true)
+    }
+}
+
+/// Watch the local clipboard while sync is switched on, calling `push` with
+/// every change that is within the size limit and the devices to send it
+/// to. Whatever is on the clipboard when sync starts stays local.
+pub async fn watch<F, Fut>(
+    clipboard: Arc<dyn Clipboard>,
+    last: LastClipboard,
+    config: Arc<Mutex<AppConfig>>,
+    push: F,
+) where
+    F: Fn(ClipboardContent, Vec<String>) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    let mut interval = tokio::time::interval(SYNC_POLL_INTERVAL);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut syncing = false;
+
+    loop {
+        interval.tick().await;
+        let settings = config.lock().unwrap().clipboard.clone();
+        if !settings.sync || settings.sync_peers.is_empty() {
+            syncing = false;
+            continue;
+        }
+
+        let changed = {
+            let (clipboard, last) = (clipboard.clone(), last.clone());
+            tokio::task::spawn_blocking(move || last.read_changed(clipboard.as_ref()))
+                .await
+                .unwrap_or_else(|e| Err(Error::Internal(format!("Clipboard read failed: {}", e))))
+        };
+        if !syncing {
+            syncing = true;
+            continue;
+        }
+        // An error means the clipboard is empty or holds something other
+        // than text or an image
+        let Ok(Some(content)) = changed else {
+            continue;
+        };
+        if content.len() > settings.sync_max_bytes {
+            debug!(bytes = content.len(); "Clipboard change too large to sync");
+            continue;
+        }
+        push(content, settings.sync_peers).await;
+    }
+}
+
+pub(crate) mod base64_bytes {
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        STANDARD.decode(encoded).map_err(serde::de::Error::custom)
+    }
+}
@@ -1,11 +1,78 @@
+use crate::bandwidth::BandwidthLimits;
+use crate::clipboard::ClipboardSettings;
+use crate::conflict::ConflictStrategy;
+use crate::error::Error;
+use crate::queue::QueueLimits;
+use crate::retry::RetryPolicy;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fs;
+use std::path::Path;
+#[cfg(feature = "gui")]
 use tauri::{AppHandle, Manager};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct AppConfig {
     pub alias: String,
     pub port: u16,
+    /// Stable identifier for this install, reported by `/info` and in mDNS TXT records.
+    /// Older settings files don't have one; `load_config` fills it in.
+    #[serde(default)]
+    pub device_id: String,
+    /// Names of the network interfaces to advertise and accept connections on.
+    /// Empty means all of them.
+    #[serde(default)]
+    pub interfaces: Vec<String>,
+    /// Outgoing transfers allowed to run at once, across all peers
+    #[serde(default = "default_max_concurrent_transfers")]
+    pub max_concurrent_transfers: usize,
+    /// Outgoing transfers allowed to run at once to the same peer
+    #[serde(default = "default_max_transfers_per_peer")]
+    pub max_transfers_per_peer: usize,
+    /// How failed sends are retried
+    #[serde(default)]
+    pub retry: RetryPolicy,
+    /// Upload and download rate limits
+    #[serde(default)]
+    pub bandwidth: BandwidthLimits,
+    /// What happens when a received file's name is already taken. Not used
+    /// on Android, where MediaStore always numbers the new file.
+    #[serde(default)]
+    pub conflict_strategy: ConflictStrategy,
+    /// How clipboard contents from peers are handled
+    #[serde(default)]
+    pub clipboard: ClipboardSettings,
+    /// IDs of the devices whose links are opened as soon as they arrive
+    #[serde(default)]
+    pub open_links_from: Vec<String>,
+    /// Named sets of device IDs that messages can be broadcast to
+    #[serde(default)]
+    pub peer_groups: BTreeMap<String, Vec<String>>,
+}
+
+fn default_max_concurrent_transfers() -> usize {
+    3
+}
+
+fn default_max_transfers_per_peer() -> usize {
+    1
+}
+
+impl AppConfig {
+    pub fn queue_limits(&self) -> QueueLimits {
+        QueueLimits {
+            max_concurrent: self.max_concurrent_transfers,
+            max_per_peer: self.max_transfers_per_peer,
+        }
+    }
+
+    /// Device IDs in the group called `name`.
+    pub fn peer_group(&self, name: &str) -> Result<&[String], Error> {
+        self.peer_groups
+            .get(name)
+            .map(Vec::as_slice)
+            .ok_or_else(|| Error::NotFound(format!("No group called {}", name)))
+    }
 }
 
 pub fn generate_anime_name() -> String {
@@ -460,52 +527,86 @@ impl Default for AppConfig {
         Self {
             alias: generate_anime_name(),
             port: 3030,
+            device_id: generate_device_id(),
+            interfaces: Vec::new(),
+            max_concurrent_transfers: default_max_concurrent_transfers(),
+            max_transfers_per_peer: default_max_transfers_per_peer(),
+            retry: RetryPolicy::default(),
+            bandwidth: BandwidthLimits::default(),
+            conflict_strategy: ConflictStrategy::default(),
+            clipboard: ClipboardSettings::default(),
+            open_links_from: Vec::new(),
+            peer_groups: BTreeMap::new(),
         }
     }
 }
 
+pub fn generate_device_id() -> String {
+    uuid::Uuid::new_v4().to_string()
+}
+
 fn is_old_style_name(name: &str) -> bool {
     // Old names crate generates names like "narrow-cows", "brave-tiger" etc.
     // They're lowercase with a hyphen. Our anime names are proper case.
     name.contains('-') && name.chars().all(|c| c.is_lowercase() || c == '-')
 }
 
+#[cfg(feature = "gui")]
 pub fn load_config(app: &AppHandle) -> AppConfig {
     // app_config_dir() is part of the path manager in Tauri
-    let config_path_res = app.path().app_config_dir();
+    match app.path().app_config_dir() {
+        Ok(config_dir) => load_config_file(&config_dir.join("settings.json")),
+        Err(_) => AppConfig::default(),
+    }
+}
 
-    if let Ok(config_dir) = config_path_res {
-        let config_path = config_dir.join("settings.json");
-        if config_path.exists() {
-            if let Ok(content) = fs::read_to_string(&config_path) {
-                if let Ok(mut config) = serde_json::from_str::<AppConfig>(&content) {
-                    // If the name looks like it came from the old generator, regenerate it
-                    if is_old_style_name(&config.alias) {
-                        config.alias = generate_anime_name();
-                        // Save the updated config with the new anime name
-                        let _ = save_config(app, &config);
-                    }
-                    return config;
+#[cfg(feature = "gui")]
+pub fn save_config(app: &AppHandle, config: &AppConfig) -> Result<(), Error> {
+    let config_dir = app
+        .path()
+        .app_config_dir()
+        .map_err(|e| Error::NotFound(format!("No config directory: {}", e)))?;
+    save_config_file(&config_dir.join("settings.json"), config)
+}
+
+/// Load settings from `config_path`, creating the file with defaults if it
+/// doesn't exist or can't be parsed.
+pub fn load_config_file(config_path: &Path) -> AppConfig {
+    if config_path.exists() {
+        if let Ok(content) = fs::read_to_string(config_path) {
+            if let Ok(mut config) = serde_json::from_str::<AppConfig>(&content) {
+                // If the name looks like it came from the old generator, regenerate it
+                if is_old_style_name(&config.alias) {
+                    config.alias = generate_anime_name();
+                    // Save the updated config with the new anime name
+                    let _ = save_config_file(config_path, &config);
+                }
+                // Settings written before device IDs existed get one assigned once
+                if config.device_id.is_empty() {
+                    config.device_id = generate_device_id();
+                    let _ = save_config_file(config_path, &config);
                 }
+                return config;
             }
         }
     }
 
     let config = AppConfig::default();
     // Try to save the default config immediately
-    let _ = save_config(app, &config);
+    let _ = save_config_file(config_path, &config);
     config
 }
 
-pub fn save_config(app: &AppHandle, config: &AppConfig) -> Result<(), String> {
-    let config_dir = app.path().app_config_dir().map_err(|e| e.to_string())?;
-
-    if !config_dir.exists() {
-        fs::create_dir_all(&config_dir).map_err(|e| e.to_string())?;
+pub fn save_config_file(config_path: &Path, config: &AppConfig) -> Result<(), Error> {
+    if let Some(config_dir) = config_path.parent() {
+        if !config_dir.exists() {
+            fs::create_dir_all(config_dir)
+                .map_err(|e| Error::io("Failed to create config directory", e))?;
+        }
     }
 
-    let config_path = config_dir.join("settings.json");
-    let content = serde_json::to_string_pretty(config).map_err(|e| e.to_string())?;
-    fs::write(config_path, content).map_err(|e| e.to_string())?;
+    let content = serde_json::to_string_pretty(config)
+        .map_err(|e| Error::Internal(format!("Failed to serialize settings: {}", e)))?;
+    fs::write(config_path, content).map_err(|e| Error::io("Failed to write settings", e))?;
     Ok(())
 }
@@ -0,0 +1,191 @@
+use crate::conflict::{ConflictResolution, FileConflict};
+use crate::error::Error;
+use crate::events::EventSink;
+use futures::future::BoxFuture;
+use log::error;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{mpsc, oneshot};
+
+/// An incoming file waiting to be accepted or rejected.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TransferRequest {
+    pub transfer_id: String,
+    pub file_name: String,
+    pub file_size: Option<u64>,
+}
+
+/// Decides whether an incoming file is accepted. The server gives up after
+/// its own timeout, so implementations may take as long as they like.
+pub trait ConfirmationProvider: Send + Sync {
+    fn confirm(&self, request: TransferRequest) -> BoxFuture<'static, bool>;
+
+    /// Decides what happens to an accepted file whose name is taken, when the
+    /// conflict strategy is to ask. Keeps both files unless overridden.
+    fn resolve_conflict(&self, _conflict: FileConflict) -> BoxFuture<'static, ConflictResolution> {
+        Box::pin(async { ConflictResolution::Rename })
+    }
+}
+
+type PendingMap<T> = Arc<Mutex<HashMap<String, oneshot::Sender<T>>>>;
+
+/// Asks the user by emitting `file-transfer-request` and waits for the answer
+/// to be passed to [`EventConfirmation::respond`]. Name conflicts are asked
+/// about with `file-conflict` and answered through
+/// [`EventConfirmation::respond_conflict`]. This is what the app uses.
+#[derive(Clone)]
+pub struct EventConfirmation {
+    events: Arc<dyn EventSink>,
+    pending: PendingMap<bool>,
+    conflicts: PendingMap<ConflictResolution>,
+}
+
+impl EventConfirmation {
+    pub fn new(events: Arc<dyn EventSink>) -> Self {
+        Self {
+            events,
+            pending: Arc::new(Mutex::new(HashMap::new())),
+            conflicts: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Answer the request with `transfer_id`.
+    pub fn respond(&self, transfer_id: &str, accepted: bool) -> Result<(), Error> {
+        answer(&self.pending, transfer_id, accepted)
+    }
+
+    /// Answer the name conflict of the transfer with `transfer_id`.
+    pub fn respond_conflict(
+        &self,
+        transfer_id: &str,
+        resolution: ConflictResolution,
+    ) -> Result<(), Error> {
+        answer(&self.conflicts, transfer_id, resolution)
+    }
+
+    /// Register a question about `transfer_id`, emit it as `event` and wait
+    /// for the answer, or `unanswered` if nobody gives one.
+    fn ask<T: Send + 'static, P: Serialize>(
+        &self,
+        pending: &PendingMap<T>,
+        transfer_id: &str,
+        event: &'static str,
+        payload: &P,
+        unanswered: T,
+    ) -> BoxFuture<'static, T> {
+        let (tx, rx) = oneshot::channel();
+        pending.lock().unwrap().insert(transfer_id.to_string(), tx);
+        let guard = PendingGuard {
+            pending: pending.clone(),
+            transfer_id: transfer_id.to_string(),
+        };
+
+        let emitted = self.events.emit(event, payload);
+
+        Box::pin(async move {
+            let _guard = guard;
+            if let Err(e) = emitted {
+                error!("Failed to emit {}: {}", event, e);
+                return unanswered;
+            }
+            // A dropped sender means nobody will answer
+            rx.await.unwrap_or(unanswered)
+        })
+    }
+}
+
+fn answer<T>(pending: &PendingMap<T>, transfer_id: &str, value: T) -> Result<(), Error> {
+    let sender = pending
+        .lock()
+        .map_err(|e| Error::Internal(format!("Failed to lock transfers: {}", e)))?
+        .remove(transfer_id)
+        .ok_or_else(|| Error::NotFound(format!("Transfer {} not found", transfer_id)))?;
+
+    sender
+        .send(value)
+        .map_err(|_| Error::Timeout(format!("Transfer {} is no longer waiting", transfer_id)))
+}
+
+/// Drops the pending entry when the server stops waiting, e.g. on timeout.
+struct PendingGuard<T> {
+    pending: PendingMap<T>,
+    transfer_id: String,
+}
+
+impl<T> Drop for PendingGuard<T> {
+    fn drop(&mut self) {
+        self.pending.lock().unwrap().remove(&self.transfer_id);
+    }
+}
+
+impl ConfirmationProvider for EventConfirmation {
+    fn confirm(&self, request: TransferRequest) -> BoxFuture<'static, bool> {
+        self.ask(
+            &self.pending,
+            &request.transfer_id,
+            "file-transfer-request",
+            &request,
+            false,
+        )
+    }
+
+    fn resolve_conflict(&self, conflict: FileConflict) -> BoxFuture<'static, ConflictResolution> {
+        self.ask(
+            &self.conflicts,
+            &conflict.transfer_id,
+            "file-conflict",
+            &conflict,
+            ConflictResolution::Rename,
+        )
+    }
+}
+
+/// Answers every request the same way, e.g. for `receive --auto-accept`.
+pub struct AutoConfirmation(pub bool);
+
+impl ConfirmationProvider for AutoConfirmation {
+    fn confirm(&self, _request: TransferRequest) -> BoxFuture<'static, bool> {
+        let accepted = self.0;
+        Box::pin(async move { accepted })
+    }
+}
+
+/// A request handed out by [`ChannelConfirmation`], answered by calling
+/// [`PendingConfirmation::respond`]. Dropping it rejects the transfer.
+pub struct PendingConfirmation {
+    pub request: TransferRequest,
+    responder: oneshot::Sender<bool>,
+}
+
+impl PendingConfirmation {
+    pub fn respond(self, accepted: bool) {
+        // The server may have timed out in the meantime
+        let _ = self.responder.send(accepted);
+    }
+}
+
+/// Forwards requests to a channel so another task can decide, e.g. a
+/// terminal prompt or a test script.
+pub struct ChannelConfirmation {
+    requests: mpsc::UnboundedSender<PendingConfirmation>,
+}
+
+impl ChannelConfirmation {
+    pub fn new() -> (Self, mpsc::UnboundedReceiver<PendingConfirmation>) {
+        let (requests, receiver) = mpsc::unbounded_channel();
+        (Self { requests }, receiver)
+    }
+}
+
+impl ConfirmationProvider for ChannelConfirmation {
+    fn confirm(&self, request: TransferRequest) -> BoxFuture<'static, bool> {
+        let (responder, rx) = oneshot::channel();
+        let sent = self
+            .requests
+            .send(PendingConfirmation { request, responder })
+            .is_ok();
+
+        Box::pin(async move { sent && rx.await.unwrap_or(false) })
+    }
+}
@@ -0,0 +1,123 @@
+use crate::filename;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::{self, ErrorKind};
+use std::path::Path;
+use tokio::fs::{self, File};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+/// Numbered names tried before giving up on finding a free one
+const MAX_NUMBERED: u32 = 10_000;
+
+/// What to do with a received file whose name is already taken in the
+/// download directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictStrategy {
+    /// Keep both, saving the new one as `photo (1).jpg`, `photo (2).jpg`, ...
+    #[default]
+    Rename,
+    /// Replace the existing file
+    Overwrite,
+    /// Don't save a file that is already there with the same content;
+    /// rename it otherwise
+    SkipIdentical,
+    /// Let the user choose for each conflict
+    Ask,
+}
+
+/// How one conflict is settled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictResolution {
+    Rename,
+    Overwrite,
+    Skip,
+}
+
+/// Payload of `file-conflict`, emitted when the user is asked what to do.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileConflict {
+    pub transfer_id: String,
+    pub file_name: String,
+    /// Whether the existing file has the same content
+    pub identical: bool,
+}
+
+/// Create `dir/name` for writing, failing with [`ErrorKind::AlreadyExists`]
+/// if something is already there. Checking and creating happen in one
+/// step, so two receives of the same name can't both get it.
+pub async fn create_new(dir: &Path, name: &str) -> io::Result<File> {
+    File::options()
+        .write(true)
+        .create_new(true)
+        .open(dir.join(name))
+        .await
+}
+
+/// Create the first free one of `name (1)`, `name (2)`, ... in `dir`,
+/// returning it and its name.
+pub async fn create_numbered(dir: &Path, name: &str) -> io::Result<(File, String)> {
+    for n in 1..=MAX_NUMBERED {
+        let numbered = filename::numbered(name, n);
+        match create_new(dir, &numbered).await {
+            Ok(file) => return Ok((file, numbered)),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Err(io::Error::new(
+        ErrorKind::AlreadyExists,
+        format!("No free name for {}", name),
+    ))
+}
+
+/// Write `data` to a new file and close it, removing it again if that fails
+/// so no partial file is left behind.
+pub async fn write_new(mut file: File, path: &Path, data: &[u8]) -> io::Result<()> {
+    let written = async {
+        file.write_all(data).await?;
+        file.sync_all().await
+    }
+    .await;
+    if written.is_err() {
+        drop(file);
+        let _ = fs::remove_file(path).await;
+    }
+    written
+}
+
+/// Replace `dir/name` with `data`. The data goes to a temporary file first
+/// that is then renamed over the old one, so readers see either the old or
+/// the new file, never half of one.
+pub async fn replace(dir: &Path, name: &str, data: &[u8]) -> io::Result<()> {
+    let temporary = format!(".{}.part", uuid::Uuid::new_v4());
+    let temporary_path = dir.join(&temporary);
+    let file = create_new(dir, &temporary).await?;
+    write_new(file, &temporary_path, data).await?;
+
+    if let Err(e) = fs::rename(&temporary_path, dir.join(name)).await {
+        let _ = fs::remove_file(&temporary_path).await;
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Whether the file at `path` holds exactly `data`, compared by SHA-256.
+pub async fn same_content(path: &Path, data: &[u8]) -> io::Result<bool> {
+    let mut file = File::open(path).await?;
+    if file.metadata().await?.len() != data.len() as u64 {
+        return Ok(false);
+    }
+
+    let mut existing = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        existing.update(&buffer[..read]);
+    }
+    Ok(existing.finalize() == Sha256::digest(data))
+}
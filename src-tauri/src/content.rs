@@ -0,0 +1,456 @@
+use std::io::SeekFrom;
+use std::path::Path;
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+pub const APK_MIME: &str = "application/vnd.android.package-archive";
+/// MIME type for content nothing more specific is known about
+pub const DEFAULT_MIME: &str = "application/octet-stream";
+/// How much of a file's start is read to recognize it by its magic bytes
+pub const SNIFF_LEN: usize = 8192;
+
+/// The entry that makes a ZIP archive an Android package
+const ANDROID_MANIFEST: &str = "AndroidManifest.xml";
+
+const ZIP_LOCAL_HEADER: [u8; 4] = [0x50, 0x4B, 0x03, 0x04];
+const ZIP_CENTRAL_HEADER: u32 = 0x02014b50;
+const ZIP_END_OF_CENTRAL_DIRECTORY: u32 = 0x06054b50;
+/// Size of the end of central directory record without its comment
+const ZIP_EOCD_LEN: usize = 22;
+/// The record is followed by a comment of at most this many bytes
+const ZIP_MAX_COMMENT_LEN: usize = u16::MAX as usize;
+/// Central directories larger than this aren't read from disk; even big
+/// APKs stay far below it
+const ZIP_MAX_CENTRAL_DIRECTORY_LEN: u64 = 16 * 1024 * 1024;
+
+/// A recognized kind of file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContentType {
+    pub mime: &'static str,
+    /// Usual extension, without the dot
+    pub extension: &'static str,
+}
+
+pub const APK: ContentType = ContentType {
+    mime: APK_MIME,
+    extension: "apk",
+};
+
+/// MIME types by lowercase extension, for files whose content isn't checked.
+const EXTENSIONS: &[(&str, &str)] = &[
+    ("apk", APK_MIME),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("png", "image/png"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("heic", "image/heic"),
+    ("heif", "image/heif"),
+    ("bmp", "image/bmp"),
+    ("svg", "image/svg+xml"),
+    ("ico", "image/vnd.microsoft.icon"),
+    ("tif", "image/tiff"),
+    ("tiff", "image/tiff"),
+    ("mp4", "video/mp4"),
+    ("m4v", "video/x-m4v"),
+    ("mov", "video/quicktime"),
+    ("mkv", "video/x-matroska"),
+    ("webm", "video/webm"),
+    ("avi", "video/x-msvideo"),
+    ("3gp", "video/3gpp"),
+    ("mp3", "audio/mpeg"),
+    ("m4a", "audio/mp4"),
+    ("aac", "audio/aac"),
+    ("ogg", "audio/ogg"),
+    ("opus", "audio/opus"),
+    ("flac", "audio/flac"),
+    ("wav", "audio/wav"),
+    ("pdf", "application/pdf"),
+    ("zip", "application/zip"),
+    ("gz", "application/gzip"),
+    ("tar", "application/x-tar"),
+    ("7z", "application/x-7z-compressed"),
+    ("rar", "application/vnd.rar"),
+    ("jar", "application/java-archive"),
+    ("json", "application/json"),
+    ("xml", "application/xml"),
+    ("txt", "text/plain"),
+    ("md", "text/markdown"),
+    ("csv", "text/csv"),
+    ("html", "text/html"),
+    ("htm", "text/html"),
+    ("css", "text/css"),
+    ("js", "text/javascript"),
+    ("doc", "application/msword"),
+    (
+        "docx",
+        "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+    ),
+    ("xls", "application/vnd.ms-excel"),
+    (
+        "xlsx",
+        "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+    ),
+    ("ppt", "application/vnd.ms-powerpoint"),
+    (
+        "pptx",
+        "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+    ),
+];
+
+/// MIME type implied by the extension of `file_name`, if it's a known one.
+pub fn from_extension(file_name: &str) -> Option<&'static str> {
+    let extension = Path::new(file_name).extension()?.to_str()?.to_lowercase();
+    EXTENSIONS
+        .iter()
+        .find(|(known, _)| *known == extension)
+        .map(|(_, mime)| *mime)
+}
+
+/// Recognize a complete file by its content. ZIP archives are only reported
+/// as APKs when their central directory lists `AndroidManifest.xml`.
+pub fn detect(data: &[u8]) -> Option<ContentType> {
+    if data.starts_with(&ZIP_LOCAL_HEADER) && zip_has_entry(data, ANDROID_MANIFEST) {
+        return Some(APK);
+    }
+    from_magic(data)
+}
+
+/// Recognize a file on disk like [`detect`], reading only its start and, for
+/// ZIP archives, its central directory. The file is left at its start.
+pub async fn detect_file(file: &mut File) -> std::io::Result<Option<ContentType>> {
+    let mut head = Vec::with_capacity(SNIFF_LEN);
+    (&mut *file)
+        .take(SNIFF_LEN as u64)
+        .read_to_end(&mut head)
+        .await?;
+
+    let detected = if head.starts_with(&ZIP_LOCAL_HEADER)
+        && file_zip_has_entry(file, ANDROID_MANIFEST).await?
+    {
+        Some(APK)
+    } else {
+        from_magic(&head)
+    };
+
+    file.rewind().await?;
+    Ok(detected)
+}
+
+/// MIME type for a file, from its extension or else from `data`, if known.
+pub fn mime_type(file_name: &str, data: Option<&[u8]>) -> Option<&'static str> {
+    from_extension(file_name).or_else(|| data.and_then(detect).map(|content| content.mime))
+}
+
+/// Like [`mime_type`] for a file on disk, looking at its content only when
+/// the extension says nothing. The file is left at its start.
+pub async fn file_mime_type(
+    file_name: &str,
+    file: &mut File,
+) -> std::io::Result<Option<&'static str>> {
+    if let Some(mime) = from_extension(file_name) {
+        return Ok(Some(mime));
+    }
+    Ok(detect_file(file).await?.map(|content| content.mime))
+}
+
+/// The MIME type a sender declared for a file, without parameters, unless it
+/// is missing, malformed or just the generic default, which says nothing
+/// about the content.
+pub fn declared(mime: Option<&str>) -> Option<&str> {
+    mime.and_then(|mime| mime.split(';').next())
+        .map(str::trim)
+        .filter(|mime| *mime != DEFAULT_MIME && mime.split_once('/').is_some_and(valid_type))
+}
+
+fn valid_type((kind, subtype): (&str, &str)) -> bool {
+    let token = |s: &str| {
+        !s.is_empty()
+            && s.bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b"!#$&-^_.+".contains(&b))
+    };
+    token(kind) && token(subtype)
+}
+
+/// Whether `file_name` already ends in an extension.
+pub fn has_extension(file_name: &str) -> bool {
+    Path::new(file_name).extension().is_some()
+}
+
+fn from_magic(data: &[u8]) -> Option<ContentType> {
+    infer::get(data).map(|kind| ContentType {
+        mime: kind.mime_type(),
+        extension: kind.extension(),
+    })
+}
+
+/// Offset and size of a ZIP archive's central directory, from the end of
+/// central directory record within `tail`, the last bytes of the archive.
+/// ZIP64 archives aren't supported; APKs are limited to 4 GiB anyway.
+fn central_directory_location(tail: &[u8]) -> Option<(u64, u64)> {
+    if tail.len() < ZIP_EOCD_LEN {
+        return None;
+    }
+    // The record sits right before a variable-length comment, so search
+    // backwards for the signature
+    (0..=tail.len() - ZIP_EOCD_LEN).rev().find_map(|start| {
+        let record = &tail[start..];
+        if read_u32(record, 0)? != ZIP_END_OF_CENTRAL_DIRECTORY {
+            return None;
+        }
+        let comment_len = read_u16(record, 20)? as usize;
+        if start + ZIP_EOCD_LEN + comment_len != tail.len() {
+            return None;
+        }
+        let size = read_u32(record, 12)? as u64;
+        let offset = read_u32(record, 16)? as u64;
+        Some((offset, size))
+    })
+}
+
+/// Whether a central directory lists an entry called `name`.
+fn central_directory_has_entry(directory: &[u8], name: &str) -> bool {
+    let mut pos = 0;
+    while read_u32(directory, pos) == Some(ZIP_CENTRAL_HEADER) {
+        let (Some(name_len), Some(extra_len), Some(comment_len)) = (
+            read_u16(directory, pos + 28),
+            read_u16(directory, pos + 30),
+            read_u16(directory, pos + 32),
+        ) else {
+            return false;
+        };
+        let name_start = pos + 46;
+        let name_end = name_start + name_len as usize;
+        match directory.get(name_start..name_end) {
+            Some(entry) if entry == name.as_bytes() => return true,
+            Some(_) => {}
+            None => return false,
+        }
+        pos = name_end + extra_len as usize + comment_len as usize;
+    }
+    false
+}
+
+/// Whether the complete ZIP archive `data` has an entry called `name`.
+pub fn zip_has_entry(data: &[u8], name: &str) -> bool {
+    let tail_start = data
+        .len()
+        .saturating_sub(ZIP_EOCD_LEN + ZIP_MAX_COMMENT_LEN);
+    let Some((offset, size)) = central_directory_location(&data[tail_start..]) else {
+        return false;
+    };
+    let Some(end) = offset.checked_add(size) else {
+        return false;
+    };
+    match data.get(offset as usize..end as usize) {
+        Some(directory) => central_directory_has_entry(directory, name),
+        None => false,
+    }
+}
+
+/// Like [`zip_has_entry`] for an archive on disk.
+async fn file_zip_has_entry(file: &mut File, name: &str) -> std::io::Result<bool> {
+    let len = file.metadata().await?.len();
+    let tail_len = len.min((ZIP_EOCD_LEN + ZIP_MAX_COMMENT_LEN) as u64);
+    let mut tail = vec![0; tail_len as usize];
+    file.seek(SeekFrom::Start(len - tail_len)).await?;
+    file.read_exact(&mut tail).await?;
+
+    let Some((offset, size)) = central_directory_location(&tail) else {
+        return Ok(false);
+    };
+    if size > ZIP_MAX_CENTRAL_DIRECTORY_LEN || offset.saturating_add(size) > len {
+        return Ok(false);
+    }
+
+    let mut directory = vec![0; size as usize];
+    file.seek(SeekFrom::Start(offset)).await?;
+    file.read_exact(&mut directory).await?;
+    Ok(central_directory_has_entry(&directory, name))
+}
+
+fn read_u16(data: &[u8], pos: usize) -> Option<u16> {
+    let bytes = data.get(pos..pos + 2)?;
+    Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_u32(data: &[u8], pos: usize) -> Option<u32> {
+    let bytes = data.get(pos..pos + 4)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// ZIP archive with the given stored entries and an optional comment.
+    fn zip(entries: &[(&str, &[u8])], comment: &[u8]) -> Vec<u8> {
+        let mut zip = Vec::new();
+        let mut central = Vec::new();
+
+        for (name, contents) in entries {
+            let local_offset = zip.len() as u32;
+            let name = name.as_bytes();
+
+            zip.extend_from_slice(&ZIP_LOCAL_HEADER);
+            zip.extend_from_slice(&[20, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+            zip.extend_from_slice(&0u32.to_le_bytes());
+            zip.extend_from_slice(&(contents.len() as u32).to_le_bytes());
+            zip.extend_from_slice(&(contents.len() as u32).to_le_bytes());
+            zip.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            zip.extend_from_slice(&0u16.to_le_bytes());
+            zip.extend_from_slice(name);
+            zip.extend_from_slice(contents);
+
+            central.extend_from_slice(&ZIP_CENTRAL_HEADER.to_le_bytes());
+            central.extend_from_slice(&[20, 0, 20, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+            central.extend_from_slice(&0u32.to_le_bytes());
+            central.extend_from_slice(&(contents.len() as u32).to_le_bytes());
+            central.extend_from_slice(&(contents.len() as u32).to_le_bytes());
+            central.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            // An extra field, to check that it is skipped
+            central.extend_from_slice(&4u16.to_le_bytes());
+            central.extend_from_slice(&[0; 10]);
+            central.extend_from_slice(&local_offset.to_le_bytes());
+            central.extend_from_slice(name);
+            central.extend_from_slice(&[0xCA, 0xFE, 0, 0]);
+        }
+
+        let central_offset = zip.len() as u32;
+        zip.extend_from_slice(&central);
+        zip.extend_from_slice(&ZIP_END_OF_CENTRAL_DIRECTORY.to_le_bytes());
+        zip.extend_from_slice(&[0, 0, 0, 0]);
+        zip.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+        zip.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+        zip.extend_from_slice(&(central.len() as u32).to_le_bytes());
+        zip.extend_from_slice(&central_offset.to_le_bytes());
+        zip.extend_from_slice(&(comment.len() as u16).to_le_bytes());
+        zip.extend_from_slice(comment);
+        zip
+    }
+
+    fn apk() -> Vec<u8> {
+        zip(
+            &[
+                ("classes.dex", b"dex\n035\0"),
+                ("res/raw/big.bin", &[7; 20_000]),
+                ("AndroidManifest.xml", b"<manifest/>"),
+            ],
+            b"",
+        )
+    }
+
+    #[test]
+    fn zip_with_manifest_is_an_apk() {
+        assert_eq!(detect(&apk()), Some(APK));
+    }
+
+    #[test]
+    fn manifest_is_found_past_the_first_kilobytes() {
+        // The manifest comes after 20 kB of other entries, beyond any
+        // fixed-size preview of the start
+        let data = apk();
+        assert!(!String::from_utf8_lossy(&data[..SNIFF_LEN]).contains(ANDROID_MANIFEST));
+        assert!(zip_has_entry(&data, ANDROID_MANIFEST));
+    }
+
+    #[test]
+    fn plain_zip_is_not_an_apk() {
+        let data = zip(&[("notes.txt", b"hello")], b"");
+        assert_eq!(
+            detect(&data).map(|content| content.mime),
+            Some("application/zip")
+        );
+    }
+
+    #[test]
+    fn manifest_mentioned_in_contents_is_not_an_entry() {
+        let data = zip(&[("readme.txt", b"see AndroidManifest.xml")], b"");
+        assert!(!zip_has_entry(&data, ANDROID_MANIFEST));
+        assert_ne!(detect(&data), Some(APK));
+    }
+
+    #[test]
+    fn similar_entry_names_dont_match() {
+        let data = zip(&[("lib/AndroidManifest.xml.bak", b"")], b"");
+        assert!(!zip_has_entry(&data, ANDROID_MANIFEST));
+    }
+
+    #[test]
+    fn archive_comment_is_skipped() {
+        let data = zip(
+            &[(ANDROID_MANIFEST, b"<manifest/>")],
+            b"comment with a fake PK\x05\x06 signature",
+        );
+        assert!(zip_has_entry(&data, ANDROID_MANIFEST));
+    }
+
+    #[test]
+    fn truncated_archive_is_not_an_apk() {
+        let data = apk();
+        assert!(!zip_has_entry(&data[..data.len() - 10], ANDROID_MANIFEST));
+        assert!(!zip_has_entry(&data[..100], ANDROID_MANIFEST));
+        assert!(!zip_has_entry(b"PK\x03\x04", ANDROID_MANIFEST));
+    }
+
+    #[test]
+    fn magic_bytes_are_recognized() {
+        let png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR\0\0\0\x01\0\0\0\x01\x08\x06\0\0\0";
+        assert_eq!(
+            detect(png),
+            Some(ContentType {
+                mime: "image/png",
+                extension: "png"
+            })
+        );
+        assert_eq!(detect(b"%PDF-1.7\n").map(|c| c.extension), Some("pdf"));
+        assert_eq!(detect(b"just some text"), None);
+    }
+
+    #[test]
+    fn extension_wins_over_content() {
+        assert_eq!(from_extension("Photo.JPG"), Some("image/jpeg"));
+        assert_eq!(from_extension("app.apk"), Some(APK_MIME));
+        assert_eq!(from_extension("archive.unknown"), None);
+        assert_eq!(from_extension("no_extension"), None);
+
+        let apk = apk();
+        assert_eq!(mime_type("package", Some(&apk)), Some(APK_MIME));
+        assert_eq!(
+            mime_type("package.zip", Some(&apk)),
+            Some("application/zip")
+        );
+        assert_eq!(mime_type("package", None), None);
+    }
+
+    #[test]
+    fn only_specific_declared_types_are_trusted() {
+        assert_eq!(declared(Some("image/heic")), Some("image/heic"));
+        assert_eq!(
+            declared(Some(" application/vnd.android.package-archive ")),
+            Some(APK_MIME)
+        );
+        assert_eq!(declared(Some(DEFAULT_MIME)), None);
+        assert_eq!(declared(Some("image")), None);
+        assert_eq!(declared(Some("image/")), None);
+        assert_eq!(
+            declared(Some("text/plain; charset=utf-8")),
+            Some("text/plain")
+        );
+        assert_eq!(declared(None), None);
+    }
+
+    #[tokio::test]
+    async fn apk_on_disk_is_detected() {
+        let path = std::env::temp_dir().join(format!("content-{}", uuid::Uuid::new_v4()));
+        std::fs::write(&path, apk()).unwrap();
+
+        let mut file = File::open(&path).await.unwrap();
+        let detected = detect_file(&mut file).await.unwrap();
+        let position = file.stream_position().await.unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(detected, Some(APK));
+        assert_eq!(position, 0);
+    }
+}
@@ -1,214 +1,448 @@
-use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
-use once_cell::sync::Lazy;
+use crate::config::AppConfig;
+use crate::error::Error;
+use crate::events::EventSink;
+use crate::network::{interface_addresses, NetworkWatcher};
+use crate::server::{device_type, PROTOCOL_VERSION};
+use crate::transfer::{candidate_addresses, fetch_device_info, ping_peer};
+use log::{debug, error, info, warn};
+use mdns_sd::{IfKind, ServiceDaemon, ServiceEvent, ServiceInfo};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::sync::mpsc::{channel, Sender};
+use std::net::IpAddr;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender, TryRecvError};
 use std::sync::{Arc, Mutex};
-use std::thread;
-use std::time::Duration;
-use tauri::{AppHandle, Emitter};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::runtime::Handle;
 
-// Global handle to the discovery system
-static DISCOVERY_CONTROL: Lazy<Arc<Mutex<Option<Sender<DiscoveryCommand>>>>> =
-    Lazy::new(|| Arc::new(Mutex::new(None)));
+const SERVICE_TYPE: &str = "_myshare_app._tcp.local.";
 
-// Global handle to store my own alias for filtering
-static MY_ALIAS: Lazy<Arc<Mutex<String>>> = Lazy::new(|| Arc::new(Mutex::new(String::new())));
+/// Peers not refreshed by mDNS or a successful ping for this long are dropped
+const PEER_GRACE_PERIOD: Duration = Duration::from_secs(90);
+/// Peers not refreshed by mDNS for this long get pinged
+const PEER_STALE_AFTER: Duration = Duration::from_secs(20);
+/// Consecutive failed pings after which a peer is dropped before the grace period ends
+const MAX_FAILED_PINGS: u32 = 3;
+/// How often the discovery thread checks peer liveness
+const LIVENESS_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Restart the browse daemon this often.
+/// This ensures we catch services that were registered before browse started
+/// or services that were missed due to mDNS cache issues
+const PERIODIC_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long to wait for the daemon to send our goodbye when unregistering
+const UNREGISTER_TIMEOUT: Duration = Duration::from_secs(1);
+
+type PeersMap = Arc<Mutex<HashMap<String, Peer>>>;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Peer {
+    /// Preferred address, kept for callers that only handle one
     pub ip: String,
+    /// Every usable address the peer advertised, best first
+    pub addresses: Vec<String>,
     pub port: u16,
     pub alias: String,
     pub hostname: String,
+    pub device_id: Option<String>,
+    pub device_type: Option<String>,
+    /// Unix time in milliseconds of the last mDNS update or successful ping
+    pub last_seen: u64,
+    #[serde(skip)]
+    failed_pings: u32,
+}
+
+impl Peer {
+    /// Every address to try, the preferred one first.
+    fn candidates(&self) -> Vec<String> {
+        candidate_addresses(self.ip.clone(), Some(self.addresses.clone()))
+    }
+}
+
+/// Where discovered devices can be reached, for telling whether a request
+/// really comes from the device it claims to be.
+pub trait PeerDirectory: Send + Sync {
+    /// Whether `device_id` was discovered at `ip`. If several devices claim
+    /// the same ID, all of them must have the address, so advertising
+    /// someone else's ID doesn't earn their trust.
+    fn is_device_at(&self, device_id: &str, ip: IpAddr) -> bool;
+}
+
+/// The peers found by a [`DiscoveryService`], kept up to date as it runs.
+#[derive(Clone)]
+pub struct DiscoveredPeers(PeersMap);
+
+impl PeerDirectory for DiscoveredPeers {
+    fn is_device_at(&self, device_id: &str, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        let peers = self.0.lock().unwrap();
+        let mut claiming = peers
+            .values()
+            .filter(|peer| peer.device_id.as_deref() == Some(device_id))
+            .peekable();
+
+        claiming.peek().is_some()
+            && claiming.all(|peer| {
+                std::iter::once(&peer.ip)
+                    .chain(&peer.addresses)
+                    .filter_map(|addr| addr.parse::<IpAddr>().ok())
+                    .any(|addr| addr.to_canonical() == ip)
+            })
+    }
+}
+
+/// This device as discovery sees it, to keep it out of the peer list.
+#[derive(Debug, Clone)]
+struct OwnDevice {
+    alias: String,
+    device_id: String,
+}
+
+impl OwnDevice {
+    /// Whether a service is ours. Told apart by device ID where the service
+    /// has one, since another device may well share our alias.
+    fn is(&self, alias: &str, device_id: Option<&str>) -> bool {
+        match device_id {
+            Some(device_id) => device_id == self.device_id,
+            None => alias == self.alias,
+        }
+    }
 }
 
 enum DiscoveryCommand {
     Refresh,
     UpdateAlias(String),
+    Stop,
 }
 
-pub fn start_discovery(app: AppHandle, my_alias: String) {
-    let service_type = "_myshare_app._tcp.local.";
+/// This device's service, as registered with mDNS.
+struct Registration {
+    daemon: ServiceDaemon,
+    fullname: String,
+}
 
-    eprintln!("Starting discovery - filtering out self: {}", my_alias);
+struct BrowseHandle {
+    commands: Sender<DiscoveryCommand>,
+    thread: JoinHandle<()>,
+}
 
-    // Store the initial alias
-    *MY_ALIAS.lock().unwrap() = my_alias.clone();
+/// Owns this device's mDNS registration and the browse thread that tracks
+/// peers. Managed as Tauri state; independent instances don't share anything.
+pub struct DiscoveryService {
+    events: Arc<dyn EventSink>,
+    /// Runs the `/info` and `/ping` requests the browse thread makes
+    runtime: Handle,
+    registration: Mutex<Option<Registration>>,
+    browse: Mutex<Option<BrowseHandle>>,
+    network_watcher: Mutex<Option<NetworkWatcher>>,
+    peers: PeersMap,
+}
 
-    let peers_map: Arc<Mutex<HashMap<String, Peer>>> = Arc::new(Mutex::new(HashMap::new()));
-    let peers_map_clone = peers_map.clone();
+impl DiscoveryService {
+    pub fn new(events: Arc<dyn EventSink>, runtime: Handle) -> Self {
+        Self {
+            events,
+            runtime,
+            registration: Mutex::new(None),
+            browse: Mutex::new(None),
+            network_watcher: Mutex::new(None),
+            peers: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
 
-    // Create control channel for refresh commands
-    let (cmd_sender, cmd_receiver) = channel::<DiscoveryCommand>();
-    *DISCOVERY_CONTROL.lock().unwrap() = Some(cmd_sender);
+    /// Start browsing for peers and register the service.
+    /// Browsing keeps running even if registration fails.
+    pub fn start(&self, config: &AppConfig) -> Result<(), Error> {
+        self.start_browsing(config.alias.clone(), config.device_id.clone());
+        self.register(config, Duration::ZERO)
+    }
 
-    thread::spawn(move || {
-        eprintln!("mDNS discovery thread started");
+    /// Stop watching the network, stop browsing and unregister the service.
+    pub fn stop(&self) {
+        // First, so a network change can't restart discovery behind our back
+        let watcher = self.network_watcher.lock().unwrap().take();
+        if let Some(watcher) = watcher {
+            watcher.stop();
+        }
+        self.stop_browsing();
+        self.unregister(Duration::ZERO);
+    }
 
-        // Flag to force refresh
-        let mut should_restart = true;
-        let mut daemon_opt: Option<ServiceDaemon> = None;
-        let mut current_alias = my_alias;
-        // Track when the browse daemon was last started for periodic refresh
-        #[allow(unused_assignments)]
-        let mut last_browse_start: Option<std::time::Instant> = None;
-        // Periodic refresh interval: restart browse daemon every 30 seconds
-        // This ensures we catch services that were registered before browse started
-        // or services that were missed due to mDNS cache issues
-        const PERIODIC_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+    /// Start browsing and registration over, e.g. after a network change.
+    /// The network watcher keeps running.
+    pub fn restart(&self, config: &AppConfig) -> Result<(), Error> {
+        self.stop_browsing();
+        self.unregister(Duration::ZERO);
+        self.start(config)
+    }
 
-        loop {
-            // Create or recreate daemon if needed
-            if should_restart {
-                eprintln!("Starting new mDNS browse...");
-
-                // Shutdown old daemon if exists
-                if let Some(old_daemon) = daemon_opt.take() {
-                    let _ = old_daemon.shutdown();
-                    // Wait a bit longer to allow mDNS cache to clear
-                    thread::sleep(Duration::from_millis(500));
-                }
+    /// Keep `watcher` running until discovery is stopped, replacing any
+    /// previous one.
+    pub fn watch_network(&self, watcher: NetworkWatcher) {
+        let previous = self.network_watcher.lock().unwrap().replace(watcher);
+        if let Some(previous) = previous {
+            previous.stop();
+        }
+    }
 
-                // Reset the restart flag
-                should_restart = false;
-                last_browse_start = Some(std::time::Instant::now());
-
-                // Create new daemon
-                match ServiceDaemon::new() {
-                    Ok(daemon) => {
-                        match daemon.browse(service_type) {
-                            Ok(receiver) => {
-                                eprintln!("✓ mDNS browse started successfully");
-                                daemon_opt = Some(daemon);
-
-                                // Process events with timeout
-                                loop {
-                                    // Check for refresh or alias update command
-                                    match cmd_receiver.try_recv() {
-                                        Ok(DiscoveryCommand::Refresh) => {
-                                            eprintln!("Refresh command received!");
-                                            // Clear peers map to force fresh discovery
-                                            peers_map_clone.lock().unwrap().clear();
-                                            emit_peers(&app, &peers_map_clone);
-                                            should_restart = true;
-                                            break;
-                                        }
-                                        Ok(DiscoveryCommand::UpdateAlias(new_alias)) => {
-                                            eprintln!(
-                                                "Alias update command received: {}",
-                                                new_alias
-                                            );
-                                            current_alias = new_alias;
-                                            // Update the global alias
-                                            *MY_ALIAS.lock().unwrap() = current_alias.clone();
-                                            // Clear peers and restart to re-filter
-                                            peers_map_clone.lock().unwrap().clear();
-                                            emit_peers(&app, &peers_map_clone);
-                                            should_restart = true;
-                                            break;
-                                        }
-                                        Err(_) => {
-                                            // No command, continue processing events
-                                        }
-                                    }
-
-                                    // Check if it's time for periodic refresh
-                                    if let Some(start_time) = last_browse_start {
-                                        if start_time.elapsed() >= PERIODIC_REFRESH_INTERVAL {
-                                            eprintln!("Periodic refresh: restarting browse daemon to ensure fresh discovery");
-                                            // Clear peers map to force fresh discovery
-                                            peers_map_clone.lock().unwrap().clear();
-                                            emit_peers(&app, &peers_map_clone);
-                                            should_restart = true;
-                                            break;
-                                        }
-                                    }
-
-                                    match receiver.recv_timeout(Duration::from_millis(500)) {
-                                        Ok(event) => {
-                                            process_mdns_event(
-                                                event,
-                                                &current_alias,
-                                                &peers_map_clone,
-                                                &app,
-                                            );
-                                        }
-                                        Err(_) => {
-                                            // Timeout - just continue to check for commands
-                                            continue;
-                                        }
-                                    }
-                                }
-                            }
-                            Err(e) => {
-                                eprintln!("✗ Failed to start browse: {}", e);
-                                thread::sleep(Duration::from_secs(5));
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        eprintln!("✗ Failed to create daemon: {}", e);
-                        thread::sleep(Duration::from_secs(5));
+    /// Broadcast our presence from `config`, replacing any previous registration.
+    /// `settle` is how long to wait for the old registration's goodbye to propagate.
+    pub fn register(&self, config: &AppConfig, settle: Duration) -> Result<(), Error> {
+        self.unregister(settle);
+
+        let registration = register_service(
+            &config.alias,
+            config.port,
+            &config.device_id,
+            &config.interfaces,
+        )?;
+        *self.registration.lock().unwrap() = Some(registration);
+        Ok(())
+    }
+
+    /// Withdraw our service, telling peers we're gone.
+    pub fn unregister(&self, settle: Duration) {
+        let old = self.registration.lock().unwrap().take();
+        if let Some(Registration { daemon, fullname }) = old {
+            debug!("Unregistering mDNS service {}...", fullname);
+            // Shutting the daemon down doesn't announce anything, so peers
+            // would keep listing us until our records expire; unregistering
+            // sends the goodbye
+            match daemon.unregister(&fullname) {
+                Ok(status) => {
+                    if status.recv_timeout(UNREGISTER_TIMEOUT).is_err() {
+                        warn!("Timed out unregistering {}", fullname);
                     }
                 }
+                Err(e) => warn!("Failed to unregister {}: {}", fullname, e),
             }
+            if let Err(e) = daemon.shutdown() {
+                warn!("Failed to shutdown old daemon: {}", e);
+            }
+            // Give time for the unregistration to propagate across the network
+            thread::sleep(settle);
         }
-    });
+    }
+
+    /// Start the browse thread, filtering out this device, `my_device_id`
+    /// called `my_alias`. Does nothing if it is already running.
+    pub fn start_browsing(&self, my_alias: String, my_device_id: String) {
+        let mut browse = self.browse.lock().unwrap();
+        if browse.is_some() {
+            return;
+        }
+
+        info!("Starting discovery - filtering out self: {}", my_alias);
+        let me = OwnDevice {
+            alias: my_alias,
+            device_id: my_device_id,
+        };
+
+        let (commands, cmd_receiver) = channel::<DiscoveryCommand>();
+        let events = self.events.clone();
+        let runtime = self.runtime.clone();
+        let peers = self.peers.clone();
+        let thread = thread::spawn(move || browse_loop(events, runtime, peers, me, cmd_receiver));
+
+        *browse = Some(BrowseHandle { commands, thread });
+    }
+
+    /// Stop the browse thread and wait for it to exit. Known peers are kept.
+    pub fn stop_browsing(&self) {
+        let handle = self.browse.lock().unwrap().take();
+        if let Some(handle) = handle {
+            let _ = handle.commands.send(DiscoveryCommand::Stop);
+            if handle.thread.join().is_err() {
+                error!("mDNS discovery thread panicked");
+            }
+        }
+    }
+
+    /// Restart the browse daemon to pick up peers we may have missed.
+    pub fn refresh(&self) -> Result<(), Error> {
+        info!("Manual discovery refresh triggered...");
+        self.send_command(DiscoveryCommand::Refresh)
+    }
+
+    /// Change the alias used to filter out our own service where it has no
+    /// device ID.
+    pub fn update_alias(&self, new_alias: String) -> Result<(), Error> {
+        info!("Updating alias to: {}", new_alias);
+        self.send_command(DiscoveryCommand::UpdateAlias(new_alias))
+    }
+
+    /// Currently known peers.
+    pub fn peers(&self) -> Vec<Peer> {
+        self.peers.lock().unwrap().values().cloned().collect()
+    }
+
+    /// A live view of the known peers, for checking where requests come from.
+    pub fn directory(&self) -> DiscoveredPeers {
+        DiscoveredPeers(self.peers.clone())
+    }
+
+    fn send_command(&self, command: DiscoveryCommand) -> Result<(), Error> {
+        let browse = self.browse.lock().unwrap();
+        let handle = browse
+            .as_ref()
+            .ok_or_else(|| Error::Internal("Discovery is not running".to_string()))?;
+        handle
+            .commands
+            .send(command)
+            .map_err(|e| Error::Internal(format!("Failed to send discovery command: {}", e)))
+    }
+}
+
+impl Drop for DiscoveryService {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+fn browse_loop(
+    events: Arc<dyn EventSink>,
+    runtime: Handle,
+    peers_map: PeersMap,
+    mut me: OwnDevice,
+    cmd_receiver: Receiver<DiscoveryCommand>,
+) {
+    debug!("mDNS discovery thread started");
+
+    let mut last_liveness_check = Instant::now();
+
+    loop {
+        debug!("Starting new mDNS browse...");
+
+        let started = ServiceDaemon::new()
+            .map_err(|e| format!("Failed to create daemon: {}", e))
+            .and_then(|daemon| match daemon.browse(SERVICE_TYPE) {
+                Ok(receiver) => Ok((daemon, receiver)),
+                Err(e) => {
+                    let _ = daemon.shutdown();
+                    Err(format!("Failed to start browse: {}", e))
+                }
+            });
+
+        let (daemon, receiver) = match started {
+            Ok(started) => started,
+            Err(e) => {
+                error!("{}", e);
+                // Retry later unless we're asked to stop in the meantime
+                match cmd_receiver.recv_timeout(Duration::from_secs(5)) {
+                    Ok(DiscoveryCommand::Stop) | Err(RecvTimeoutError::Disconnected) => return,
+                    Ok(DiscoveryCommand::UpdateAlias(new_alias)) => me.alias = new_alias,
+                    Ok(DiscoveryCommand::Refresh) | Err(RecvTimeoutError::Timeout) => {}
+                }
+                continue;
+            }
+        };
+
+        debug!("mDNS browse started successfully");
+        let browse_start = Instant::now();
+        let mut stop = false;
+
+        // Process events until something asks for a restart
+        loop {
+            // Check for refresh or alias update command
+            match cmd_receiver.try_recv() {
+                Ok(DiscoveryCommand::Refresh) => {
+                    debug!("Refresh command received!");
+                    // Known peers are kept; the liveness check drops
+                    // the ones that don't show up again
+                    break;
+                }
+                Ok(DiscoveryCommand::UpdateAlias(new_alias)) => {
+                    debug!("Alias update command received: {}", new_alias);
+                    me.alias = new_alias;
+                    // Re-filter: a peer with our new alias and no ID is us
+                    remove_peers_where(&events, &peers_map, |p| {
+                        me.is(&p.alias, p.device_id.as_deref())
+                    });
+                    break;
+                }
+                Ok(DiscoveryCommand::Stop) | Err(TryRecvError::Disconnected) => {
+                    stop = true;
+                    break;
+                }
+                Err(TryRecvError::Empty) => {
+                    // No command, continue processing events
+                }
+            }
+
+            if browse_start.elapsed() >= PERIODIC_REFRESH_INTERVAL {
+                debug!("Periodic refresh: restarting browse daemon to ensure fresh discovery");
+                break;
+            }
+
+            if last_liveness_check.elapsed() >= LIVENESS_CHECK_INTERVAL {
+                last_liveness_check = Instant::now();
+                check_liveness(&events, &runtime, &peers_map);
+            }
+
+            // Timeout - just loop around to check for commands
+            if let Ok(event) = receiver.recv_timeout(Duration::from_millis(500)) {
+                process_mdns_event(event, &me, &peers_map, &events, &runtime);
+            }
+        }
+
+        let _ = daemon.shutdown();
+
+        if stop {
+            debug!("mDNS discovery thread stopped");
+            return;
+        }
+
+        // Wait a bit to allow mDNS cache to clear
+        thread::sleep(Duration::from_millis(500));
+    }
 }
 
 fn process_mdns_event(
     event: ServiceEvent,
-    my_alias: &str,
-    peers_map: &Arc<Mutex<HashMap<String, Peer>>>,
-    app: &AppHandle,
+    me: &OwnDevice,
+    peers_map: &PeersMap,
+    events: &Arc<dyn EventSink>,
+    runtime: &Handle,
 ) {
     match event {
         ServiceEvent::SearchStarted(service_type) => {
-            eprintln!("mDNS search started for {}", service_type);
+            debug!("mDNS search started for {}", service_type);
         }
         ServiceEvent::ServiceFound(service_type, fullname) => {
-            eprintln!("Service found: {} (type: {})", fullname, service_type);
+            debug!("Service found: {} (type: {})", fullname, service_type);
         }
         ServiceEvent::ServiceResolved(info) => {
-            eprintln!("Service resolved: {}", info.get_fullname());
+            debug!("Service resolved: {}", info.get_fullname());
 
-            // Get alias first to check if this is our own device
-            let alias = match info.get_property_val("alias") {
-                Some(val) => {
-                    // Handle the nested Option structure
-                    match val {
-                        Some(bytes) => String::from_utf8_lossy(bytes).to_string(),
-                        None => "Unknown".to_string(),
-                    }
-                }
-                None => "Unknown".to_string(),
+            let txt = |key: &str| {
+                info.get_property_val(key)
+                    .flatten()
+                    .map(|bytes| String::from_utf8_lossy(bytes).to_string())
             };
 
-            eprintln!("  Alias: {}", alias);
+            // Get alias first to check if this is our own device
+            let txt_alias = txt("alias");
+            let alias = txt_alias.clone().unwrap_or_else(|| "Unknown".to_string());
+            let device_id = txt("device_id");
+            let device_type = txt("device_type");
+
+            debug!("Alias: {}", alias);
 
-            // Skip if this is our own device (simple alias comparison)
-            if alias == my_alias {
-                eprintln!("  Skipping - this is our own device");
+            // Skip if this is our own device
+            if me.is(&alias, device_id.as_deref()) {
+                debug!("Skipping - this is our own device");
                 return;
             }
 
             // Get all IP addresses from the service
-            let addresses: Vec<String> = info
-                .get_addresses()
-                .iter()
-                .map(|ip| ip.to_string())
-                .collect();
-
-            eprintln!("  Addresses: {:?}", addresses);
-            eprintln!("  Port: {}", info.get_port());
-
-            // Get the first valid IP address
-            let ip = addresses
-                .iter()
-                .find(|addr| !addr.is_empty())
-                .cloned()
-                .unwrap_or_default();
+            let addresses = usable_addresses(info.get_addresses().iter().map(|ip| ip.to_string()));
+
+            debug!("Addresses: {:?}", addresses);
+            debug!("Port: {}", info.get_port());
+
+            // The best-ranked address doubles as the primary one
+            let ip = addresses.first().cloned().unwrap_or_default();
 
             // Skip if no valid IP found
             if !ip.is_empty() {
@@ -218,161 +452,688 @@ fn process_mdns_event(
 
                 let peer = Peer {
                     ip: ip.clone(),
+                    addresses,
                     port,
                     alias: alias.clone(),
                     hostname: hostname.clone(),
+                    device_id,
+                    device_type,
+                    last_seen: now_millis(),
+                    failed_pings: 0,
                 };
 
-                let mut peers = peers_map.lock().unwrap();
+                // Older builds and some mDNS responders publish no (or partial) TXT data,
+                // so ask the peer to describe itself instead
+                let needs_info = txt_alias.is_none() || peer.device_id.is_none();
 
-                // Check if we already have a peer with the same IP but different alias
-                // If so, remove the old entry to avoid duplicates
-                let existing_key = peers
-                    .iter()
-                    .find(|(_, p)| p.ip == ip && p.alias != alias)
-                    .map(|(k, _)| k.clone());
+                info!("Adding/updating peer: {} ({}:{})", alias, ip, port);
+                store_peer(&key, peer.clone(), peers_map, events);
 
-                if let Some(old_key) = existing_key {
-                    eprintln!(
-                        "  Removing old peer entry with same IP but different alias: {}",
-                        old_key
-                    );
-                    peers.remove(&old_key);
+                // Only once the peer is in the map, so a quick answer has
+                // an entry to fill in
+                if needs_info {
+                    enrich_peer(key, peer, me, peers_map.clone(), events.clone(), runtime);
                 }
-
-                eprintln!("  Adding/updating peer: {} ({}:{})", alias, ip, port);
-                peers.insert(key, peer);
-                drop(peers); // Release lock before emitting
-                emit_peers(app, peers_map);
             } else {
-                eprintln!("  Skipping - no valid IP found");
+                debug!("Skipping - no valid IP found");
             }
         }
         ServiceEvent::ServiceRemoved(_service_type, fullname) => {
-            eprintln!("Service removed: {}", fullname);
+            info!("Service removed: {}", fullname);
             let key = fullname;
-            peers_map.lock().unwrap().remove(&key);
-            emit_peers(app, peers_map);
+            // A goodbye packet is an explicit departure, no need to wait for the grace period
+            let removed = peers_map.lock().unwrap().remove(&key);
+            if let Some(peer) = removed {
+                let _ = events.emit("peer-removed", &peer);
+                emit_peers(events, peers_map);
+            }
         }
         _ => {
-            eprintln!("Other mDNS event: {:?}", event);
+            debug!("Other mDNS event: {:?}", event);
+        }
+    }
+}
+
+/// Store a resolved peer under `key`, emitting `peer-added` for a new one,
+/// `peer-updated` when a known one moved or was renamed, and the full list.
+/// Another entry at the same address under a different alias is replaced.
+fn store_peer(key: &str, peer: Peer, peers_map: &PeersMap, events: &Arc<dyn EventSink>) {
+    let mut peers = peers_map.lock().unwrap();
+
+    // Check if we already have a peer with the same IP but different alias
+    // If so, remove the old entry to avoid duplicates
+    let existing_key = peers
+        .iter()
+        .find(|(k, p)| k.as_str() != key && p.ip == peer.ip && p.alias != peer.alias)
+        .map(|(k, _)| k.clone());
+
+    let replaced = existing_key.and_then(|old_key| {
+        debug!(
+            "Removing old peer entry with same IP but different alias: {}",
+            old_key
+        );
+        peers.remove(&old_key)
+    });
+
+    let previous = peers.insert(key.to_string(), peer.clone());
+    drop(peers); // Release lock before emitting
+
+    if let Some(old_peer) = replaced {
+        let _ = events.emit("peer-removed", &old_peer);
+    }
+    match previous {
+        None => {
+            let _ = events.emit("peer-added", &peer);
+        }
+        Some(previous)
+            if previous.addresses != peer.addresses
+                || previous.alias != peer.alias
+                || previous.port != peer.port =>
+        {
+            debug!("Peer {} changed: {:?} -> {:?}", key, previous, peer);
+            let _ = events.emit("peer-updated", &peer);
+        }
+        Some(_) => {}
+    }
+    emit_peers(events, peers_map);
+}
+
+/// Fill in a peer's alias, device ID and type from its `/info` endpoint,
+/// dropping it if it turns out to be us.
+fn enrich_peer(
+    key: String,
+    peer: Peer,
+    me: &OwnDevice,
+    peers_map: PeersMap,
+    events: Arc<dyn EventSink>,
+    runtime: &Handle,
+) {
+    let me = me.clone();
+    runtime.spawn(async move {
+        let info = match fetch_device_info(&peer.candidates(), peer.port).await {
+            Ok(info) => info,
+            Err(e) => {
+                warn!("Could not fetch info for {}: {}", key, e);
+                return;
+            }
+        };
+
+        let (removed, renamed) = {
+            let mut peers = peers_map.lock().unwrap();
+            if me.is(&info.alias, Some(&info.device_id)) {
+                debug!("{} turned out to be our own device, removing", key);
+                (peers.remove(&key), None)
+            } else if let Some(existing) = peers.get_mut(&key) {
+                info!("Enriched peer {} from /info: {}", key, info.alias);
+                let renamed = existing.alias != info.alias;
+                existing.alias = info.alias;
+                existing.device_id = Some(info.device_id);
+                existing.device_type = Some(info.device_type);
+                existing.last_seen = now_millis();
+                (None, renamed.then(|| existing.clone()))
+            } else {
+                // Removed while we were waiting for the response
+                return;
+            }
+        };
+
+        if let Some(removed) = removed {
+            let _ = events.emit("peer-removed", &removed);
         }
+        if let Some(renamed) = renamed {
+            let _ = events.emit("peer-updated", &renamed);
+        }
+        emit_peers(&events, &peers_map);
+    });
+}
+
+/// Parse, filter and rank the addresses from an mDNS record.
+/// Routable IPv4 comes first, then global/unique-local IPv6, then IPv4
+/// link-local. IPv6 link-local addresses are dropped since they need a zone
+/// ID, which can't be expressed in an HTTP URL.
+fn usable_addresses(raw: impl Iterator<Item = String>) -> Vec<String> {
+    let mut addrs: Vec<IpAddr> = raw
+        // Scoped addresses are rendered as "fe80::1%eth0"
+        .filter_map(|addr| addr.split('%').next()?.parse::<IpAddr>().ok())
+        .filter(|ip| !ip.is_loopback() && !ip.is_unspecified() && !ip.is_multicast())
+        .filter(|ip| match ip {
+            IpAddr::V6(v6) => (v6.segments()[0] & 0xffc0) != 0xfe80,
+            IpAddr::V4(_) => true,
+        })
+        .collect();
+
+    addrs.sort_by_key(|ip| match ip {
+        IpAddr::V4(v4) if v4.is_link_local() => 2,
+        IpAddr::V4(_) => 0,
+        IpAddr::V6(_) => 1,
+    });
+    addrs.dedup();
+
+    addrs.iter().map(|ip| ip.to_string()).collect()
+}
+
+/// Addresses to advertise: those of the selected interfaces, or of every
+/// interface when none are selected, falling back to the default route's
+/// address if interfaces can't be listed.
+fn local_addresses(interfaces: &[String]) -> Result<Vec<IpAddr>, Error> {
+    let addrs = interface_addresses(interfaces);
+    if !addrs.is_empty() {
+        return Ok(addrs);
     }
+
+    if !interfaces.is_empty() {
+        return Err(Error::Network(format!(
+            "None of the selected interfaces have an address: {}",
+            interfaces.join(", ")
+        )));
+    }
+
+    let ip = local_ip_address::local_ip()
+        .map_err(|e| Error::Network(format!("Failed to get local IP: {}", e)))?;
+    Ok(vec![ip])
 }
 
-fn emit_peers(app: &AppHandle, peers: &Arc<Mutex<HashMap<String, Peer>>>) {
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Drop peers that have been silent for too long and ping the ones going stale.
+fn check_liveness(events: &Arc<dyn EventSink>, runtime: &Handle, peers_map: &PeersMap) {
+    let now = now_millis();
+    let grace = PEER_GRACE_PERIOD.as_millis() as u64;
+    let stale = PEER_STALE_AFTER.as_millis() as u64;
+
+    remove_peers_where(events, peers_map, |p| {
+        now.saturating_sub(p.last_seen) > grace || p.failed_pings >= MAX_FAILED_PINGS
+    });
+
+    let stale_peers: Vec<(String, Peer)> = peers_map
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(_, p)| now.saturating_sub(p.last_seen) > stale)
+        .map(|(k, p)| (k.clone(), p.clone()))
+        .collect();
+
+    for (key, peer) in stale_peers {
+        let peers_map = peers_map.clone();
+        runtime.spawn(async move {
+            let alive = ping_peer(&peer.candidates(), peer.port).await;
+            if let Some(existing) = peers_map.lock().unwrap().get_mut(&key) {
+                if alive {
+                    existing.last_seen = now_millis();
+                    existing.failed_pings = 0;
+                } else {
+                    existing.failed_pings += 1;
+                    warn!(
+                        "Ping to {} ({}:{}) failed ({} in a row)",
+                        existing.alias, existing.ip, existing.port, existing.failed_pings
+                    );
+                }
+            }
+        });
+    }
+}
+
+/// Remove every peer matching `predicate`, emitting a `peer-removed` event for each.
+fn remove_peers_where(
+    events: &Arc<dyn EventSink>,
+    peers_map: &PeersMap,
+    predicate: impl Fn(&Peer) -> bool,
+) {
+    let removed: Vec<Peer> = {
+        let mut peers = peers_map.lock().unwrap();
+        let keys: Vec<String> = peers
+            .iter()
+            .filter(|(_, p)| predicate(p))
+            .map(|(k, _)| k.clone())
+            .collect();
+        keys.iter().filter_map(|k| peers.remove(k)).collect()
+    };
+
+    if removed.is_empty() {
+        return;
+    }
+
+    for peer in &removed {
+        info!("Peer expired: {} ({}:{})", peer.alias, peer.ip, peer.port);
+        let _ = events.emit("peer-removed", peer);
+    }
+    emit_peers(events, peers_map);
+}
+
+fn emit_peers(events: &Arc<dyn EventSink>, peers: &PeersMap) {
     let list: Vec<Peer> = peers.lock().unwrap().values().cloned().collect();
-    let _ = app.emit("peers-update", list);
+    let _ = events.emit("peers-update", list);
 }
 
 // Function to register the service (broadcast presence)
-pub fn register_service(alias: &str, port: u16) -> Result<ServiceDaemon, String> {
-    eprintln!("Registering mDNS service...");
+fn register_service(
+    alias: &str,
+    port: u16,
+    device_id: &str,
+    interfaces: &[String],
+) -> Result<Registration, Error> {
+    debug!("Registering mDNS service...");
 
     let daemon = ServiceDaemon::new().map_err(|e| {
-        let err_msg = format!("Failed to create ServiceDaemon: {}", e);
-        eprintln!("{}", err_msg);
-        err_msg
+        let err = Error::Internal(format!("Failed to create ServiceDaemon: {}", e));
+        error!("{}", err);
+        err
     })?;
 
-    let service_type = "_myshare_app._tcp.local.";
     let hostname = hostname::get()
         .unwrap_or_default()
         .to_string_lossy()
         .to_string();
 
-    eprintln!("  Hostname: {}", hostname);
+    debug!("Hostname: {}", hostname);
 
-    let ip_addr = local_ip_address::local_ip().map_err(|e| {
-        let err_msg = format!("Failed to get local IP: {}", e);
-        eprintln!("{}", err_msg);
-        err_msg
+    // Only answer mDNS queries on the interfaces the user picked
+    if !interfaces.is_empty() {
+        debug!("Interfaces: {:?}", interfaces);
+        let _ = daemon.disable_interface(IfKind::All);
+        for name in interfaces {
+            if let Err(e) = daemon.enable_interface(IfKind::Name(name.clone())) {
+                warn!("Failed to enable interface {}: {}", name, e);
+            }
+        }
+    }
+
+    let ip_addrs = local_addresses(interfaces).map_err(|e| {
+        error!("{}", e);
+        e
     })?;
 
-    eprintln!("  IP Address: {}", ip_addr);
-    eprintln!("  Port: {}", port);
-    eprintln!("  Alias: {}", alias);
+    debug!("IP Addresses: {:?}", ip_addrs);
+    debug!("Port: {}", port);
+    debug!("Alias: {}", alias);
 
-    let properties = [("alias", alias)];
+    let properties = [
+        ("alias", alias),
+        ("device_id", device_id),
+        ("device_type", device_type()),
+        ("protocol", PROTOCOL_VERSION),
+    ];
 
     let my_service = ServiceInfo::new(
-        service_type,
+        SERVICE_TYPE,
         alias,
         &format!("{}.local.", hostname),
-        &ip_addr.to_string(),
+        &ip_addrs[..],
         port,
         &properties[..],
     )
     .map_err(|e| {
-        let err_msg = format!("Failed to create ServiceInfo: {}", e);
-        eprintln!("{}", err_msg);
-        err_msg
+        let err = Error::Internal(format!("Failed to create ServiceInfo: {}", e));
+        error!("{}", err);
+        err
     })?;
+    let fullname = my_service.get_fullname().to_string();
 
     daemon.register(my_service).map_err(|e| {
-        let err_msg = format!("Failed to register service: {}", e);
-        eprintln!("{}", err_msg);
-        err_msg
+        let err = Error::Internal(format!("Failed to register service: {}", e));
+        error!("{}", err);
+        err
     })?;
 
-    eprintln!("  Service registered successfully!");
-    Ok(daemon)
+    info!("Service registered successfully!");
+    Ok(Registration { daemon, fullname })
 }
 
-// Function to manually refresh discovery
-pub fn refresh_discovery() -> Result<(), String> {
-    eprintln!("Manual discovery refresh triggered...");
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::ChannelEventSink;
+    use axum::{routing::get, Router};
+    use std::cell::RefCell;
+    use std::collections::HashSet;
+
+    fn peer(device_id: &str, addresses: &[&str]) -> Peer {
+        Peer {
+            ip: addresses[0].to_string(),
+            addresses: addresses.iter().map(|addr| addr.to_string()).collect(),
+            port: 53317,
+            alias: device_id.to_string(),
+            hostname: format!("{}.local.", device_id),
+            device_id: Some(device_id.to_string()),
+            device_type: None,
+            last_seen: now_millis(),
+            failed_pings: 0,
+        }
+    }
+
+    fn peers_map(peers: Vec<Peer>) -> PeersMap {
+        let peers = peers
+            .into_iter()
+            .enumerate()
+            .map(|(i, peer)| (format!("peer-{}", i), peer))
+            .collect();
+        Arc::new(Mutex::new(peers))
+    }
+
+    #[test]
+    fn own_device_is_told_apart_by_id() {
+        let me = OwnDevice {
+            alias: "Naruto-Sensei".to_string(),
+            device_id: "my-id".to_string(),
+        };
+
+        assert!(me.is("Naruto-Sensei", Some("my-id")));
+        assert!(me.is("Renamed", Some("my-id")));
+        // Someone else who picked the same alias
+        assert!(!me.is("Naruto-Sensei", Some("their-id")));
+        // Services without an ID can only be told apart by alias
+        assert!(me.is("Naruto-Sensei", None));
+        assert!(!me.is("Kakashi", None));
+    }
+
+    #[test]
+    fn devices_are_trusted_only_where_they_were_discovered() {
+        let ip = |addr: &str| addr.parse::<IpAddr>().unwrap();
+        let directory = DiscoveredPeers(peers_map(vec![peer(
+            "alice",
+            &["192.168.1.20", "fd00::20"],
+        )]));
+
+        assert!(directory.is_device_at("alice", ip("192.168.1.20")));
+        assert!(directory.is_device_at("alice", ip("fd00::20")));
+        assert!(directory.is_device_at("alice", ip("::ffff:192.168.1.20")));
+        assert!(!directory.is_device_at("alice", ip("192.168.1.21")));
+        assert!(!directory.is_device_at("bob", ip("192.168.1.20")));
+
+        // Someone else advertising alice's ID makes both untrusted
+        directory
+            .0
+            .lock()
+            .unwrap()
+            .insert("impostor".to_string(), peer("alice", &["192.168.1.66"]));
+        assert!(!directory.is_device_at("alice", ip("192.168.1.20")));
+        assert!(!directory.is_device_at("alice", ip("192.168.1.66")));
+    }
+
+    #[test]
+    fn usable_addresses_are_ranked_and_filtered() {
+        let raw = [
+            "169.254.10.1",
+            "fe80::1%eth0",
+            "2001:db8::20",
+            "127.0.0.1",
+            "192.168.1.20",
+            "::1",
+            "0.0.0.0",
+            "224.0.0.251",
+            "fd00::20",
+            "192.168.1.20",
+            "not an address",
+        ];
 
-    if let Ok(control_lock) = DISCOVERY_CONTROL.lock() {
-        if let Some(sender) = control_lock.as_ref() {
-            match sender.send(DiscoveryCommand::Refresh) {
-                Ok(_) => {
-                    eprintln!("  Refresh command sent successfully");
-                    Ok(())
+        assert_eq!(
+            usable_addresses(raw.iter().map(|addr| addr.to_string())),
+            vec!["192.168.1.20", "2001:db8::20", "fd00::20", "169.254.10.1"]
+        );
+    }
+
+    /// Wait until `check` holds for the peer stored under `key`.
+    async fn wait_for_peer(peers: &PeersMap, key: &str, check: impl Fn(&Peer) -> bool) {
+        for _ in 0..100 {
+            if peers.lock().unwrap().get(key).is_some_and(&check) {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("{} never got there", key);
+    }
+
+    #[tokio::test]
+    async fn silent_and_unreachable_peers_are_dropped() {
+        let (sink, mut received) = ChannelEventSink::new();
+        let events: Arc<dyn EventSink> = Arc::new(sink);
+
+        let mut gone = peer("gone", &["192.168.1.20"]);
+        gone.last_seen = now_millis() - PEER_GRACE_PERIOD.as_millis() as u64 - 1000;
+        let mut failing = peer("failing", &["192.168.1.21"]);
+        failing.failed_pings = MAX_FAILED_PINGS;
+        let peers = peers_map(vec![gone, failing, peer("here", &["192.168.1.22"])]);
+
+        check_liveness(&events, &Handle::current(), &peers);
+
+        let left: Vec<String> = peers
+            .lock()
+            .unwrap()
+            .values()
+            .map(|p| p.alias.clone())
+            .collect();
+        assert_eq!(left, vec!["here".to_string()]);
+        let mut removed = Vec::new();
+        while let Ok(event) = received.try_recv() {
+            if event.name == "peer-removed" {
+                removed.push(event.payload["alias"].as_str().unwrap().to_string());
+            }
+        }
+        removed.sort();
+        assert_eq!(removed, vec!["failing", "gone"]);
+    }
+
+    #[test]
+    fn changed_peers_are_reported_as_updated() {
+        let (sink, mut received) = ChannelEventSink::new();
+        let events: Arc<dyn EventSink> = Arc::new(sink);
+        let peers = peers_map(Vec::new());
+        let mut names = || {
+            let mut names = Vec::new();
+            while let Ok(event) = received.try_recv() {
+                if event.name != "peers-update" {
+                    names.push(event.name);
                 }
-                Err(e) => {
-                    let err_msg = format!("Failed to send refresh command: {}", e);
-                    eprintln!("  {}", err_msg);
-                    Err(err_msg)
+            }
+            names
+        };
+
+        store_peer("peer-0", peer("alice", &["192.168.1.20"]), &peers, &events);
+        assert_eq!(names(), vec!["peer-added"]);
+
+        // Resolved again as it was, e.g. after a periodic refresh
+        store_peer("peer-0", peer("alice", &["192.168.1.20"]), &peers, &events);
+        assert!(names().is_empty());
+
+        store_peer(
+            "peer-0",
+            peer("alice", &["192.168.1.20", "fd00::20"]),
+            &peers,
+            &events,
+        );
+        assert_eq!(names(), vec!["peer-updated"]);
+
+        let mut renamed = peer("alice", &["192.168.1.20", "fd00::20"]);
+        renamed.alias = "Alice's laptop".to_string();
+        store_peer("peer-0", renamed, &peers, &events);
+        assert_eq!(names(), vec!["peer-updated"]);
+
+        let mut moved = peer("alice", &["192.168.1.20", "fd00::20"]);
+        moved.alias = "Alice's laptop".to_string();
+        moved.port = 53318;
+        store_peer("peer-0", moved, &peers, &events);
+        assert_eq!(names(), vec!["peer-updated"]);
+        assert_eq!(peers.lock().unwrap()["peer-0"].port, 53318);
+    }
+
+    #[tokio::test]
+    async fn stale_peers_are_pinged_on_every_address() {
+        let (sink, _events) = ChannelEventSink::new();
+        let events: Arc<dyn EventSink> = Arc::new(sink);
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let app = Router::new().route("/ping", get(|| async { "pong" }));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let stale_since = now_millis() - PEER_STALE_AFTER.as_millis() as u64 - 1000;
+        // Nothing listens on 127.0.0.2, but the peer answers on its other address
+        let mut moved = peer("moved", &["127.0.0.2", "127.0.0.1"]);
+        moved.port = port;
+        moved.last_seen = stale_since;
+        moved.failed_pings = 1;
+        let mut silent = peer("silent", &["127.0.0.2"]);
+        silent.port = port;
+        silent.last_seen = stale_since;
+        let peers = peers_map(vec![moved, silent]);
+
+        check_liveness(&events, &Handle::current(), &peers);
+
+        wait_for_peer(&peers, "peer-0", |p| p.failed_pings == 0).await;
+        assert!(peers.lock().unwrap()["peer-0"].last_seen > stale_since);
+        wait_for_peer(&peers, "peer-1", |p| p.failed_pings == 1).await;
+        assert_eq!(peers.lock().unwrap()["peer-1"].last_seen, stale_since);
+    }
+
+    /// Watches the network for our service type, as another device would.
+    struct Observer {
+        daemon: ServiceDaemon,
+        events: mdns_sd::Receiver<ServiceEvent>,
+        /// Full names of the services announced and not withdrawn so far
+        present: RefCell<HashSet<String>>,
+    }
+
+    impl Observer {
+        fn new() -> Self {
+            let daemon = ServiceDaemon::new().unwrap();
+            let events = daemon.browse(SERVICE_TYPE).unwrap();
+            Self {
+                daemon,
+                events,
+                present: Default::default(),
+            }
+        }
+
+        /// Wait until the service called `alias` has been announced, or, if
+        /// not `present`, has said goodbye.
+        fn expect(&self, alias: &str, present: bool) {
+            let fullname = format!("{}.{}", alias, SERVICE_TYPE);
+            let deadline = Instant::now() + Duration::from_secs(10);
+            while self.present.borrow().contains(&fullname) != present {
+                let Some(left) = deadline.checked_duration_since(Instant::now()) else {
+                    panic!(
+                        "{} was never {}",
+                        alias,
+                        if present { "announced" } else { "withdrawn" }
+                    );
+                };
+                match self.events.recv_timeout(left) {
+                    Ok(ServiceEvent::ServiceResolved(info)) => {
+                        self.present
+                            .borrow_mut()
+                            .insert(info.get_fullname().to_string());
+                    }
+                    Ok(ServiceEvent::ServiceRemoved(_, name)) => {
+                        self.present.borrow_mut().remove(&name);
+                    }
+                    _ => {}
                 }
             }
-        } else {
-            let err_msg = "Discovery control not initialized".to_string();
-            eprintln!("  {}", err_msg);
-            Err(err_msg)
         }
-    } else {
-        let err_msg = "Failed to lock discovery control".to_string();
-        eprintln!("  {}", err_msg);
-        Err(err_msg)
     }
-}
 
-// Function to update alias in discovery
-pub fn update_alias(new_alias: String) -> Result<(), String> {
-    eprintln!("Updating alias to: {}", new_alias);
+    impl Drop for Observer {
+        fn drop(&mut self) {
+            let _ = self.daemon.shutdown();
+        }
+    }
+
+    fn unique_config(alias: &str) -> AppConfig {
+        let id = uuid::Uuid::new_v4().to_string();
+        AppConfig {
+            alias: format!("{}-{}", alias, &id[..8]),
+            device_id: id,
+            ..AppConfig::default()
+        }
+    }
 
-    if let Ok(control_lock) = DISCOVERY_CONTROL.lock() {
-        if let Some(sender) = control_lock.as_ref() {
-            match sender.send(DiscoveryCommand::UpdateAlias(new_alias)) {
-                Ok(_) => {
-                    eprintln!("  Alias update command sent successfully");
-                    Ok(())
+    fn is_running(service: &DiscoveryService) -> (bool, bool) {
+        (
+            service.registration.lock().unwrap().is_some(),
+            service.browse.lock().unwrap().is_some(),
+        )
+    }
+
+    #[test]
+    fn stopped_and_restarted_discovery_leaves_no_stale_registration() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let (sink, _events) = ChannelEventSink::new();
+        let service = DiscoveryService::new(Arc::new(sink), runtime.handle().clone());
+        let observer = Observer::new();
+        let config = unique_config("restarted");
+
+        service.start(&config).unwrap();
+        assert_eq!(is_running(&service), (true, true));
+        observer.expect(&config.alias, true);
+        assert!(service.refresh().is_ok());
+
+        service.stop();
+        assert_eq!(is_running(&service), (false, false));
+        observer.expect(&config.alias, false);
+        assert!(service.refresh().is_err());
+        assert!(service.update_alias("anything".to_string()).is_err());
+
+        service.restart(&config).unwrap();
+        assert_eq!(is_running(&service), (true, true));
+        observer.expect(&config.alias, true);
+
+        // A new alias replaces the old registration, as the app does it
+        let renamed = AppConfig {
+            alias: format!("{}-renamed", config.alias),
+            ..config.clone()
+        };
+        service.register(&renamed, Duration::ZERO).unwrap();
+        service.update_alias(renamed.alias.clone()).unwrap();
+        observer.expect(&config.alias, false);
+        observer.expect(&renamed.alias, true);
+
+        drop(service);
+        observer.expect(&renamed.alias, false);
+    }
+
+    #[test]
+    fn two_services_in_one_process_find_each_other() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let start = |config: &AppConfig| {
+            let (sink, _events) = ChannelEventSink::new();
+            let service = DiscoveryService::new(Arc::new(sink), runtime.handle().clone());
+            service.start(config).unwrap();
+            service
+        };
+        let (alice_config, bob_config) = (unique_config("alice"), unique_config("bob"));
+        let alice = start(&alice_config);
+        let bob = start(&bob_config);
+
+        let finds = |service: &DiscoveryService, config: &AppConfig, present: bool| {
+            let deadline = Instant::now() + Duration::from_secs(10);
+            loop {
+                let found = service
+                    .peers()
+                    .iter()
+                    .any(|peer| peer.device_id.as_deref() == Some(config.device_id.as_str()));
+                if found == present {
+                    return true;
                 }
-                Err(e) => {
-                    let err_msg = format!("Failed to send alias update command: {}", e);
-                    eprintln!("  {}", err_msg);
-                    Err(err_msg)
+                if Instant::now() >= deadline {
+                    return false;
                 }
+                thread::sleep(Duration::from_millis(100));
             }
-        } else {
-            let err_msg = "Discovery control not initialized".to_string();
-            eprintln!("  {}", err_msg);
-            Err(err_msg)
-        }
-    } else {
-        let err_msg = "Failed to lock discovery control".to_string();
-        eprintln!("  {}", err_msg);
-        Err(err_msg)
+        };
+        assert!(finds(&alice, &bob_config, true));
+        assert!(finds(&bob, &alice_config, true));
+        // Neither lists itself
+        assert!(finds(&alice, &alice_config, false));
+        assert!(finds(&bob, &bob_config, false));
+
+        // Stopping one leaves the other running, and it sees the goodbye
+        bob.stop();
+        assert_eq!(is_running(&alice), (true, true));
+        assert!(finds(&alice, &bob_config, false));
     }
 }
@@ -0,0 +1,77 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Error returned by commands and the transfer code. Serialized as
+/// `{ "kind": "network", "message": "..." }` so the frontend can tell
+/// failures apart without matching on the message.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "message", rename_all = "snake_case")]
+pub enum Error {
+    /// The peer couldn't be reached, the connection broke or timed out
+    Network(String),
+    /// The receiving user declined the transfer
+    Rejected(String),
+    /// The receiving user didn't answer the confirmation prompt in time
+    Timeout(String),
+    /// Reading or writing a local file failed
+    Io(String),
+    /// A file, peer or transfer doesn't exist
+    NotFound(String),
+    /// The OS refused access to a file or directory
+    Permission(String),
+    /// A command was called with arguments that don't make sense
+    InvalidInput(String),
+    /// The transfer was stopped before it finished
+    Cancelled(String),
+    /// Not available on this platform
+    Unsupported(String),
+    /// Anything else, e.g. mDNS or event delivery failures
+    Internal(String),
+}
+
+impl Error {
+    /// Error from an I/O failure, with `context` describing what was being done.
+    pub fn io(context: &str, err: std::io::Error) -> Self {
+        let message = format!("{}: {}", context, err);
+        match err.kind() {
+            std::io::ErrorKind::NotFound => Error::NotFound(message),
+            std::io::ErrorKind::PermissionDenied => Error::Permission(message),
+            std::io::ErrorKind::TimedOut => Error::Network(message),
+            _ => Error::Io(message),
+        }
+    }
+
+    /// Error from a failed HTTP request to a peer. Connect and read timeouts
+    /// count as network errors, not as [`Error::Timeout`].
+    pub fn request(context: &str, err: reqwest::Error) -> Self {
+        let message = format!("{}: {}", context, err);
+        if err.is_builder() {
+            Error::InvalidInput(message)
+        } else {
+            Error::Network(message)
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            Error::Network(message)
+            | Error::Rejected(message)
+            | Error::Timeout(message)
+            | Error::Io(message)
+            | Error::NotFound(message)
+            | Error::Permission(message)
+            | Error::InvalidInput(message)
+            | Error::Cancelled(message)
+            | Error::Unsupported(message)
+            | Error::Internal(message) => message,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.message())
+    }
+}
+
+impl std::error::Error for Error {}
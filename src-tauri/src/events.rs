@@ -0,0 +1,51 @@
+use crate::error::Error;
+use serde::Serialize;
+use serde_json::Value;
+use tokio::sync::mpsc;
+
+/// Destination for the events the server, discovery and transfer code report
+/// (`transfer-progress`, `peers-update`, ...). The GUI forwards them to the
+/// webview; the CLI prints them.
+pub trait EventSink: Send + Sync {
+    fn emit_event(&self, event: &str, payload: Value) -> Result<(), Error>;
+}
+
+impl dyn EventSink {
+    /// Serialize `payload` and emit it as `event`.
+    pub fn emit<S: Serialize>(&self, event: &str, payload: S) -> Result<(), Error> {
+        let payload = serde_json::to_value(payload).map_err(|e| {
+            Error::Internal(format!("Failed to serialize {} payload: {}", event, e))
+        })?;
+        self.emit_event(event, payload)
+    }
+}
+
+/// An event as delivered by [`ChannelEventSink`].
+#[derive(Debug, Clone)]
+pub struct Event {
+    pub name: String,
+    pub payload: Value,
+}
+
+/// Forwards every event to a channel, for tests and headless use.
+pub struct ChannelEventSink {
+    sender: mpsc::UnboundedSender<Event>,
+}
+
+impl ChannelEventSink {
+    pub fn new() -> (Self, mpsc::UnboundedReceiver<Event>) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        (Self { sender }, receiver)
+    }
+}
+
+impl EventSink for ChannelEventSink {
+    fn emit_event(&self, event: &str, payload: Value) -> Result<(), Error> {
+        self.sender
+            .send(Event {
+                name: event.to_string(),
+                payload,
+            })
+            .map_err(|_| Error::Internal(format!("Nobody is listening for {}", event)))
+    }
+}
@@ -0,0 +1,223 @@
+use unicode_normalization::UnicodeNormalization;
+
+/// Longest file name, in bytes, that common file systems accept
+pub const MAX_NAME_BYTES: usize = 255;
+/// Used when nothing is left of a name after sanitizing it
+const FALLBACK_NAME: &str = "file";
+/// Extensions longer than this are treated as part of the name when
+/// shortening, since they're unlikely to be real ones
+const MAX_EXTENSION_BYTES: usize = 16;
+
+/// Names Windows reserves for devices, with or without an extension,
+/// including the ports numbered with superscript digits
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "CONIN$", "CONOUT$", "COM1", "COM2", "COM3", "COM4", "COM5",
+    "COM6", "COM7", "COM8", "COM9", "COM¹", "COM²", "COM³", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5",
+    "LPT6", "LPT7", "LPT8", "LPT9", "LPT¹", "LPT²", "LPT³",
+];
+
+/// Turn a file name from a peer into one that is safe to create in the
+/// download directory on any platform:
+///
+/// - normalized to NFC, so the same name looks the same on every system
+/// - path separators, characters Windows forbids and control characters are
+///   replaced by `_`, so the name can't point outside the directory
+/// - leading dots are dropped, so files don't end up hidden and `..` isn't
+///   left over, and trailing dots and spaces, which Windows strips itself
+/// - Windows device names such as `CON.txt` get a `_` prefix
+/// - names longer than [`MAX_NAME_BYTES`] are shortened, keeping the
+///   extension
+pub fn sanitize(name: &str) -> String {
+    let replaced: String = name
+        .nfc()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+
+    let mut name = trim(&replaced).to_string();
+    if name.is_empty() {
+        return FALLBACK_NAME.to_string();
+    }
+    if is_reserved(&name) {
+        name.insert(0, '_');
+    }
+    shorten(&name, MAX_NAME_BYTES)
+}
+
+fn trim(name: &str) -> &str {
+    name.trim_start_matches(|c: char| c == '.' || c.is_whitespace())
+        .trim_end_matches(|c: char| c == '.' || c.is_whitespace())
+}
+
+/// Whether Windows treats `name` as a device, which it does for the
+/// reserved names followed by any extension.
+fn is_reserved(name: &str) -> bool {
+    let stem = name.split('.').next().unwrap_or(name).trim_end();
+    RESERVED_NAMES
+        .iter()
+        .any(|reserved| stem.eq_ignore_ascii_case(reserved))
+}
+
+/// Cut `name` down to at most `max` bytes without splitting a character,
+/// taking the bytes from before the extension.
+fn shorten(name: &str, max: usize) -> String {
+    if name.len() <= max {
+        return name.to_string();
+    }
+
+    let (stem, extension) = split_extension(name);
+    let stem = truncate(stem, max - extension.len());
+    // The cut may have left dots or spaces at the end of the stem
+    let stem = trim(stem);
+    if stem.is_empty() {
+        return truncate(name, max).to_string();
+    }
+    format!("{}{}", stem, extension)
+}
+
+/// `name` with ` (n)` before its extension, e.g. `photo (1).jpg`, shortened
+/// to stay within [`MAX_NAME_BYTES`].
+pub fn numbered(name: &str, n: u32) -> String {
+    let (stem, extension) = split_extension(name);
+    let suffix = format!(" ({})", n);
+    let stem = truncate(stem, MAX_NAME_BYTES - suffix.len() - extension.len());
+    format!("{}{}{}", stem.trim_end(), suffix, extension)
+}
+
+/// Split `name` before the dot of its extension, if it has a plausible one.
+fn split_extension(name: &str) -> (&str, &str) {
+    match name.rfind('.') {
+        Some(dot) if dot > 0 && name.len() - dot <= MAX_EXTENSION_BYTES + 1 => name.split_at(dot),
+        _ => (name, ""),
+    }
+}
+
+fn truncate(s: &str, max: usize) -> &str {
+    let mut end = max.min(s.len());
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ordinary_names_are_kept() {
+        assert_eq!(sanitize("photo.jpg"), "photo.jpg");
+        assert_eq!(
+            sanitize("Résumé 2024 (final).pdf"),
+            "Résumé 2024 (final).pdf"
+        );
+        assert_eq!(sanitize("archive.tar.gz"), "archive.tar.gz");
+    }
+
+    #[test]
+    fn parent_directories_cant_be_reached() {
+        assert_eq!(sanitize(".."), FALLBACK_NAME);
+        assert_eq!(sanitize("."), FALLBACK_NAME);
+        assert_eq!(sanitize("../../etc/passwd"), "_.._etc_passwd");
+        assert_eq!(sanitize("..\\..\\Windows\\win.ini"), "_.._Windows_win.ini");
+        assert_eq!(sanitize("/etc/passwd"), "_etc_passwd");
+    }
+
+    #[test]
+    fn forbidden_characters_are_replaced() {
+        assert_eq!(sanitize("image:1000283390"), "image_1000283390");
+        assert_eq!(sanitize("what?*.txt"), "what__.txt");
+        assert_eq!(sanitize("\"quoted\" <a|b>.txt"), "_quoted_ _a_b_.txt");
+    }
+
+    #[test]
+    fn control_characters_are_replaced() {
+        assert_eq!(sanitize("line\nbreak.txt"), "line_break.txt");
+        assert_eq!(sanitize("tab\there\u{7f}.txt"), "tab_here_.txt");
+        assert_eq!(sanitize("nul\0byte"), "nul_byte");
+    }
+
+    #[test]
+    fn trailing_dots_and_spaces_are_dropped() {
+        assert_eq!(sanitize("report."), "report");
+        assert_eq!(sanitize("report.pdf. . "), "report.pdf");
+        assert_eq!(sanitize("..."), FALLBACK_NAME);
+        assert_eq!(sanitize("   "), FALLBACK_NAME);
+        assert_eq!(sanitize(""), FALLBACK_NAME);
+    }
+
+    #[test]
+    fn leading_dots_are_dropped() {
+        assert_eq!(sanitize(".bashrc"), "bashrc");
+        assert_eq!(sanitize("...hidden.txt"), "hidden.txt");
+        assert_eq!(sanitize(" . spaced"), "spaced");
+    }
+
+    #[test]
+    fn windows_device_names_are_prefixed() {
+        assert_eq!(sanitize("CON"), "_CON");
+        assert_eq!(sanitize("con.txt"), "_con.txt");
+        assert_eq!(sanitize("Lpt1.tar.gz"), "_Lpt1.tar.gz");
+        assert_eq!(sanitize("nul .txt"), "_nul .txt");
+        assert_eq!(sanitize("COM10.txt"), "COM10.txt");
+        assert_eq!(sanitize("console.txt"), "console.txt");
+        assert_eq!(sanitize("my CON.txt"), "my CON.txt");
+    }
+
+    #[test]
+    fn console_and_superscript_port_names_are_prefixed() {
+        assert_eq!(sanitize("CONIN$"), "_CONIN$");
+        assert_eq!(sanitize("conout$.log"), "_conout$.log");
+        assert_eq!(sanitize("COM¹"), "_COM¹");
+        assert_eq!(sanitize("lpt³.txt"), "_lpt³.txt");
+        assert_eq!(sanitize("COM⁴.txt"), "COM⁴.txt");
+        assert_eq!(sanitize("CONIN.txt"), "CONIN.txt");
+    }
+
+    #[test]
+    fn long_names_keep_their_extension() {
+        let long = format!("{}.jpg", "a".repeat(300));
+        let sanitized = sanitize(&long);
+        assert_eq!(sanitized.len(), MAX_NAME_BYTES);
+        assert!(sanitized.ends_with("a.jpg"));
+
+        // Multi-byte characters aren't split
+        let long = format!("{}.txt", "é".repeat(200));
+        let sanitized = sanitize(&long);
+        assert!(sanitized.len() <= MAX_NAME_BYTES);
+        assert!(sanitized.ends_with("é.txt"));
+
+        // Something after the last dot that is too long for an extension
+        // is cut like the rest of the name
+        let long = format!("notes.{}", "b".repeat(300));
+        assert_eq!(sanitize(&long), truncate(&long, MAX_NAME_BYTES));
+
+        // The cut doesn't leave a dot before the extension's own dot
+        let long = format!("{}. .png", "c".repeat(250));
+        assert_eq!(sanitize(&long), format!("{}.png", "c".repeat(250)));
+    }
+
+    #[test]
+    fn numbers_go_before_the_extension() {
+        assert_eq!(numbered("photo.jpg", 1), "photo (1).jpg");
+        assert_eq!(numbered("archive.tar.gz", 2), "archive.tar (2).gz");
+        assert_eq!(numbered("README", 12), "README (12)");
+
+        let long = format!("{}.jpg", "a".repeat(251));
+        let numbered = numbered(&long, 3);
+        assert_eq!(numbered.len(), MAX_NAME_BYTES);
+        assert!(numbered.ends_with("a (3).jpg"));
+    }
+
+    #[test]
+    fn names_are_normalized_to_nfc() {
+        // "é" as "e" followed by a combining acute accent, as macOS sends it
+        let decomposed = "cafe\u{301}.txt";
+        let sanitized = sanitize(decomposed);
+        assert_eq!(sanitized, "caf\u{e9}.txt");
+        assert_eq!(sanitized.len(), decomposed.len() - 1);
+    }
+}
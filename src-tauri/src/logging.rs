@@ -0,0 +1,370 @@
+use crate::error::Error;
+use log::kv::{Key, Value, VisitSource};
+use log::{LevelFilter, Log, Metadata, Record};
+use std::fmt::Write as _;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Name of the active log file; rotated copies get `.1`, `.2`, ... appended.
+pub const LOG_FILE_NAME: &str = "local-share.log";
+/// Environment variable overriding the log filter, e.g.
+/// `LOCAL_SHARE_LOG=info,local_share_lib::discovery=debug`
+pub const LOG_FILTER_ENV: &str = "LOCAL_SHARE_LOG";
+/// Filter used when the environment variable isn't set. mDNS and HTTP
+/// libraries are chatty, so only their warnings are kept.
+pub const DEFAULT_LOG_FILTER: &str = "warn,local_share_lib=info";
+
+/// Rotate the log file once it grows past this size
+const MAX_LOG_FILE_SIZE: u64 = 1024 * 1024;
+/// Rotated files to keep besides the active one
+const MAX_ROTATED_FILES: usize = 4;
+/// Structured fields holding file names or paths, hidden by `export_logs` on request
+const FILE_NAME_FIELDS: &[&str] = &["file", "raw_file", "new_file", "path", "dir", "transfer_id"];
+
+/// Per-target level filter parsed from `default,target=level,...`.
+/// The longest matching target prefix wins.
+#[derive(Debug, Clone)]
+pub struct LogFilter {
+    default: LevelFilter,
+    targets: Vec<(String, LevelFilter)>,
+}
+
+impl LogFilter {
+    pub fn parse(spec: &str) -> Self {
+        let mut filter = LogFilter {
+            default: LevelFilter::Info,
+            targets: Vec::new(),
+        };
+
+        for directive in spec.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            match directive.split_once('=') {
+                Some((target, level)) => match level.trim().parse() {
+                    Ok(level) => filter.targets.push((target.trim().to_string(), level)),
+                    Err(_) => eprintln!("Ignoring invalid log directive: {}", directive),
+                },
+                None => match directive.parse() {
+                    Ok(level) => filter.default = level,
+                    Err(_) => eprintln!("Ignoring invalid log directive: {}", directive),
+                },
+            }
+        }
+
+        // Longest prefixes first so the most specific directive wins
+        filter
+            .targets
+            .sort_by_key(|(target, _)| std::cmp::Reverse(target.len()));
+        filter
+    }
+
+    /// The filter from `LOCAL_SHARE_LOG`, or `default` if it isn't set.
+    pub fn from_env(default: &str) -> Self {
+        match std::env::var(LOG_FILTER_ENV) {
+            Ok(spec) if !spec.trim().is_empty() => Self::parse(&spec),
+            _ => Self::parse(default),
+        }
+    }
+
+    fn level_for(&self, target: &str) -> LevelFilter {
+        self.targets
+            .iter()
+            .find(|(prefix, _)| {
+                target == prefix
+                    || (target.starts_with(prefix.as_str())
+                        && target[prefix.len()..].starts_with("::"))
+            })
+            .map(|(_, level)| *level)
+            .unwrap_or(self.default)
+    }
+
+    fn max_level(&self) -> LevelFilter {
+        self.targets
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(self.default, |a, b| a.max(b))
+    }
+}
+
+struct Logger {
+    filter: LogFilter,
+    file: Option<Mutex<RotatingFile>>,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.filter.level_for(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let line = format_record(record);
+        eprintln!("{}", line);
+
+        if let Some(file) = &self.file {
+            if let Err(e) = file.lock().unwrap().write_line(&line) {
+                eprintln!("Failed to write log file: {}", e);
+            }
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(file) = &self.file {
+            let _ = file.lock().unwrap().file.flush();
+        }
+    }
+}
+
+/// `2026-01-31 12:00:00.000 INFO  local_share_lib::server: Message key="value"`
+fn format_record(record: &Record) -> String {
+    let mut line = format!(
+        "{} {:<5} {}: {}",
+        chrono::Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),
+        record.level(),
+        record.target(),
+        record.args()
+    );
+
+    let mut fields = FieldWriter(&mut line);
+    let _ = record.key_values().visit(&mut fields);
+    line
+}
+
+struct FieldWriter<'a>(&'a mut String);
+
+impl<'kvs> VisitSource<'kvs> for FieldWriter<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), log::kv::Error> {
+        // Quoted so values with spaces can be found again by `redact_fields`
+        let _ = write!(self.0, " {}={:?}", key, value.to_string());
+        Ok(())
+    }
+}
+
+/// Size-based rotation: `local-share.log` becomes `.1`, `.1` becomes `.2`,
+/// and so on, dropping the oldest.
+struct RotatingFile {
+    dir: PathBuf,
+    file: File,
+    size: u64,
+}
+
+impl RotatingFile {
+    fn open(dir: &Path) -> std::io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join(LOG_FILE_NAME))?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            file,
+            size,
+        })
+    }
+
+    fn write_line(&mut self, line: &str) -> std::io::Result<()> {
+        if self.size + line.len() as u64 + 1 > MAX_LOG_FILE_SIZE {
+            self.rotate()?;
+        }
+        writeln!(self.file, "{}", line)?;
+        self.size += line.len() as u64 + 1;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> std::io::Result<()> {
+        for i in (1..MAX_ROTATED_FILES).rev() {
+            let from = rotated_path(&self.dir, i);
+            if from.exists() {
+                fs::rename(&from, rotated_path(&self.dir, i + 1))?;
+            }
+        }
+        fs::rename(self.dir.join(LOG_FILE_NAME), rotated_path(&self.dir, 1))?;
+
+        *self = Self::open(&self.dir)?;
+        Ok(())
+    }
+}
+
+fn rotated_path(dir: &Path, index: usize) -> PathBuf {
+    dir.join(format!("{}.{}", LOG_FILE_NAME, index))
+}
+
+/// Install the global logger. Records go to stderr and, with `log_dir`, to a
+/// rotating file in that directory. Only the first call has any effect.
+pub fn init(log_dir: Option<&Path>, filter: LogFilter) -> Result<(), Error> {
+    let file = match log_dir {
+        Some(dir) => Some(Mutex::new(
+            RotatingFile::open(dir).map_err(|e| Error::io("Failed to open log file", e))?,
+        )),
+        None => None,
+    };
+
+    let max_level = filter.max_level();
+    log::set_boxed_logger(Box::new(Logger { filter, file }))
+        .map_err(|e| Error::Internal(format!("Logger already installed: {}", e)))?;
+    log::set_max_level(max_level);
+    Ok(())
+}
+
+/// The kept logs, oldest first, for attaching to bug reports. With
+/// `redact_file_names`, values of fields holding file names or paths are
+/// replaced by `<redacted>`. Message text is left alone, so a name that
+/// ends up in an error message is still exported.
+pub fn export_logs(log_dir: &Path, redact_file_names: bool) -> Result<String, Error> {
+    let mut files: Vec<PathBuf> = (1..=MAX_ROTATED_FILES)
+        .rev()
+        .map(|i| rotated_path(log_dir, i))
+        .collect();
+    files.push(log_dir.join(LOG_FILE_NAME));
+
+    let mut logs = String::new();
+    for path in files.iter().filter(|path| path.exists()) {
+        let content =
+            fs::read_to_string(path).map_err(|e| Error::io("Failed to read log file", e))?;
+        for line in content.lines() {
+            if redact_file_names {
+                logs.push_str(&redact_fields(line, FILE_NAME_FIELDS));
+            } else {
+                logs.push_str(line);
+            }
+            logs.push('\n');
+        }
+    }
+    Ok(logs)
+}
+
+/// Replace the quoted values of ` key="..."` fields written by `FieldWriter`.
+fn redact_fields(line: &str, keys: &[&str]) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut rest = line;
+
+    'scan: while !rest.is_empty() {
+        for key in keys {
+            let prefix = format!(" {}=\"", key);
+            if let Some(after) = rest.strip_prefix(prefix.as_str()) {
+                if let Some(end) = closing_quote(after) {
+                    out.push_str(&prefix);
+                    out.push_str("<redacted>\"");
+                    rest = &after[end + 1..];
+                    continue 'scan;
+                }
+            }
+        }
+
+        let ch = rest.chars().next().unwrap();
+        out.push(ch);
+        rest = &rest[ch.len_utf8()..];
+    }
+    out
+}
+
+/// Index of the quote ending a `{:?}`-escaped string.
+fn closing_quote(s: &str) -> Option<usize> {
+    let mut escaped = false;
+    for (i, ch) in s.char_indices() {
+        match ch {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn most_specific_target_wins() {
+        let filter =
+            LogFilter::parse("warn, local_share_lib=info,local_share_lib::discovery=trace");
+        assert_eq!(
+            filter.level_for("mdns_sd::service_daemon"),
+            LevelFilter::Warn
+        );
+        assert_eq!(filter.level_for("local_share_lib"), LevelFilter::Info);
+        assert_eq!(
+            filter.level_for("local_share_lib::server"),
+            LevelFilter::Info
+        );
+        assert_eq!(
+            filter.level_for("local_share_lib::discovery"),
+            LevelFilter::Trace
+        );
+        // Only whole path segments match
+        assert_eq!(filter.level_for("local_share_library"), LevelFilter::Warn);
+        assert_eq!(filter.max_level(), LevelFilter::Trace);
+    }
+
+    #[test]
+    fn invalid_directives_are_skipped() {
+        let filter = LogFilter::parse("loud,,local_share_lib=noisy,hyper=error");
+        assert_eq!(filter.level_for("local_share_lib"), LevelFilter::Info);
+        assert_eq!(filter.level_for("hyper::proto"), LevelFilter::Error);
+    }
+
+    #[test]
+    fn file_name_fields_are_redacted() {
+        let line = r#"INFO x: Saved "a.txt" file="my \"best\" photo.jpg" size="12" path="C:\\tmp""#;
+        assert_eq!(
+            redact_fields(line, FILE_NAME_FIELDS),
+            r#"INFO x: Saved "a.txt" file="<redacted>" size="12" path="<redacted>""#
+        );
+        // A field name inside another value isn't mistaken for a field
+        let line = r#"x: m alias=" file=\"y" new_file="z""#;
+        assert_eq!(
+            redact_fields(line, FILE_NAME_FIELDS),
+            r#"x: m alias=" file=\"y" new_file="<redacted>""#
+        );
+    }
+
+    #[test]
+    fn closing_quote_skips_escapes() {
+        assert_eq!(closing_quote(r#"ab" c"#), Some(2));
+        assert_eq!(closing_quote(r#"a\"b" c"#), Some(4));
+        assert_eq!(closing_quote(r#"a\\" c"#), Some(3));
+        assert_eq!(closing_quote(r#"a\""#), None);
+    }
+
+    #[test]
+    fn log_file_rotates_at_the_size_limit() {
+        let dir = std::env::temp_dir().join(format!("logging-{}", uuid::Uuid::new_v4()));
+        let mut file = RotatingFile::open(&dir).unwrap();
+        let line = "x".repeat(1023);
+
+        // Exactly fills the file, with the newline
+        for _ in 0..MAX_LOG_FILE_SIZE / 1024 {
+            file.write_line(&line).unwrap();
+        }
+        assert!(!rotated_path(&dir, 1).exists());
+        assert_eq!(file.size, MAX_LOG_FILE_SIZE);
+
+        file.write_line("next").unwrap();
+        assert_eq!(
+            fs::metadata(rotated_path(&dir, 1)).unwrap().len(),
+            MAX_LOG_FILE_SIZE
+        );
+        assert_eq!(
+            fs::read_to_string(dir.join(LOG_FILE_NAME)).unwrap(),
+            "next\n"
+        );
+
+        // The oldest file is dropped once all slots are taken
+        for _ in 0..=MAX_ROTATED_FILES {
+            file.write_line(&"y".repeat(MAX_LOG_FILE_SIZE as usize))
+                .unwrap();
+        }
+        assert!(rotated_path(&dir, MAX_ROTATED_FILES).exists());
+        assert!(!rotated_path(&dir, MAX_ROTATED_FILES + 1).exists());
+        let exported = export_logs(&dir, false).unwrap();
+        assert!(!exported.contains("next"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,361 @@
+use crate::clipboard::base64_bytes;
+use crate::error::Error;
+use serde::{Deserialize, Serialize};
+
+/// Largest `POST /message` body accepted, in bytes; leaves room for an
+/// inline image and its base64 overhead
+pub const MAX_MESSAGE_BYTES: usize = 16 * 1024 * 1024;
+/// Largest image sent inline in an image message
+pub const MAX_INLINE_IMAGE_BYTES: usize = 10 * 1024 * 1024;
+/// Largest thumbnail; anything bigger isn't a thumbnail
+pub const MAX_THUMBNAIL_BYTES: usize = 256 * 1024;
+
+/// Body of `POST /message`.
+///
+/// Older peers send and read only `sender_alias` and `content`. Their
+/// messages read as text messages with an ID made up on arrival, and they
+/// ignore the fields they don't know, showing `content`, so
+/// [`MessagePayload::new`] fills in a stand-in when an image or file goes
+/// without a caption.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MessagePayload {
+    /// Set by the sender so replies can refer to the message
+    #[serde(default = "new_message_id")]
+    pub id: String,
+    pub sender_alias: String,
+    /// The sender's device ID, which decides whether its links are opened
+    /// right away; older peers and the CLI don't send one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device_id: Option<String>,
+    #[serde(flatten)]
+    pub body: MessageBody,
+}
+
+/// What a message says, as composed by the sender.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MessageBody {
+    #[serde(rename = "type", default)]
+    pub kind: MessageKind,
+    /// The text of a text message, the address of a link, or the caption of
+    /// an image or file
+    #[serde(default)]
+    pub content: String,
+    /// The picture of an image message
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image: Option<InlineImage>,
+    /// The file a file message is about
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<FileRef>,
+    /// Small preview shown for images, files and links
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thumbnail: Option<InlineImage>,
+    /// ID of the message this one answers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reply_to: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageKind {
+    #[default]
+    Text,
+    Url,
+    Image,
+    File,
+}
+
+/// An image carried in the message itself.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InlineImage {
+    pub mime_type: String,
+    #[serde(with = "base64_bytes")]
+    pub data: Vec<u8>,
+}
+
+/// A file sent, or about to be sent, as an ordinary transfer.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileRef {
+    pub name: String,
+    pub size: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mime_type: Option<String>,
+}
+
+fn new_message_id() -> String {
+    uuid::Uuid::new_v4().to_string()
+}
+
+impl MessagePayload {
+    /// A new message with a fresh ID.
+    pub fn new(sender_alias: String, mut body: MessageBody) -> Self {
+        body.fill_in_content();
+        Self {
+            id: new_message_id(),
+            sender_alias,
+            device_id: None,
+            body,
+        }
+    }
+}
+
+impl MessageBody {
+    pub fn text(text: String) -> Self {
+        Self {
+            content: text,
+            ..Self::default()
+        }
+    }
+
+    /// Mark a text message that is nothing but a web address as a link,
+    /// since older peers and the CLI send links as text.
+    pub fn detect_link(&mut self) {
+        if self.kind == MessageKind::Text && is_link(&self.content) {
+            self.kind = MessageKind::Url;
+            self.content = self.content.trim().to_string();
+        }
+    }
+
+    /// Give an uncaptioned image or file a `content` older peers can show:
+    /// `[Image]`, or the name of the file.
+    fn fill_in_content(&mut self) {
+        if !self.content.trim().is_empty() {
+            return;
+        }
+        match (self.kind, &self.file) {
+            (MessageKind::Image, _) => self.content = "[Image]".to_string(),
+            (MessageKind::File, Some(file)) => self.content = file.name.clone(),
+            _ => {}
+        }
+    }
+
+    /// The address of a link message.
+    pub fn link(&self) -> Option<&str> {
+        (self.kind == MessageKind::Url).then(|| self.content.trim())
+    }
+
+    /// Check that the parts the type needs are there and within limits.
+    pub fn validate(&self) -> Result<(), Error> {
+        let invalid = |reason: &str| Err(Error::InvalidInput(reason.to_string()));
+
+        match self.kind {
+            MessageKind::Text => {}
+            MessageKind::Url => {
+                if !is_link(&self.content) {
+                    return invalid("A link message needs an http or https URL");
+                }
+            }
+            MessageKind::Image => match &self.image {
+                Some(image) => {
+                    if image.data.len() > MAX_INLINE_IMAGE_BYTES {
+                        return invalid("The image is too large to send in a message");
+                    }
+                    if !is_image(image) {
+                        return invalid("The image of an image message isn't an image");
+                    }
+                }
+                None => return invalid("An image message needs an image"),
+            },
+            MessageKind::File => {
+                if self.file.is_none() {
+                    return invalid("A file message needs a file");
+                }
+            }
+        }
+
+        if let Some(thumbnail) = &self.thumbnail {
+            if thumbnail.data.len() > MAX_THUMBNAIL_BYTES {
+                return invalid("The thumbnail is too large");
+            }
+            if !is_image(thumbnail) {
+                return invalid("The thumbnail isn't an image");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether `text` is a single http or https URL, give or take surrounding
+/// whitespace.
+fn is_link(text: &str) -> bool {
+    let text = text.trim();
+    !text.contains(char::is_whitespace)
+        && url::Url::parse(text).is_ok_and(|url| matches!(url.scheme(), "http" | "https"))
+}
+
+fn is_image(image: &InlineImage) -> bool {
+    image.mime_type.starts_with("image/") && !image.data.is_empty()
+}
+
+/// Opens links in the system browser. The app's goes through the opener
+/// plugin; the server only sees this trait.
+pub trait LinkOpener: Send + Sync {
+    fn open(&self, url: &str) -> Result<(), Error>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn old_messages_read_as_text() {
+        let message: MessagePayload = serde_json::from_value(json!({
+            "sender_alias": "alice",
+            "content": "hello"
+        }))
+        .unwrap();
+        assert_eq!(message.sender_alias, "alice");
+        assert_eq!(message.body, MessageBody::text("hello".to_string()));
+        assert!(!message.id.is_empty());
+        assert!(message.body.validate().is_ok());
+    }
+
+    #[test]
+    fn old_peers_find_what_they_read() {
+        let message = MessagePayload::new(
+            "alice".to_string(),
+            MessageBody {
+                kind: MessageKind::Url,
+                content: "https://example.com".to_string(),
+                reply_to: Some("1234".to_string()),
+                ..MessageBody::default()
+            },
+        );
+        let json = serde_json::to_value(&message).unwrap();
+        assert_eq!(json["sender_alias"], "alice");
+        assert_eq!(json["content"], "https://example.com");
+        assert_eq!(json["type"], "url");
+        assert_eq!(json["reply_to"], "1234");
+        // Absent parts are left out rather than sent as null
+        assert!(json.get("image").is_none());
+
+        let read: MessagePayload = serde_json::from_value(json).unwrap();
+        assert_eq!(read, message);
+    }
+
+    #[test]
+    fn old_peers_see_uncaptioned_images_and_files() {
+        let image = MessagePayload::new(
+            "alice".to_string(),
+            MessageBody {
+                kind: MessageKind::Image,
+                image: Some(InlineImage {
+                    mime_type: "image/png".to_string(),
+                    data: vec![0x89, b'P', b'N', b'G'],
+                }),
+                ..MessageBody::default()
+            },
+        );
+        let file = MessagePayload::new(
+            "alice".to_string(),
+            MessageBody {
+                kind: MessageKind::File,
+                file: Some(FileRef {
+                    name: "report.pdf".to_string(),
+                    size: 1024,
+                    mime_type: None,
+                }),
+                ..MessageBody::default()
+            },
+        );
+        let captioned = MessagePayload::new(
+            "alice".to_string(),
+            MessageBody {
+                content: "the numbers".to_string(),
+                ..file.body.clone()
+            },
+        );
+
+        // What an older peer reads: the alias and the content, nothing else
+        let seen = |message: &MessagePayload| {
+            let json = serde_json::to_value(message).unwrap();
+            (json["sender_alias"].clone(), json["content"].clone())
+        };
+        assert_eq!(seen(&image), (json!("alice"), json!("[Image]")));
+        assert_eq!(seen(&file), (json!("alice"), json!("report.pdf")));
+        assert_eq!(seen(&captioned), (json!("alice"), json!("the numbers")));
+    }
+
+    #[test]
+    fn only_bare_web_addresses_are_links() {
+        let detected = |text: &str| {
+            let mut body = MessageBody::text(text.to_string());
+            body.detect_link();
+            body.link().map(str::to_string)
+        };
+
+        assert_eq!(
+            detected("https://example.com/a?b=c#d").as_deref(),
+            Some("https://example.com/a?b=c#d")
+        );
+        assert_eq!(
+            detected("  http://192.168.1.5:8080/\n").as_deref(),
+            Some("http://192.168.1.5:8080/")
+        );
+        assert_eq!(detected("see https://example.com"), None);
+        assert_eq!(detected("https://example.com and more"), None);
+        assert_eq!(detected("ftp://example.com/file"), None);
+        assert_eq!(detected("javascript:alert(1)"), None);
+        assert_eq!(detected("example.com"), None);
+        assert_eq!(detected(""), None);
+    }
+
+    #[test]
+    fn messages_need_the_parts_of_their_type() {
+        let png = InlineImage {
+            mime_type: "image/png".to_string(),
+            data: vec![0x89, b'P', b'N', b'G'],
+        };
+        let body = |kind| MessageBody {
+            kind,
+            ..MessageBody::default()
+        };
+
+        assert!(body(MessageKind::Image).validate().is_err());
+        assert!(MessageBody {
+            image: Some(png.clone()),
+            ..body(MessageKind::Image)
+        }
+        .validate()
+        .is_ok());
+        assert!(MessageBody {
+            image: Some(InlineImage {
+                mime_type: "text/plain".to_string(),
+                ..png.clone()
+            }),
+            ..body(MessageKind::Image)
+        }
+        .validate()
+        .is_err());
+
+        assert!(body(MessageKind::File).validate().is_err());
+        assert!(MessageBody {
+            file: Some(FileRef {
+                name: "report.pdf".to_string(),
+                size: 1024,
+                mime_type: None,
+            }),
+            thumbnail: Some(png.clone()),
+            ..body(MessageKind::File)
+        }
+        .validate()
+        .is_ok());
+
+        assert!(MessageBody {
+            content: "javascript:alert(1)".to_string(),
+            ..body(MessageKind::Url)
+        }
+        .validate()
+        .is_err());
+
+        assert!(MessageBody {
+            thumbnail: Some(InlineImage {
+                data: vec![0; MAX_THUMBNAIL_BYTES + 1],
+                ..png
+            }),
+            ..MessageBody::text("hi".to_string())
+        }
+        .validate()
+        .is_err());
+    }
+}
@@ -0,0 +1,211 @@
+use log::{error, info};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// How often the watcher re-reads the interface list
+const NETWORK_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct NetworkInterface {
+    pub name: String,
+    pub addresses: Vec<String>,
+}
+
+/// List the non-loopback interfaces of this machine with their addresses,
+/// sorted by name so snapshots can be compared directly.
+pub fn list_interfaces() -> Vec<NetworkInterface> {
+    let netifas = match local_ip_address::list_afinet_netifas() {
+        Ok(netifas) => netifas,
+        Err(e) => {
+            error!("Failed to list network interfaces: {}", e);
+            return Vec::new();
+        }
+    };
+
+    let mut by_name: BTreeMap<String, Vec<IpAddr>> = BTreeMap::new();
+    for (name, ip) in netifas {
+        if !ip.is_loopback() {
+            by_name.entry(name).or_default().push(ip);
+        }
+    }
+
+    by_name
+        .into_iter()
+        .map(|(name, mut ips)| {
+            ips.sort();
+            ips.dedup();
+            NetworkInterface {
+                name,
+                addresses: ips.iter().map(|ip| ip.to_string()).collect(),
+            }
+        })
+        .collect()
+}
+
+/// Addresses of the given interfaces, or of every interface when `allowed` is empty.
+pub fn interface_addresses(allowed: &[String]) -> Vec<IpAddr> {
+    list_interfaces()
+        .into_iter()
+        .filter(|iface| allowed.is_empty() || allowed.contains(&iface.name))
+        .flat_map(|iface| iface.addresses)
+        .filter_map(|addr| addr.parse().ok())
+        .collect()
+}
+
+/// Whether a connection arriving on local address `ip` should be served,
+/// given the addresses of the selected interfaces. Loopback is always
+/// allowed so the app can talk to itself.
+pub fn is_allowed_local_addr(ip: IpAddr, allowed: &[IpAddr]) -> bool {
+    // Dual-stack sockets report IPv4 clients as ::ffff:a.b.c.d
+    let ip = ip.to_canonical();
+    ip.is_loopback() || allowed.contains(&ip)
+}
+
+/// The addresses of the selected interfaces, for checking every incoming
+/// connection without listing the interfaces each time. They are read again
+/// when the selection changes or after `NETWORK_POLL_INTERVAL`, so address
+/// changes are picked up as quickly as the network watcher notices them.
+#[derive(Debug, Default)]
+pub struct AllowedAddresses {
+    interfaces: Vec<String>,
+    addresses: Vec<IpAddr>,
+    read_at: Option<Instant>,
+}
+
+impl AllowedAddresses {
+    /// Whether a connection arriving on local address `ip` should be served
+    /// with `interfaces` selected; with none selected, all are allowed.
+    pub fn allows(&mut self, ip: IpAddr, interfaces: &[String]) -> bool {
+        if interfaces.is_empty() {
+            return true;
+        }
+
+        let fresh = self
+            .read_at
+            .is_some_and(|read_at| read_at.elapsed() < NETWORK_POLL_INTERVAL);
+        if !fresh || self.interfaces != interfaces {
+            self.addresses = interface_addresses(interfaces);
+            self.interfaces = interfaces.to_vec();
+            self.read_at = Some(Instant::now());
+        }
+
+        is_allowed_local_addr(ip, &self.addresses)
+    }
+}
+
+/// The thread started by [`start_network_watcher`]. Stopping or dropping it
+/// ends the thread and waits for a running `on_change` to return.
+pub struct NetworkWatcher {
+    stop: Sender<()>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl NetworkWatcher {
+    /// Same as dropping it, for call sites where that reads better.
+    pub fn stop(self) {}
+}
+
+impl Drop for NetworkWatcher {
+    fn drop(&mut self) {
+        let _ = self.stop.send(());
+        let Some(thread) = self.thread.take() else {
+            return;
+        };
+        // Dropped from `on_change` itself, which returns to a loop that
+        // sees the stop right away
+        if thread.thread().id() == thread::current().id() {
+            return;
+        }
+        if thread.join().is_err() {
+            error!("Network watcher thread panicked");
+        }
+    }
+}
+
+/// Poll the interface list in the background and call `on_change` with the
+/// new list whenever an interface or address appears or disappears, until
+/// the returned watcher is stopped.
+pub fn start_network_watcher<F>(on_change: F) -> NetworkWatcher
+where
+    F: Fn(Vec<NetworkInterface>) + Send + 'static,
+{
+    let (stop, stopped) = channel();
+    let thread = thread::spawn(move || {
+        let mut previous = list_interfaces();
+        loop {
+            match stopped.recv_timeout(NETWORK_POLL_INTERVAL) {
+                Err(RecvTimeoutError::Timeout) => {}
+                Ok(()) | Err(RecvTimeoutError::Disconnected) => return,
+            }
+
+            let current = list_interfaces();
+            if current != previous {
+                info!(
+                    "Network interfaces changed: {:?} -> {:?}",
+                    previous, current
+                );
+                on_change(current.clone());
+                previous = current;
+            }
+        }
+    });
+
+    NetworkWatcher {
+        stop,
+        thread: Some(thread),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(addr: &str) -> IpAddr {
+        addr.parse().unwrap()
+    }
+
+    #[test]
+    fn only_selected_addresses_are_allowed() {
+        let allowed = [ip("192.168.1.20"), ip("fd00::20")];
+
+        assert!(is_allowed_local_addr(ip("192.168.1.20"), &allowed));
+        assert!(is_allowed_local_addr(ip("fd00::20"), &allowed));
+        assert!(!is_allowed_local_addr(ip("10.8.0.2"), &allowed));
+        assert!(!is_allowed_local_addr(ip("fd00::21"), &allowed));
+        // An IPv4 client on a dual-stack socket
+        assert!(is_allowed_local_addr(ip("::ffff:192.168.1.20"), &allowed));
+        assert!(!is_allowed_local_addr(ip("::ffff:10.8.0.2"), &allowed));
+    }
+
+    #[test]
+    fn loopback_is_always_allowed() {
+        assert!(is_allowed_local_addr(ip("127.0.0.1"), &[]));
+        assert!(is_allowed_local_addr(ip("::1"), &[]));
+        assert!(is_allowed_local_addr(ip("::ffff:127.0.0.1"), &[]));
+    }
+
+    #[test]
+    fn network_watcher_stops_without_waiting_for_a_poll() {
+        let watcher = start_network_watcher(|_| {});
+        let started = Instant::now();
+        watcher.stop();
+        assert!(started.elapsed() < NETWORK_POLL_INTERVAL);
+    }
+
+    #[test]
+    fn allowed_addresses_follow_the_selection() {
+        let mut allowed = AllowedAddresses::default();
+        assert!(allowed.allows(ip("10.8.0.2"), &[]));
+        assert!(allowed.read_at.is_none(), "interfaces were listed");
+
+        // An interface that doesn't exist has no addresses to allow
+        let missing = vec!["no-such-interface0".to_string()];
+        assert!(!allowed.allows(ip("10.8.0.2"), &missing));
+        assert!(allowed.allows(ip("127.0.0.1"), &missing));
+        assert_eq!(allowed.interfaces, missing);
+    }
+}
@@ -0,0 +1,168 @@
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+/// Minimum time between two `transfer-progress` events of one transfer
+const REPORT_INTERVAL: Duration = Duration::from_millis(100);
+/// How quickly the reported speed follows changes: after this long at a new
+/// rate, about two thirds of the difference is reflected
+const SPEED_TIME_CONSTANT: Duration = Duration::from_secs(2);
+
+/// Payload of `transfer-progress`, emitted while a file is sent or received.
+#[derive(Debug, Clone, Serialize)]
+pub struct ProgressPayload {
+    pub transfer_id: String,
+    pub current_bytes: u64,
+    pub total_bytes: Option<u64>,
+    /// Smoothed throughput, 0 until the first measurement
+    pub bytes_per_second: u64,
+    /// Estimated time left, if both the size and the speed are known
+    pub eta_ms: Option<u64>,
+    pub elapsed_ms: u64,
+    /// Set when the same file goes to several peers at once
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub peer: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    Send,
+    Receive,
+}
+
+/// Payload of `transfer-summary`, emitted once a file has been sent or
+/// received completely, just before the send returns or
+/// `file-receive-complete` is emitted.
+#[derive(Debug, Clone, Serialize)]
+pub struct TransferSummary {
+    pub transfer_id: String,
+    pub direction: Direction,
+    pub bytes: u64,
+    pub elapsed_ms: u64,
+    pub average_bytes_per_second: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub peer: Option<String>,
+}
+
+/// Measures one transfer: how far it got, how fast it is currently going
+/// (an exponentially weighted moving average, so a single slow chunk doesn't
+/// make the ETA jump) and how long it has taken so far. Senders and the
+/// receiver both report through this, so every consumer sees the same
+/// numbers.
+#[derive(Debug, Clone)]
+pub struct TransferMeter {
+    transfer_id: String,
+    peer: Option<String>,
+    total_bytes: Option<u64>,
+    current_bytes: u64,
+    started: Instant,
+    /// When and at which byte count the speed was last measured
+    sampled: (Instant, u64),
+    speed: Option<f64>,
+}
+
+impl TransferMeter {
+    pub fn new(transfer_id: String, peer: Option<String>, total_bytes: Option<u64>) -> Self {
+        let now = Instant::now();
+        Self {
+            transfer_id,
+            peer,
+            total_bytes,
+            current_bytes: 0,
+            started: now,
+            sampled: (now, 0),
+            speed: None,
+        }
+    }
+
+    pub fn transfer_id(&self) -> &str {
+        &self.transfer_id
+    }
+
+    pub fn current_bytes(&self) -> u64 {
+        self.current_bytes
+    }
+
+    /// Continue counting from `offset`, e.g. when a retry resumes where the
+    /// peer's copy stopped. The time spent in between doesn't count as slow.
+    pub fn seek(&mut self, offset: u64) {
+        self.current_bytes = offset;
+        self.sampled = (Instant::now(), offset);
+    }
+
+    /// Count `bytes` more and return the progress to report, if the last
+    /// report is old enough.
+    pub fn advance(&mut self, bytes: u64) -> Option<ProgressPayload> {
+        self.current_bytes += bytes;
+
+        let now = Instant::now();
+        let (sampled_at, sampled_bytes) = self.sampled;
+        let interval = now.duration_since(sampled_at);
+        if interval < REPORT_INTERVAL {
+            return None;
+        }
+
+        let rate = self.current_bytes.saturating_sub(sampled_bytes) as f64 / interval.as_secs_f64();
+        // Weigh the new rate by how long it was measured over, since chunks
+        // don't arrive at a steady pace
+        let weight = 1.0 - (-interval.as_secs_f64() / SPEED_TIME_CONSTANT.as_secs_f64()).exp();
+        self.speed = Some(match self.speed {
+            Some(speed) => speed + weight * (rate - speed),
+            None => rate,
+        });
+        self.sampled = (now, self.current_bytes);
+
+        Some(self.progress())
+    }
+
+    /// The progress as it stands.
+    pub fn progress(&self) -> ProgressPayload {
+        let speed = self.speed.unwrap_or(0.0);
+        let eta_ms = match self.total_bytes {
+            Some(total) if speed > 0.0 => {
+                let remaining = total.saturating_sub(self.current_bytes);
+                Some((remaining as f64 / speed * 1000.0) as u64)
+            }
+            _ => None,
+        };
+
+        ProgressPayload {
+            transfer_id: self.transfer_id.clone(),
+            current_bytes: self.current_bytes,
+            total_bytes: self.total_bytes,
+            bytes_per_second: speed as u64,
+            eta_ms,
+            elapsed_ms: self.started.elapsed().as_millis() as u64,
+            peer: self.peer.clone(),
+        }
+    }
+
+    /// Mark the transfer as done, returning the final 100% progress.
+    pub fn complete(&mut self) -> ProgressPayload {
+        self.total_bytes = Some(self.current_bytes);
+        ProgressPayload {
+            eta_ms: Some(0),
+            ..self.progress()
+        }
+    }
+
+    /// Totals for `transfer-summary`, averaged over the whole transfer
+    /// including any retries.
+    pub fn summary(&self, direction: Direction) -> TransferSummary {
+        let elapsed = self.started.elapsed();
+        let average = if elapsed.is_zero() {
+            0.0
+        } else {
+            self.current_bytes as f64 / elapsed.as_secs_f64()
+        };
+
+        TransferSummary {
+            transfer_id: self.transfer_id.clone(),
+            direction,
+            bytes: self.current_bytes,
+            elapsed_ms: elapsed.as_millis() as u64,
+            average_bytes_per_second: average as u64,
+            peer: self.peer.clone(),
+        }
+    }
+}
@@ -0,0 +1,481 @@
+use crate::error::Error;
+use crate::events::EventSink;
+use futures::future::BoxFuture;
+use log::{debug, error};
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use tokio::runtime::Handle;
+use tokio::sync::oneshot;
+
+/// How many queued transfers may run at the same time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueueLimits {
+    /// Across all peers
+    pub max_concurrent: usize,
+    /// To any single peer, so its user isn't asked about a pile of files at once
+    pub max_per_peer: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QueueStatus {
+    /// Waiting for a free slot
+    Queued,
+    /// Held back until resumed
+    Paused,
+    Running,
+}
+
+/// A queued or running transfer as reported in `queue-update` events.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueueItem {
+    pub id: String,
+    /// Key of the peer the transfer goes to; limits are counted per key
+    pub peer: String,
+    pub file_name: String,
+    /// Higher runs first; equal priorities run in the order they were queued
+    pub priority: i32,
+    pub status: QueueStatus,
+}
+
+struct Entry {
+    item: QueueItem,
+    seq: u64,
+    job: Option<BoxFuture<'static, Result<(), Error>>>,
+    done: Option<oneshot::Sender<Result<(), Error>>>,
+}
+
+struct QueueState {
+    limits: QueueLimits,
+    entries: Vec<Entry>,
+    running_per_peer: HashMap<String, usize>,
+    running: usize,
+    next_seq: u64,
+}
+
+/// Runs transfers a few at a time instead of all at once, so that sending
+/// many files doesn't split the bandwidth between all of them and flood the
+/// receiver with confirmations. Emits `queue-update` with every queued and
+/// running item whenever something changes.
+#[derive(Clone)]
+pub struct TransferQueue {
+    state: Arc<Mutex<QueueState>>,
+    events: Arc<dyn EventSink>,
+    /// Runs the transfers; scheduling also happens outside of it, e.g. when
+    /// a paused transfer is resumed from a command
+    runtime: Handle,
+}
+
+impl TransferQueue {
+    pub fn new(events: Arc<dyn EventSink>, runtime: Handle, limits: QueueLimits) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(QueueState {
+                limits,
+                entries: Vec::new(),
+                running_per_peer: HashMap::new(),
+                running: 0,
+                next_seq: 0,
+            })),
+            events,
+            runtime,
+        }
+    }
+
+    /// Queue `job` for `peer` and wait until it has run. The job isn't
+    /// polled before its turn, so it should do all its work, including
+    /// opening files, inside the future.
+    pub async fn run<F>(&self, peer: String, file_name: String, job: F) -> Result<(), Error>
+    where
+        F: Future<Output = Result<(), Error>> + Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        {
+            let mut state = self.state.lock().unwrap();
+            let seq = state.next_seq;
+            state.next_seq += 1;
+            state.entries.push(Entry {
+                item: QueueItem {
+                    id: uuid::Uuid::new_v4().to_string(),
+                    peer,
+                    file_name,
+                    priority: 0,
+                    status: QueueStatus::Queued,
+                },
+                seq,
+                job: Some(Box::pin(job)),
+                done: Some(tx),
+            });
+        }
+        self.schedule();
+
+        rx.await
+            .unwrap_or_else(|_| Err(Error::Cancelled("Transfer was dropped".to_string())))
+    }
+
+    /// Queued and running transfers, in the order they will run.
+    pub fn items(&self) -> Vec<QueueItem> {
+        self.state.lock().unwrap().items()
+    }
+
+    pub fn set_limits(&self, limits: QueueLimits) {
+        self.state.lock().unwrap().limits = limits;
+        self.schedule();
+    }
+
+    pub fn set_priority(&self, id: &str, priority: i32) -> Result<(), Error> {
+        self.update(id, |item| {
+            item.priority = priority;
+            Ok(())
+        })
+    }
+
+    /// Hold a queued transfer back until [`TransferQueue::resume`].
+    pub fn pause(&self, id: &str) -> Result<(), Error> {
+        self.update(id, |item| match item.status {
+            QueueStatus::Queued | QueueStatus::Paused => {
+                item.status = QueueStatus::Paused;
+                Ok(())
+            }
+            QueueStatus::Running => Err(Error::InvalidInput(
+                "Only queued transfers can be paused".to_string(),
+            )),
+        })
+    }
+
+    pub fn resume(&self, id: &str) -> Result<(), Error> {
+        self.update(id, |item| {
+            if item.status == QueueStatus::Paused {
+                item.status = QueueStatus::Queued;
+            }
+            Ok(())
+        })
+    }
+
+    fn update(
+        &self,
+        id: &str,
+        change: impl FnOnce(&mut QueueItem) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        {
+            let mut state = self.state.lock().unwrap();
+            let entry = state
+                .entries
+                .iter_mut()
+                .find(|entry| entry.item.id == id)
+                .ok_or_else(|| Error::NotFound(format!("Transfer {} is not queued", id)))?;
+            change(&mut entry.item)?;
+        }
+        self.schedule();
+        Ok(())
+    }
+
+    /// Start every queued transfer that fits within the limits, best first,
+    /// then report the new state.
+    fn schedule(&self) {
+        let mut state = self.state.lock().unwrap();
+        let mut starting = Vec::new();
+
+        loop {
+            let QueueState {
+                limits,
+                entries,
+                running_per_peer,
+                running,
+                ..
+            } = &mut *state;
+            if *running >= limits.max_concurrent.max(1) {
+                break;
+            }
+
+            let next = entries
+                .iter_mut()
+                .filter(|entry| entry.item.status == QueueStatus::Queued)
+                .filter(|entry| {
+                    running_per_peer.get(&entry.item.peer).copied().unwrap_or(0)
+                        < limits.max_per_peer.max(1)
+                })
+                .min_by_key(|entry| run_order(entry));
+            let Some(entry) = next else {
+                break;
+            };
+
+            entry.item.status = QueueStatus::Running;
+            *running += 1;
+            *running_per_peer.entry(entry.item.peer.clone()).or_default() += 1;
+
+            let id = entry.item.id.clone();
+            let job = entry.job.take().expect("queued transfer without a job");
+            debug!(file = entry.item.file_name.as_str(); "Starting queued transfer");
+
+            starting.push((id, job));
+        }
+
+        let items = state.items();
+        drop(state);
+
+        // Outside the lock: a job dropped without running gives up its slot
+        // right away, which takes the lock again
+        for (id, job) in starting {
+            let slot = Slot {
+                queue: self.clone(),
+                id,
+                result: None,
+            };
+            self.runtime.spawn(async move {
+                let mut slot = slot;
+                slot.result = Some(job.await);
+            });
+        }
+
+        if let Err(e) = self.events.emit("queue-update", items) {
+            error!("Failed to emit queue-update: {}", e);
+        }
+    }
+
+    fn finish(&self, id: &str, result: Result<(), Error>) {
+        {
+            let mut state = self.state.lock().unwrap();
+            let Some(index) = state.entries.iter().position(|entry| entry.item.id == id) else {
+                return;
+            };
+            let mut entry = state.entries.remove(index);
+
+            state.running -= 1;
+            if let Some(count) = state.running_per_peer.get_mut(&entry.item.peer) {
+                *count -= 1;
+                if *count == 0 {
+                    state.running_per_peer.remove(&entry.item.peer);
+                }
+            }
+
+            if let Some(done) = entry.done.take() {
+                // The caller may have given up waiting
+                let _ = done.send(result);
+            }
+        }
+        self.schedule();
+    }
+}
+
+/// A running transfer's place in the queue, given up when dropped: after
+/// the job finishes, but also if it panics or its task is cancelled, so the
+/// slot doesn't stay taken forever.
+struct Slot {
+    queue: TransferQueue,
+    id: String,
+    result: Option<Result<(), Error>>,
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        let result = self.result.take().unwrap_or_else(|| {
+            Err(Error::Internal(
+                "The transfer stopped unexpectedly".to_string(),
+            ))
+        });
+        self.queue.finish(&self.id, result);
+    }
+}
+
+impl QueueState {
+    fn items(&self) -> Vec<QueueItem> {
+        let mut entries: Vec<&Entry> = self.entries.iter().collect();
+        entries.sort_by_key(|entry| run_order(entry));
+        entries
+            .into_iter()
+            .map(|entry| entry.item.clone())
+            .collect()
+    }
+}
+
+/// Sort key putting running transfers first, then the rest by descending
+/// priority and queue order.
+fn run_order(entry: &Entry) -> (bool, Reverse<i32>, u64) {
+    (
+        entry.item.status != QueueStatus::Running,
+        Reverse(entry.item.priority),
+        entry.seq,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{ChannelEventSink, Event};
+    use std::time::Duration;
+    use tokio::sync::mpsc::UnboundedReceiver;
+
+    type Started = Arc<Mutex<Vec<String>>>;
+
+    fn queue(
+        max_concurrent: usize,
+        max_per_peer: usize,
+    ) -> (TransferQueue, UnboundedReceiver<Event>) {
+        let (sink, events) = ChannelEventSink::new();
+        let limits = QueueLimits {
+            max_concurrent,
+            max_per_peer,
+        };
+        (
+            TransferQueue::new(Arc::new(sink), Handle::current(), limits),
+            events,
+        )
+    }
+
+    /// Queue a transfer called `name` that notes in `started` when it runs
+    /// and then waits until the returned sender fires.
+    async fn submit(
+        queue: &TransferQueue,
+        peer: &str,
+        name: &str,
+        started: &Started,
+    ) -> oneshot::Sender<()> {
+        let (release, released) = oneshot::channel::<()>();
+        let (queued, name, started) = (queue.items().len(), name.to_string(), started.clone());
+        let job = {
+            let name = name.clone();
+            async move {
+                started.lock().unwrap().push(name);
+                let _ = released.await;
+                Ok(())
+            }
+        };
+        let (queue_ref, peer) = (queue.clone(), peer.to_string());
+        tokio::spawn(async move { queue_ref.run(peer, name, job).await });
+
+        wait_until(|| queue.items().len() > queued).await;
+        release
+    }
+
+    async fn wait_until(check: impl Fn() -> bool) {
+        for _ in 0..100 {
+            if check() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("timed out waiting for the queue");
+    }
+
+    fn find(queue: &TransferQueue, name: &str) -> QueueItem {
+        queue
+            .items()
+            .into_iter()
+            .find(|item| item.file_name == name)
+            .unwrap()
+    }
+
+    fn names(queue: &TransferQueue) -> Vec<String> {
+        queue
+            .items()
+            .into_iter()
+            .map(|item| item.file_name)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn higher_priority_runs_first() {
+        let (queue, _events) = queue(1, 1);
+        let started = Started::default();
+
+        let first = submit(&queue, "a", "first", &started).await;
+        let _second = submit(&queue, "b", "second", &started).await;
+        let _third = submit(&queue, "c", "third", &started).await;
+        assert_eq!(names(&queue), ["first", "second", "third"]);
+
+        queue.set_priority(&find(&queue, "third").id, 5).unwrap();
+        assert_eq!(names(&queue), ["first", "third", "second"]);
+
+        first.send(()).unwrap();
+        wait_until(|| started.lock().unwrap().len() == 2).await;
+        assert_eq!(*started.lock().unwrap(), ["first", "third"]);
+        assert_eq!(find(&queue, "second").status, QueueStatus::Queued);
+    }
+
+    #[tokio::test]
+    async fn paused_transfers_wait_until_resumed() {
+        let (queue, _events) = queue(1, 1);
+        let started = Started::default();
+
+        let first = submit(&queue, "a", "first", &started).await;
+        let _second = submit(&queue, "b", "second", &started).await;
+        let running = find(&queue, "first").id;
+        let paused = find(&queue, "second").id;
+
+        assert!(matches!(queue.pause(&running), Err(Error::InvalidInput(_))));
+        queue.pause(&paused).unwrap();
+        assert!(matches!(queue.pause("unknown"), Err(Error::NotFound(_))));
+
+        // A free slot doesn't start it
+        first.send(()).unwrap();
+        wait_until(|| queue.items().len() == 1).await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(find(&queue, "second").status, QueueStatus::Paused);
+        assert_eq!(started.lock().unwrap().len(), 1);
+
+        queue.resume(&paused).unwrap();
+        wait_until(|| started.lock().unwrap().len() == 2).await;
+        assert_eq!(find(&queue, "second").status, QueueStatus::Running);
+    }
+
+    #[tokio::test]
+    async fn each_peer_gets_a_limited_share() {
+        let (queue, _events) = queue(3, 1);
+        let started = Started::default();
+
+        let x1 = submit(&queue, "x", "x1", &started).await;
+        let _x2 = submit(&queue, "x", "x2", &started).await;
+        let _y1 = submit(&queue, "y", "y1", &started).await;
+
+        // y isn't held up by x's second file
+        wait_until(|| started.lock().unwrap().len() == 2).await;
+        assert_eq!(*started.lock().unwrap(), ["x1", "y1"]);
+        assert_eq!(find(&queue, "x2").status, QueueStatus::Queued);
+
+        x1.send(()).unwrap();
+        wait_until(|| started.lock().unwrap().len() == 3).await;
+        assert_eq!(started.lock().unwrap()[2], "x2");
+    }
+
+    fn broken_transfer() -> Result<(), Error> {
+        panic!("broken transfer")
+    }
+
+    #[tokio::test]
+    async fn panicking_transfers_free_their_slot() {
+        let (queue, _events) = queue(1, 1);
+        let started = Started::default();
+
+        let result = queue
+            .run("a".to_string(), "broken".to_string(), async {
+                broken_transfer()
+            })
+            .await;
+        assert!(matches!(result, Err(Error::Internal(_))), "{:?}", result);
+        assert!(queue.items().is_empty());
+
+        // Neither the peer nor the queue is stuck
+        let _next = submit(&queue, "a", "next", &started).await;
+        wait_until(|| started.lock().unwrap().len() == 1).await;
+        assert_eq!(find(&queue, "next").status, QueueStatus::Running);
+    }
+
+    #[tokio::test]
+    async fn raised_limits_start_waiting_transfers() {
+        let (queue, _events) = queue(1, 1);
+        let started = Started::default();
+
+        let _a = submit(&queue, "a", "a", &started).await;
+        let _b = submit(&queue, "b", "b", &started).await;
+        assert_eq!(started.lock().unwrap().len(), 1);
+
+        queue.set_limits(QueueLimits {
+            max_concurrent: 2,
+            max_per_peer: 1,
+        });
+        wait_until(|| started.lock().unwrap().len() == 2).await;
+    }
+}
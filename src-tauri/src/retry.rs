@@ -0,0 +1,137 @@
+use crate::error::Error;
+use crate::events::EventSink;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How often and how patiently a failed send is tried again. Only network
+/// errors are retried, timed out connections included; a rejection or an
+/// unanswered confirmation is final.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// Total attempts including the first; 1 disables retrying
+    pub max_attempts: u32,
+    /// Wait before the first retry
+    pub initial_delay_ms: u64,
+    /// Upper bound for the wait between attempts
+    pub max_delay_ms: u64,
+    /// Factor the wait grows by after every attempt
+    pub multiplier: f64,
+    /// An upload attempt fails once the peer has taken none of it for this
+    /// long. Not a limit on the whole upload, which may take as long as the
+    /// rate allows. Must outlast the peer's confirmation prompt, during which
+    /// it reads nothing, and the time it needs to read what is already on
+    /// its way at its download limit.
+    pub idle_timeout_ms: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 4,
+            initial_delay_ms: 1000,
+            max_delay_ms: 30_000,
+            multiplier: 2.0,
+            idle_timeout_ms: 120_000,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Try once and give up on the first error.
+    pub fn never() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Self::default()
+        }
+    }
+
+    /// Wait after failed attempt number `attempt` (starting at 1).
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = self
+            .multiplier
+            .max(1.0)
+            .powi(attempt.saturating_sub(1) as i32);
+        let delay = (self.initial_delay_ms as f64 * factor).min(self.max_delay_ms as f64);
+        Duration::from_millis(delay as u64)
+    }
+
+    pub fn idle_timeout(&self) -> Duration {
+        Duration::from_millis(self.idle_timeout_ms)
+    }
+}
+
+/// Whether trying the same request again could succeed. Socket timeouts
+/// arrive here as [`Error::Network`], see [`Error::request`].
+pub fn is_retryable(error: &Error) -> bool {
+    matches!(error, Error::Network(_))
+}
+
+/// Which send is being retried, as reported in `transfer-retrying`.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct RetriedTransfer<'a> {
+    /// Unique to this send, so sends of the same file to several peers, or
+    /// of two messages, can be told apart
+    pub transfer_id: &'a str,
+    /// The file name, or `message` or `clipboard`
+    pub name: &'a str,
+    /// `ip:port` of the peer
+    pub peer: &'a str,
+}
+
+#[derive(Serialize)]
+struct RetryingPayload<'a> {
+    #[serde(flatten)]
+    transfer: RetriedTransfer<'a>,
+    /// The attempt about to start, starting at 2
+    attempt: u32,
+    max_attempts: u32,
+    delay_ms: u64,
+    error: &'a Error,
+}
+
+/// Run `attempt` until it succeeds, fails with an error that isn't worth
+/// retrying, or `policy` runs out of attempts, emitting `transfer-retrying`
+/// before each retry. The closure gets the attempt number, starting at 1.
+pub async fn with_retry<T, F, Fut>(
+    policy: &RetryPolicy,
+    events: &Arc<dyn EventSink>,
+    transfer: RetriedTransfer<'_>,
+    mut attempt: F,
+) -> Result<T, Error>
+where
+    F: FnMut(u32) -> Fut,
+    Fut: Future<Output = Result<T, Error>>,
+{
+    let mut number = 1;
+    loop {
+        let error = match attempt(number).await {
+            Ok(value) => return Ok(value),
+            Err(e) => e,
+        };
+        if number >= policy.max_attempts.max(1) || !is_retryable(&error) {
+            return Err(error);
+        }
+
+        let delay = policy.delay(number);
+        number += 1;
+        warn!(
+            transfer_id = transfer.transfer_id, peer = transfer.peer;
+            "Attempt {} of {} in {:?} after: {}", number, policy.max_attempts, delay, error
+        );
+        let _ = events.emit(
+            "transfer-retrying",
+            RetryingPayload {
+                transfer,
+                attempt: number,
+                max_attempts: policy.max_attempts,
+                delay_ms: delay.as_millis() as u64,
+                error: &error,
+            },
+        );
+        tokio::time::sleep(delay).await;
+    }
+}
@@ -1,113 +1,410 @@
-use crate::PendingTransfers;
+use crate::bandwidth::Bandwidth;
+use crate::clipboard::{
+    Clipboard, ClipboardContent, ClipboardMessage, ClipboardSyncMessage, LastClipboard,
+    MAX_CLIPBOARD_BYTES,
+};
+use crate::config::AppConfig;
+use crate::confirmation::{ConfirmationProvider, TransferRequest};
+use crate::conflict::{self, ConflictResolution, ConflictStrategy, FileConflict};
+use crate::content;
+use crate::discovery::PeerDirectory;
+use crate::error::Error;
+use crate::events::EventSink;
+use crate::filename;
+use crate::message::{LinkOpener, MessagePayload, MAX_MESSAGE_BYTES};
+use crate::network::AllowedAddresses;
+use crate::progress::{Direction, TransferMeter};
 use axum::{
-    extract::{DefaultBodyLimit, Multipart, State},
+    extract::{
+        connect_info::Connected, ConnectInfo, DefaultBodyLimit, Multipart, Path as UrlPath, State,
+    },
+    http::StatusCode,
     routing::{get, post},
+    serve::{IncomingStream, Listener},
     Json, Router,
 };
+use log::{debug, error, info, warn};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use std::net::SocketAddr;
-use std::path::{Path, PathBuf};
-use std::time::Instant;
-use tauri::Emitter;
-use tauri::{AppHandle, Manager}; // Import Manager for path()
+use socket2::{Domain, Protocol, Socket, Type};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tokio::fs::{self};
-use tokio::sync::oneshot;
+use tokio::net::{TcpListener, TcpStream};
 use urlencoding::decode;
-use uuid::Uuid;
 
-#[cfg(target_os = "android")]
+#[cfg(all(target_os = "android", feature = "gui"))]
+use tauri::AppHandle;
+#[cfg(all(target_os = "android", feature = "gui"))]
 use tauri_plugin_android_fs::{AndroidFsExt, PublicGeneralPurposeDir};
 
+/// Version of the HTTP protocol spoken on `/upload`, `/message` and `/info`.
+pub const PROTOCOL_VERSION: &str = "1.0";
+
+/// Optional protocol features this build understands, reported by `/info`.
+pub const CAPABILITIES: &[&str] = &[
+    "upload",
+    "message",
+    "info",
+    "resume",
+    "clipboard",
+    "clipboard_sync",
+    "rich_message",
+];
+
+/// How long a sender is kept waiting for the user to accept a file.
+pub const CONFIRMATION_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How long the received part of an interrupted upload is kept for the
+/// sender to resume.
+const PARTIAL_UPLOAD_TTL: Duration = Duration::from_secs(10 * 60);
+/// Most bytes kept across all interrupted uploads. They are held in memory,
+/// so older ones are dropped to make room for newer ones.
+const MAX_PARTIAL_UPLOAD_BYTES: usize = 256 * 1024 * 1024;
+
+/// How long the IDs of delivered messages and clipboard pushes are kept to
+/// recognize a sender's retry.
+const DELIVERED_TTL: Duration = Duration::from_secs(10 * 60);
+/// Most delivery IDs kept; the oldest are forgotten first.
+const MAX_DELIVERED_IDS: usize = 4096;
+
+/// Everything the HTTP server needs; shared by all request handlers.
 #[derive(Clone)]
-struct ServerState {
-    app_handle: AppHandle,
-    download_dir: PathBuf,
-    pending_transfers: PendingTransfers,
+pub struct ServerState {
+    pub events: Arc<dyn EventSink>,
+    /// Decides whether incoming files are accepted
+    pub confirmation: Arc<dyn ConfirmationProvider>,
+    /// How long to wait for `confirmation` before giving up
+    pub confirmation_timeout: Duration,
+    pub download_dir: PathBuf,
+    pub config: Arc<Mutex<AppConfig>>,
+    /// Interrupted uploads waiting for the sender to resume them
+    pub partial_uploads: PartialUploads,
+    /// Messages and clipboard pushes already handled, so retries aren't
+    /// handled twice
+    pub delivered: Delivered,
+    /// Download limits, applied while reading uploads
+    pub bandwidth: Bandwidth,
+    /// Where received clipboard contents go in receive-to-clipboard mode;
+    /// without one they are only reported
+    pub clipboard: Option<Arc<dyn Clipboard>>,
+    /// Shared with the clipboard sync watcher, so content written from a
+    /// peer isn't sent back
+    pub last_clipboard: LastClipboard,
+    /// Opens links from devices in `open_links_from`; without one they are
+    /// only shown
+    pub link_opener: Option<Arc<dyn LinkOpener>>,
+    /// Where discovered devices are; links are only opened and the clipboard
+    /// only written or synced when a request comes from where its device ID
+    /// was found
+    pub peers: Arc<dyn PeerDirectory>,
+    /// Received files are written through MediaStore on Android
+    #[cfg(all(target_os = "android", feature = "gui"))]
+    pub app_handle: AppHandle,
+}
+
+/// Self-description returned by `GET /info`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DeviceInfo {
+    pub alias: String,
+    pub device_id: String,
+    pub device_type: String,
+    pub app_version: String,
+    pub protocol_version: String,
+    /// Port the server listens on, for addresses entered without one
+    pub port: u16,
+    pub capabilities: Vec<String>,
 }
 
-#[derive(Serialize, Clone)]
-struct FileTransferRequest {
-    transfer_id: String,
+/// The part of an accepted upload received before the connection broke.
+struct PartialUpload {
+    /// Only the sender may resume it, since resuming skips confirmation
+    sender: IpAddr,
     file_name: String,
-    file_size: Option<u64>,
+    data: Vec<u8>,
+    /// Keeps measuring across the interruption, so the summary covers it all
+    meter: TransferMeter,
+    updated: Instant,
 }
 
-#[derive(Serialize, Clone)]
-struct ProgressPayload {
-    transfer_id: String,
-    current_bytes: u64,
-    total_bytes: Option<u64>,
+impl PartialUpload {
+    fn is_from(&self, sender: IpAddr) -> bool {
+        self.sender.to_canonical() == sender.to_canonical()
+            && self.updated.elapsed() < PARTIAL_UPLOAD_TTL
+    }
 }
 
-#[derive(Deserialize, Serialize, Clone)]
-struct MessagePayload {
-    sender_alias: String,
-    content: String,
+/// Interrupted uploads by the `resume_key` their sender chose. The same
+/// sender retrying with that key and an `offset` equal to what was received
+/// continues the upload without being confirmed again.
+#[derive(Clone)]
+pub struct PartialUploads {
+    uploads: Arc<Mutex<HashMap<String, PartialUpload>>>,
+    max_bytes: usize,
 }
 
-pub async fn start_server(app: AppHandle, port: u16, pending_transfers: PendingTransfers) {
-    // Get the proper Downloads directory for the platform
-    let download_dir = if cfg!(target_os = "android") {
-        // On Android, use the public Downloads directory
-        // This path is standard on Android
-        PathBuf::from("/storage/emulated/0/Downloads")
-    } else if cfg!(target_os = "windows") {
-        // On Windows, use the user's Downloads folder
-        app.path().download_dir().unwrap_or_else(|_| {
-            // Fallback: try to construct the path manually
-            if let Ok(user_profile) = std::env::var("USERPROFILE") {
-                PathBuf::from(user_profile).join("Downloads")
-            } else {
-                PathBuf::from("downloads")
+impl Default for PartialUploads {
+    fn default() -> Self {
+        Self::with_limit(MAX_PARTIAL_UPLOAD_BYTES)
+    }
+}
+
+impl PartialUploads {
+    /// Keep at most `max_bytes` across all interrupted uploads.
+    pub fn with_limit(max_bytes: usize) -> Self {
+        Self {
+            uploads: Arc::default(),
+            max_bytes,
+        }
+    }
+
+    /// Keep `upload` for its sender, dropping the oldest ones if they
+    /// don't all fit. Returns whether it was kept.
+    fn insert(&self, key: String, upload: PartialUpload) -> bool {
+        let mut uploads = self.uploads.lock().unwrap();
+        uploads.retain(|_, upload| upload.updated.elapsed() < PARTIAL_UPLOAD_TTL);
+        uploads.remove(&key);
+        if upload.data.len() > self.max_bytes {
+            return false;
+        }
+
+        let mut kept: usize = uploads.values().map(|upload| upload.data.len()).sum();
+        while kept + upload.data.len() > self.max_bytes {
+            let Some(oldest) = uploads
+                .iter()
+                .min_by_key(|(_, upload)| upload.updated)
+                .map(|(key, _)| key.clone())
+            else {
+                break;
+            };
+            let dropped = uploads.remove(&oldest).unwrap();
+            warn!(
+                transfer_id = dropped.meter.transfer_id();
+                "Dropping interrupted upload to make room for a newer one"
+            );
+            kept -= dropped.data.len();
+        }
+        uploads.insert(key, upload);
+        true
+    }
+
+    fn take(&self, key: &str, sender: IpAddr) -> Option<PartialUpload> {
+        let mut uploads = self.uploads.lock().unwrap();
+        if !uploads.get(key)?.is_from(sender) {
+            return None;
+        }
+        uploads.remove(key)
+    }
+
+    fn received(&self, key: &str, sender: IpAddr) -> Option<u64> {
+        self.uploads
+            .lock()
+            .unwrap()
+            .get(key)
+            .filter(|upload| upload.is_from(sender))
+            .map(|upload| upload.data.len() as u64)
+    }
+}
+
+/// IDs of messages and clipboard pushes already handled, by sender. A sender
+/// whose response got lost retries with the same ID, which is then answered
+/// without handling the request again.
+#[derive(Clone, Default)]
+pub struct Delivered(Arc<Mutex<HashMap<(IpAddr, String), Instant>>>);
+
+impl Delivered {
+    /// Remember that `sender` delivered `id`. Returns whether this is the
+    /// first time.
+    fn first_delivery(&self, sender: IpAddr, id: &str) -> bool {
+        let mut delivered = self.0.lock().unwrap();
+        delivered.retain(|_, at| at.elapsed() < DELIVERED_TTL);
+        if delivered.len() >= MAX_DELIVERED_IDS {
+            if let Some(oldest) = delivered
+                .iter()
+                .min_by_key(|(_, at)| **at)
+                .map(|(key, _)| key.clone())
+            {
+                delivered.remove(&oldest);
             }
-        })
-    } else {
-        // On other platforms (Linux, macOS), use the system download directory
-        app.path()
-            .download_dir()
-            .unwrap_or_else(|_| PathBuf::from("downloads"))
-    };
+        }
+        delivered
+            .insert((sender, id.to_string()), Instant::now())
+            .is_none()
+    }
+}
+
+/// Response of `GET /upload/{resume_key}`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct UploadStatus {
+    /// Bytes received so far; the sender continues from here
+    pub received: u64,
+}
+
+pub async fn start_server(state: ServerState, port: u16) -> Result<(), Error> {
+    let listener = bind(port).await?;
+    serve(state, listener).await
+}
+
+/// Listen on `port` on every address, IPv4 and IPv6 alike. Peers advertise
+/// addresses of both kinds, so one dual-stack `[::]` socket serves them all;
+/// where the system has no IPv6, only IPv4 is served.
+pub async fn bind(port: u16) -> Result<TcpListener, Error> {
+    let addr = SocketAddr::from((Ipv6Addr::UNSPECIFIED, port));
+    match bind_dual_stack(addr) {
+        Ok(listener) => {
+            info!("Server listening on {}", addr);
+            return Ok(listener);
+        }
+        Err(e) => warn!("Failed to bind {}, serving IPv4 only: {}", addr, e),
+    }
+
+    let addr = SocketAddr::from((Ipv4Addr::UNSPECIFIED, port));
+    info!("Server listening on {}", addr);
+    TcpListener::bind(addr)
+        .await
+        .map_err(|e| Error::io(&format!("Failed to bind {}", addr), e))
+}
 
+fn bind_dual_stack(addr: SocketAddr) -> std::io::Result<TcpListener> {
+    let socket = Socket::new(Domain::IPV6, Type::STREAM, Some(Protocol::TCP))?;
+    // Windows and some BSDs only accept IPv6 on IPv6 sockets by default
+    socket.set_only_v6(false)?;
+    // Like `TcpListener::bind`, so a restart doesn't wait for TIME_WAIT
+    #[cfg(not(windows))]
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    TcpListener::from_std(socket.into())
+}
+
+/// Serve requests on an already bound listener, e.g. one on an ephemeral port.
+pub async fn serve(state: ServerState, listener: TcpListener) -> Result<(), Error> {
     // Ensure download directory exists
-    if !download_dir.exists() {
-        if let Err(e) = fs::create_dir_all(&download_dir).await {
-            eprintln!("Failed to create download directory: {}", e);
+    if !state.download_dir.exists() {
+        if let Err(e) = fs::create_dir_all(&state.download_dir).await {
+            error!("Failed to create download directory: {}", e);
         }
     }
 
-    eprintln!("Download directory: {:?}", download_dir);
+    info!(dir:? = state.download_dir; "Download directory");
 
-    let state = ServerState {
-        app_handle: app.clone(),
-        download_dir,
-        pending_transfers,
+    let listener = InterfaceFilteredListener {
+        inner: listener,
+        config: state.config.clone(),
+        allowed: AllowedAddresses::default(),
     };
 
     let app_router = Router::new()
         .route("/upload", post(upload_handler))
-        .route("/message", post(message_handler))
+        .route("/upload/{resume_key}", get(upload_status_handler))
+        .route(
+            "/message",
+            post(message_handler).layer(DefaultBodyLimit::max(MAX_MESSAGE_BYTES)),
+        )
+        .route(
+            "/clipboard",
+            // Base64 makes images a third larger on the way
+            post(clipboard_handler)
+                .layer(DefaultBodyLimit::max(MAX_CLIPBOARD_BYTES / 3 * 4 + 1024)),
+        )
+        .route(
+            "/clipboard/sync",
+            post(clipboard_sync_handler)
+                .layer(DefaultBodyLimit::max(MAX_CLIPBOARD_BYTES / 3 * 4 + 1024)),
+        )
         .route("/ping", get(|| async { "pong" }))
+        .route("/info", get(info_handler))
         .layer(DefaultBodyLimit::disable()) // Disable body size limit for file transfers
         .with_state(state);
 
-    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+    axum::serve(
+        listener,
+        app_router.into_make_service_with_connect_info::<RemoteAddr>(),
+    )
+    .await
+    .map_err(|e| Error::io("Server error", e))
+}
 
-    println!("Server listening on {}", addr);
+/// Accepts connections on all addresses but drops the ones that arrive on an
+/// interface the user didn't select in settings. Checked per connection so
+/// settings and network changes apply without rebinding.
+struct InterfaceFilteredListener {
+    inner: TcpListener,
+    config: Arc<Mutex<AppConfig>>,
+    allowed: AllowedAddresses,
+}
+
+impl Listener for InterfaceFilteredListener {
+    type Io = TcpStream;
+    type Addr = SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        loop {
+            let (stream, remote) = Listener::accept(&mut self.inner).await;
+
+            let interfaces = self.config.lock().unwrap().interfaces.clone();
+            match stream.local_addr() {
+                Ok(local) if !self.allowed.allows(local.ip(), &interfaces) => {
+                    warn!(
+                        "Rejecting connection from {} on non-selected interface address {}",
+                        remote,
+                        local.ip()
+                    );
+                }
+                _ => return (stream, remote),
+            }
+        }
+    }
+
+    fn local_addr(&self) -> std::io::Result<Self::Addr> {
+        self.inner.local_addr()
+    }
+}
 
-    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-    axum::serve(listener, app_router).await.unwrap();
+/// Address of the peer a request came from, for per-peer download limits and
+/// for checking that it is the device it claims to be.
+#[derive(Debug, Clone, Copy)]
+struct RemoteAddr(SocketAddr);
+
+impl Connected<IncomingStream<'_, InterfaceFilteredListener>> for RemoteAddr {
+    fn connect_info(stream: IncomingStream<'_, InterfaceFilteredListener>) -> Self {
+        // IPv4 peers on the dual-stack socket arrive as ::ffff:a.b.c.d, but
+        // are known, limited and remembered by their IPv4 address
+        let remote = stream.remote_addr();
+        Self(SocketAddr::new(remote.ip().to_canonical(), remote.port()))
+    }
 }
 
-async fn upload_handler(State(state): State<ServerState>, mut multipart: Multipart) {
+async fn upload_handler(
+    State(state): State<ServerState>,
+    ConnectInfo(RemoteAddr(remote)): ConnectInfo<RemoteAddr>,
+    mut multipart: Multipart,
+) -> StatusCode {
     let mut file_size: Option<u64> = None;
+    let mut resume_key: Option<String> = None;
+    let mut offset: u64 = 0;
+    let mut failed = false;
 
     while let Ok(Some(mut field)) = multipart.next_field().await {
         let name = field.name().unwrap_or("").to_string();
         if name == "size" {
             if let Ok(txt) = field.text().await {
                 file_size = txt.parse().ok();
-                eprintln!("Received file size: {:?}", file_size);
+                debug!("Received file size: {:?}", file_size);
+            }
+            continue;
+        }
+        if name == "resume_key" {
+            resume_key = field.text().await.ok().filter(|key| !key.is_empty());
+            continue;
+        }
+        if name == "offset" {
+            if let Ok(txt) = field.text().await {
+                offset = txt.parse().unwrap_or(0);
             }
             continue;
         }
@@ -117,211 +414,187 @@ async fn upload_handler(State(state): State<ServerState>, mut multipart: Multipa
         } else {
             continue;
         };
+        let declared_mime = field.content_type().map(str::to_string);
 
-        // Decode URL-encoded filename (e.g., image%3A1000283390 -> image:1000283390)
-        let file_name = decode(&raw_file_name)
-            .map(|s| s.to_string())
-            .unwrap_or_else(|_| raw_file_name.clone());
+        // A sender retrying after a dropped connection continues its earlier upload
+        let resumed = match resume_key.as_deref() {
+            Some(key) if offset > 0 => match state.partial_uploads.take(key, remote.ip()) {
+                Some(partial) if partial.data.len() as u64 == offset => Some(partial),
+                _ => {
+                    warn!(
+                        "Can't resume upload at byte {}, nothing kept for it",
+                        offset
+                    );
+                    return StatusCode::CONFLICT;
+                }
+            },
+            _ => None,
+        };
 
-        // Sanitize filename to remove problematic characters (like :)
-        let mut sanitized_name = file_name
-            .replace(':', "_")
-            .replace('/', "_")
-            .replace('\\', "_");
+        let (transfer_id, mut sanitized_name, mut file_data, mut meter) = match resumed {
+            Some(mut partial) => {
+                let transfer_id = partial.meter.transfer_id().to_string();
+                info!(transfer_id = transfer_id.as_str(); "Resuming upload at byte {}", offset);
+                partial.meter.seek(offset);
+                (transfer_id, partial.file_name, partial.data, partial.meter)
+            }
+            None => {
+                // Decode URL-encoded filename (e.g., image%3A1000283390 -> image:1000283390)
+                let file_name = decode(&raw_file_name)
+                    .map(|s| s.to_string())
+                    .unwrap_or_else(|_| raw_file_name.clone());
 
-        eprintln!(
-            "Receiving file: {} (original: {})",
-            sanitized_name, raw_file_name
-        );
+                let sanitized_name = filename::sanitize(&file_name);
 
-        // Generate a unique transfer ID
-        let transfer_id = format!(
-            "{}_{}",
-            sanitized_name,
-            std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_millis()
-        );
+                info!(
+                    file = sanitized_name.as_str(), raw_file = raw_file_name.as_str();
+                    "Receiving file"
+                );
 
-        // Create a oneshot channel for the response
-        let (tx, rx) = oneshot::channel();
+                // Generate a unique transfer ID
+                let transfer_id = format!(
+                    "{}_{}",
+                    sanitized_name,
+                    std::time::SystemTime::now()
+                        .duration_since(std::time::UNIX_EPOCH)
+                        .unwrap()
+                        .as_millis()
+                );
 
-        // Store the sender in pending_transfers
-        {
-            let mut transfers = state.pending_transfers.transfers.lock().unwrap();
-            transfers.insert(transfer_id.clone(), tx);
-        }
+                let request = TransferRequest {
+                    transfer_id: transfer_id.clone(),
+                    file_name: sanitized_name.clone(),
+                    file_size,
+                };
 
-        // Emit event to frontend requesting confirmation
-        let request = FileTransferRequest {
-            transfer_id: transfer_id.clone(),
-            file_name: sanitized_name.clone(),
-            file_size,
-        };
+                debug!(transfer_id = transfer_id.as_str(); "Waiting for user confirmation");
 
-        if let Err(e) = state.app_handle.emit("file-transfer-request", &request) {
-            eprintln!("Failed to emit file-transfer-request: {}", e);
-            // Clean up
-            let mut transfers = state.pending_transfers.transfers.lock().unwrap();
-            transfers.remove(&transfer_id);
-            continue;
-        }
+                // Wait for user response (with timeout)
+                let (accepted, timed_out) = match tokio::time::timeout(
+                    state.confirmation_timeout,
+                    state.confirmation.confirm(request),
+                )
+                .await
+                {
+                    Ok(response) => {
+                        info!(transfer_id = transfer_id.as_str(); "User response: {}", response);
+                        (response, false)
+                    }
+                    Err(_) => {
+                        warn!(transfer_id = transfer_id.as_str(); "Timeout waiting for confirmation");
+                        let _ = state.events.emit("file-transfer-timeout", &transfer_id);
+                        (false, true)
+                    }
+                };
 
-        eprintln!(
-            "Waiting for user confirmation for transfer: {}",
-            transfer_id
-        );
+                if !accepted {
+                    info!(transfer_id = transfer_id.as_str(); "Transfer rejected or timed out");
+                    let _ = state.events.emit("file-transfer-rejected", &sanitized_name);
+                    // We should stop here. If we continue, we risk reading the next field incorrectly or stalling.
+                    // Best to drop the multipart stream by returning, which closes the connection.
+                    // The status tells the sender why.
+                    return if timed_out {
+                        StatusCode::REQUEST_TIMEOUT
+                    } else {
+                        StatusCode::FORBIDDEN
+                    };
+                }
 
-        // Wait for user response (with timeout)
-        let accepted = match tokio::time::timeout(
-            std::time::Duration::from_secs(60), // 60 second timeout
-            rx,
-        )
-        .await
-        {
-            Ok(Ok(response)) => {
-                eprintln!("User response for {}: {}", transfer_id, response);
-                response
-            }
-            Ok(Err(_)) => {
-                eprintln!("Channel closed for transfer: {}", transfer_id);
-                false
-            }
-            Err(_) => {
-                eprintln!("Timeout waiting for confirmation: {}", transfer_id);
-                let _ = state.app_handle.emit("file-transfer-timeout", &transfer_id);
-                false
+                let meter = TransferMeter::new(transfer_id.clone(), None, file_size);
+                (transfer_id, sanitized_name, Vec::new(), meter)
             }
         };
 
-        if !accepted {
-            eprintln!("Transfer rejected or timed out: {}", transfer_id);
-            let _ = state
-                .app_handle
-                .emit("file-transfer-rejected", &sanitized_name);
-            // We should stop here. If we continue, we risk reading the next field incorrectly or stalling.
-            // Best to drop the multipart stream by returning, which closes the connection.
-            return;
-        }
-
         // User accepted, stream to file
-        eprintln!("Transfer accepted, streaming file: {}", sanitized_name);
+        info!(file = sanitized_name.as_str(); "Transfer accepted, streaming file");
 
         let start_payload = json!({
             "transfer_id": transfer_id,
             "file_name": sanitized_name
         });
-        let _ = state.app_handle.emit("file-receive-start", start_payload);
+        let _ = state.events.emit("file-receive-start", start_payload);
 
-        let mut current_bytes = 0;
-        let mut last_emit = Instant::now();
-        let mut first_chunk = true;
         let mut write_error = false;
-        let mut file_data = Vec::new();
+        let throttle = state
+            .bandwidth
+            .throttle(Direction::Receive, &remote.ip().to_string());
 
         // Read all chunks into memory
         loop {
             match field.chunk().await {
                 Ok(Some(chunk)) => {
-                    if first_chunk {
-                        // Only infer extension if missing AND not already handled by sender
-                        // This prevents overriding extensions that were already determined
-                        if std::path::Path::new(&sanitized_name).extension().is_none() {
-                            // Check for APK signature first
-                            let is_apk = chunk.len() > 30
-                                && chunk.starts_with(&[0x50, 0x4B, 0x03, 0x04]) // PK ZIP signature
-                                && String::from_utf8_lossy(&chunk[..chunk.len().min(2048)])
-                                    .contains("AndroidManifest");
-
-                            if is_apk {
-                                eprintln!("Detected APK file on receive");
-                                sanitized_name = format!("{}.apk", sanitized_name);
-                            } else if let Some(kind) = infer::get(&chunk) {
-                                let ext = kind.extension();
-                                // Only add extension if it's not a generic ZIP (could be APK)
-                                if kind.mime_type() != "application/zip" {
-                                    eprintln!(
-                                        "Inferred extension for {}: .{}",
-                                        sanitized_name, ext
-                                    );
-                                    sanitized_name = format!("{}.{}", sanitized_name, ext);
-                                } else {
-                                    eprintln!(
-                                        "Skipping ZIP extension inference (might be APK or other)"
-                                    );
-                                }
-                            }
-                        }
-                        first_chunk = false;
-                    }
+                    // Not reading any faster makes the sender slow down too
+                    throttle.acquire(chunk.len() as u64).await;
 
                     file_data.extend_from_slice(&chunk);
-                    current_bytes += chunk.len() as u64;
-
-                    if last_emit.elapsed().as_millis() > 100 {
-                        last_emit = Instant::now();
-                        let _ = state.app_handle.emit(
-                            "transfer-progress",
-                            ProgressPayload {
-                                transfer_id: transfer_id.clone(),
-                                current_bytes,
-                                total_bytes: file_size,
-                            },
-                        );
+                    if let Some(progress) = meter.advance(chunk.len() as u64) {
+                        let _ = state.events.emit("transfer-progress", progress);
                     }
                 }
                 Ok(None) => break, // End of field
                 Err(e) => {
-                    eprintln!("Error reading chunk: {}", e);
-                    let _ = state.app_handle.emit("file-receive-error", &sanitized_name);
+                    error!(file = sanitized_name.as_str(); "Error reading chunk: {}", e);
                     write_error = true;
                     break;
                 }
             }
         }
 
-        // If there was an error during reading, skip to next field
+        // If there was an error during reading, keep what arrived for the
+        // sender to resume if it can, and skip to next field
         if write_error {
+            let kept = match resume_key.take() {
+                Some(key) => state.partial_uploads.insert(
+                    key,
+                    PartialUpload {
+                        sender: remote.ip(),
+                        file_name: sanitized_name.clone(),
+                        data: file_data,
+                        meter,
+                        updated: Instant::now(),
+                    },
+                ),
+                None => false,
+            };
+            if kept {
+                let _ = state.events.emit("file-receive-interrupted", &transfer_id);
+            } else {
+                let _ = state.events.emit("file-receive-error", &sanitized_name);
+            }
+            failed = true;
             continue;
         }
 
+        // Name files that arrived without an extension after what they turned
+        // out to be, now that the whole content, including a ZIP's central
+        // directory, is here
+        if !content::has_extension(&sanitized_name) {
+            if let Some(detected) = content::detect(&file_data) {
+                debug!(
+                    file = sanitized_name.as_str();
+                    "Inferred extension .{}", detected.extension
+                );
+                sanitized_name =
+                    filename::sanitize(&format!("{}.{}", sanitized_name, detected.extension));
+            }
+        }
+
+        // The sender looked at the file where it came from, so its type is
+        // trusted over what can be told from here
+        let mime_type = content::declared(declared_mime.as_deref())
+            .or_else(|| content::mime_type(&sanitized_name, Some(&file_data)));
+
+        // Set when the file was already there and not written again
+        #[cfg_attr(all(target_os = "android", feature = "gui"), allow(unused_mut))]
+        let mut skipped = false;
+
         // Now write the file using the appropriate method for the platform
-        #[cfg(target_os = "android")]
+        #[cfg(all(target_os = "android", feature = "gui"))]
         {
-            // On Android, use the Android FS plugin to write to Downloads via MediaStore
-            eprintln!("Using Android MediaStore to save file: {}", sanitized_name);
-
-            // Determine MIME type - prioritize APK detection over generic ZIP detection
-            let mime_type = if sanitized_name.to_lowercase().ends_with(".apk") {
-                Some("application/vnd.android.package-archive".to_string())
-            } else if file_data.len() > 30
-                && file_data.starts_with(&[0x50, 0x4B, 0x03, 0x04])
-                && String::from_utf8_lossy(&file_data[..file_data.len().min(8192)])
-                    .contains("AndroidManifest")
-            {
-                // Detected APK by content signature
-                eprintln!("Detected APK file by content signature");
-                Some("application/vnd.android.package-archive".to_string())
-            } else if let Some(kind) = infer::get(&file_data) {
-                let detected_mime = kind.mime_type();
-                // If infer detected ZIP but it might be an APK, check more carefully
-                if detected_mime == "application/zip" {
-                    // Check if it's actually an APK
-                    if String::from_utf8_lossy(&file_data[..file_data.len().min(8192)])
-                        .contains("AndroidManifest")
-                    {
-                        eprintln!("Detected APK file (was misidentified as ZIP)");
-                        Some("application/vnd.android.package-archive".to_string())
-                    } else {
-                        Some(detected_mime.to_string())
-                    }
-                } else {
-                    Some(detected_mime.to_string())
-                }
-            } else {
-                None
-            };
+            // On Android, use the Android FS plugin to write to Downloads via MediaStore.
+            // It numbers the new file when the name is taken and offers no way to
+            // look one up by name, so `conflict_strategy` can't be applied here.
+            debug!(file = sanitized_name.as_str(); "Using Android MediaStore to save file");
 
             // Use async API directly
             let api = state.app_handle.android_fs_async();
@@ -331,69 +604,66 @@ async fn upload_handler(State(state): State<ServerState>, mut multipart: Multipa
                     None, // Use primary storage
                     PublicGeneralPurposeDir::Download,
                     &sanitized_name,
-                    mime_type.as_deref(),
+                    mime_type,
                     &file_data,
                 )
                 .await
             {
                 Ok(_) => {
-                    eprintln!("File saved successfully via MediaStore: {}", sanitized_name);
+                    debug!(file = sanitized_name.as_str(); "File saved via MediaStore");
                 }
                 Err(e) => {
-                    eprintln!("Failed to save file via MediaStore: {}", e);
-                    let _ = state.app_handle.emit("file-receive-error", &sanitized_name);
+                    error!(file = sanitized_name.as_str(); "Failed to save file via MediaStore: {}", e);
+                    let _ = state.events.emit("file-receive-error", &sanitized_name);
+                    failed = true;
                     continue;
                 }
             }
         }
 
-        #[cfg(not(target_os = "android"))]
+        #[cfg(not(all(target_os = "android", feature = "gui")))]
         {
             // On other platforms, use standard file I/O
-            // Get a unique filename if the file already exists
-            let unique_filename = get_unique_filename(&state.download_dir, &sanitized_name).await;
-            let final_path = state.download_dir.join(&unique_filename);
-            eprintln!("Saving file to: {:?}", final_path);
-
-            match fs::write(&final_path, &file_data).await {
-                Ok(_) => {
-                    eprintln!(
-                        "File saved successfully: {:?} ({} bytes)",
-                        final_path, current_bytes
-                    );
-                    // Update sanitized_name to the unique filename for the completion event
-                    sanitized_name = unique_filename;
+            debug!(file = sanitized_name.as_str(); "Saving file");
+            match save_file(&state, &transfer_id, &sanitized_name, &file_data).await {
+                Ok(Saved::Written(name)) => {
+                    debug!(file = name.as_str(); "File written");
+                    // The name may have changed to avoid overwriting another file
+                    sanitized_name = name;
+                }
+                Ok(Saved::Skipped(name)) => {
+                    info!(file = name.as_str(); "Identical file already exists, not saving it again");
+                    sanitized_name = name;
+                    skipped = true;
                 }
                 Err(e) => {
-                    eprintln!("Failed to write file: {}", e);
-                    let _ = state.app_handle.emit("file-receive-error", &sanitized_name);
+                    error!(file = sanitized_name.as_str(); "Failed to write file: {}", e);
+                    let _ = state.events.emit("file-receive-error", &sanitized_name);
+                    failed = true;
                     continue;
                 }
             }
         }
 
-        eprintln!(
-            "File saved successfully: {} ({} bytes)",
-            sanitized_name, current_bytes
+        info!(
+            file = sanitized_name.as_str(), bytes = meter.current_bytes();
+            "File saved successfully"
         );
 
         // Emit 100% progress first
-        eprintln!(
-            "Emitting 100% progress: {} / {}",
-            current_bytes, current_bytes
-        );
-        let _ = state.app_handle.emit(
-            "transfer-progress",
-            ProgressPayload {
-                transfer_id: transfer_id.clone(),
-                current_bytes,
-                total_bytes: Some(current_bytes),
-            },
+        let _ = state.events.emit("transfer-progress", meter.complete());
+
+        // The summary goes first, so the completion can show it
+        let summary = meter.summary(Direction::Receive);
+        info!(
+            transfer_id = transfer_id.as_str();
+            "Received in {} ms, {} bytes/s on average", summary.elapsed_ms, summary.average_bytes_per_second
         );
+        let _ = state.events.emit("transfer-summary", summary);
 
         // Then emit completion
         // Determine the file path based on platform
-        let file_path = if cfg!(target_os = "android") {
+        let file_path = if cfg!(all(target_os = "android", feature = "gui")) {
             // On Android, we can't easily get the file path from MediaStore
             // The file is saved via MediaStore API, so we'll return None
             None
@@ -405,7 +675,9 @@ async fn upload_handler(State(state): State<ServerState>, mut multipart: Multipa
 
         let mut complete_payload = json!({
             "transfer_id": transfer_id.clone(),
-            "file_name": sanitized_name.clone()
+            "file_name": sanitized_name.clone(),
+            "mime_type": mime_type.unwrap_or(content::DEFAULT_MIME),
+            "skipped": skipped
         });
 
         // Add file_path if available (Windows, Linux, macOS)
@@ -413,50 +685,376 @@ async fn upload_handler(State(state): State<ServerState>, mut multipart: Multipa
             complete_payload["file_path"] = json!(path);
         }
 
-        eprintln!("Emitting file-receive-complete: {:?}", complete_payload);
-        if let Err(e) = state
-            .app_handle
-            .emit("file-receive-complete", complete_payload)
-        {
-            eprintln!("Failed to emit file-receive-complete: {}", e);
+        debug!(transfer_id = transfer_id.as_str(); "Emitting file-receive-complete");
+        if let Err(e) = state.events.emit("file-receive-complete", complete_payload) {
+            error!("Failed to emit file-receive-complete: {}", e);
         }
 
         // Reset file_size for next field
         file_size = None;
+        resume_key = None;
+        offset = 0;
+    }
+
+    if failed {
+        StatusCode::INTERNAL_SERVER_ERROR
+    } else {
+        StatusCode::OK
     }
 }
 
-/// Generate a unique filename by adding a UUID if the file already exists
-async fn get_unique_filename(download_dir: &Path, filename: &str) -> String {
-    let path = download_dir.join(filename);
+/// Where a received file went.
+#[cfg_attr(all(target_os = "android", feature = "gui"), allow(dead_code))]
+enum Saved {
+    /// Written under this name
+    Written(String),
+    /// Not written, since the same file is already there under this name
+    Skipped(String),
+}
 
-    // If the file doesn't exist, return the original filename
-    if !path.exists() {
-        return filename.to_string();
+/// Write a received file to the download directory, settling a clash with
+/// an existing file as the configured [`ConflictStrategy`] says.
+#[cfg_attr(all(target_os = "android", feature = "gui"), allow(dead_code))]
+async fn save_file(
+    state: &ServerState,
+    transfer_id: &str,
+    name: &str,
+    data: &[u8],
+) -> std::io::Result<Saved> {
+    let dir = &state.download_dir;
+    match conflict::create_new(dir, name).await {
+        Ok(file) => {
+            conflict::write_new(file, &dir.join(name), data).await?;
+            return Ok(Saved::Written(name.to_string()));
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
+        Err(e) => return Err(e),
     }
 
-    // Split filename into name and extension
-    let (name, ext) = if let Some(dot_pos) = filename.rfind('.') {
-        let (n, e) = filename.split_at(dot_pos);
-        (n, &e[1..]) // Remove the dot from extension
-    } else {
-        (filename, "")
+    let existing = dir.join(name);
+    let strategy = state.config.lock().unwrap().conflict_strategy;
+    let resolution = match strategy {
+        ConflictStrategy::Rename => ConflictResolution::Rename,
+        ConflictStrategy::Overwrite => ConflictResolution::Overwrite,
+        ConflictStrategy::SkipIdentical => {
+            if conflict::same_content(&existing, data).await? {
+                ConflictResolution::Skip
+            } else {
+                ConflictResolution::Rename
+            }
+        }
+        ConflictStrategy::Ask => {
+            let request = FileConflict {
+                transfer_id: transfer_id.to_string(),
+                file_name: name.to_string(),
+                identical: conflict::same_content(&existing, data)
+                    .await
+                    .unwrap_or(false),
+            };
+            let answer = tokio::time::timeout(
+                state.confirmation_timeout,
+                state.confirmation.resolve_conflict(request),
+            )
+            .await;
+            answer.unwrap_or_else(|_| {
+                warn!(file = name; "No answer about the existing file, keeping both");
+                ConflictResolution::Rename
+            })
+        }
     };
+    info!(file = name; "File already exists, resolved as {:?}", resolution);
 
-    // Generate a unique filename with UUID
-    let new_filename = if ext.is_empty() {
-        format!("{}_{}", name, Uuid::new_v4())
+    match resolution {
+        ConflictResolution::Rename => {
+            let (file, numbered) = conflict::create_numbered(dir, name).await?;
+            conflict::write_new(file, &dir.join(&numbered), data).await?;
+            Ok(Saved::Written(numbered))
+        }
+        ConflictResolution::Overwrite => {
+            conflict::replace(dir, name, data).await?;
+            Ok(Saved::Written(name.to_string()))
+        }
+        ConflictResolution::Skip => Ok(Saved::Skipped(name.to_string())),
+    }
+}
+
+/// How much of an interrupted upload is kept, so its sender can resume.
+/// Other devices are told there is nothing.
+async fn upload_status_handler(
+    State(state): State<ServerState>,
+    ConnectInfo(RemoteAddr(remote)): ConnectInfo<RemoteAddr>,
+    UrlPath(resume_key): UrlPath<String>,
+) -> Result<Json<UploadStatus>, StatusCode> {
+    state
+        .partial_uploads
+        .received(&resume_key, remote.ip())
+        .map(|received| Json(UploadStatus { received }))
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+pub fn device_type() -> &'static str {
+    if cfg!(any(target_os = "android", target_os = "ios")) {
+        "mobile"
     } else {
-        format!("{}_{}.{}", name, Uuid::new_v4(), ext)
+        "desktop"
+    }
+}
+
+async fn info_handler(State(state): State<ServerState>) -> Json<DeviceInfo> {
+    let (alias, device_id, port) = {
+        let config = state.config.lock().unwrap();
+        (config.alias.clone(), config.device_id.clone(), config.port)
     };
 
-    eprintln!(
-        "File '{}' already exists, using '{}' instead",
-        filename, new_filename
+    Json(DeviceInfo {
+        alias,
+        device_id,
+        device_type: device_type().to_string(),
+        app_version: env!("CARGO_PKG_VERSION").to_string(),
+        protocol_version: PROTOCOL_VERSION.to_string(),
+        port,
+        capabilities: CAPABILITIES.iter().map(|c| c.to_string()).collect(),
+    })
+}
+
+/// Payload of `message-received`.
+#[derive(Serialize)]
+struct MessageReceived {
+    #[serde(flatten)]
+    message: MessagePayload,
+    /// Whether the link in the message was opened right away
+    opened: bool,
+}
+
+async fn message_handler(
+    State(state): State<ServerState>,
+    ConnectInfo(RemoteAddr(remote)): ConnectInfo<RemoteAddr>,
+    Json(mut message): Json<MessagePayload>,
+) -> StatusCode {
+    if let Err(e) = message.body.validate() {
+        warn!("Refused message from {}: {}", message.sender_alias, e);
+        return StatusCode::UNPROCESSABLE_ENTITY;
+    }
+    message.body.detect_link();
+
+    if !state.delivered.first_delivery(remote.ip(), &message.id) {
+        debug!(
+            "Message {} from {} was already delivered",
+            message.id, message.sender_alias
+        );
+        return StatusCode::OK;
+    }
+
+    let trusted = message.device_id.as_ref().is_some_and(|id| {
+        let chosen = state.config.lock().unwrap().open_links_from.contains(id);
+        chosen && state.peers.is_device_at(id, remote.ip())
+    });
+    let opened = match (message.body.link(), &state.link_opener) {
+        (Some(link), Some(opener)) if trusted => match opener.open(link) {
+            Ok(()) => {
+                info!("Opened link from {}", message.sender_alias);
+                true
+            }
+            Err(e) => {
+                error!("Failed to open link from {}: {}", message.sender_alias, e);
+                false
+            }
+        },
+        _ => false,
+    };
+
+    let _ = state
+        .events
+        .emit("message-received", MessageReceived { message, opened });
+    StatusCode::OK
+}
+
+/// Payload of `clipboard-received`.
+#[derive(Serialize)]
+struct ClipboardReceived {
+    sender_alias: String,
+    /// Whether the content was put into the local clipboard
+    written: bool,
+    #[serde(flatten)]
+    content: ClipboardContent,
+}
+
+async fn clipboard_handler(
+    State(state): State<ServerState>,
+    ConnectInfo(RemoteAddr(remote)): ConnectInfo<RemoteAddr>,
+    Json(message): Json<ClipboardMessage>,
+) -> StatusCode {
+    if message.content.len() > MAX_CLIPBOARD_BYTES {
+        return StatusCode::PAYLOAD_TOO_LARGE;
+    }
+    if !state.delivered.first_delivery(remote.ip(), &message.id) {
+        debug!(
+            "Clipboard push {} from {} was already delivered",
+            message.id, message.sender_alias
+        );
+        return StatusCode::OK;
+    }
+
+    // Only devices found on the network may write; anyone else's content is
+    // just shown
+    let write_received = state.config.lock().unwrap().clipboard.write_received;
+    let known = message
+        .device_id
+        .as_ref()
+        .is_some_and(|id| state.peers.is_device_at(id, remote.ip()));
+    if write_received && !known {
+        warn!(
+            "Not writing clipboard content from {} ({}): not a device discovered there",
+            message.sender_alias,
+            remote.ip()
+        );
+    }
+    let written = match &state.clipboard {
+        Some(clipboard) if write_received && known => {
+            match write_clipboard(&state.last_clipboard, clipboard, &message.content).await {
+                Ok(_) => true,
+                Err(e) => {
+                    error!("Failed to write received clipboard content: {}", e);
+                    return StatusCode::INTERNAL_SERVER_ERROR;
+                }
+            }
+        }
+        _ => false,
+    };
+    info!(
+        bytes = message.content.len(), written = written;
+        "Clipboard content received from {}", message.sender_alias
     );
-    new_filename
+
+    let received = ClipboardReceived {
+        sender_alias: message.sender_alias,
+        written,
+        content: message.content,
+    };
+    let _ = state.events.emit("clipboard-received", received);
+    StatusCode::OK
+}
+
+/// Write content from a peer to the clipboard through `last`, off the async
+/// runtime since images are decoded and the system clipboard may block.
+async fn write_clipboard(
+    last: &LastClipboard,
+    clipboard: &Arc<dyn Clipboard>,
+    content: &ClipboardContent,
+) -> Result<bool, Error> {
+    let (last, clipboard, content) = (last.clone(), clipboard.clone(), content.clone());
+    tokio::task::spawn_blocking(move || last.write(clipboard.as_ref(), &content))
+        .await
+        .unwrap_or_else(|e| Err(Error::Internal(format!("Clipboard write failed: {}", e))))
+}
+
+/// Payload of `clipboard-synced`.
+#[derive(Serialize)]
+struct ClipboardSynced {
+    sender_alias: String,
+    /// `text` or `image`
+    kind: &'static str,
 }
 
-async fn message_handler(State(state): State<ServerState>, Json(payload): Json<MessagePayload>) {
-    let _ = state.app_handle.emit("message-received", payload);
+async fn clipboard_sync_handler(
+    State(state): State<ServerState>,
+    ConnectInfo(RemoteAddr(remote)): ConnectInfo<RemoteAddr>,
+    Json(message): Json<ClipboardSyncMessage>,
+) -> StatusCode {
+    let settings = state.config.lock().unwrap().clipboard.clone();
+    if !settings.syncs_with(&message.device_id) {
+        warn!(
+            "Refused clipboard sync from {} ({}), which isn't a sync device",
+            message.sender_alias, message.device_id
+        );
+        return StatusCode::FORBIDDEN;
+    }
+    if !state.peers.is_device_at(&message.device_id, remote.ip()) {
+        warn!(
+            "Refused clipboard sync claiming to be from {} ({}): {} is not where that device was discovered",
+            message.sender_alias, message.device_id, remote.ip()
+        );
+        return StatusCode::FORBIDDEN;
+    }
+    if message.content.len() > settings.sync_max_bytes {
+        return StatusCode::PAYLOAD_TOO_LARGE;
+    }
+    let Some(clipboard) = &state.clipboard else {
+        return StatusCode::SERVICE_UNAVAILABLE;
+    };
+
+    match write_clipboard(&state.last_clipboard, clipboard, &message.content).await {
+        Ok(true) => {
+            debug!(
+                bytes = message.content.len();
+                "Clipboard synced from {}", message.sender_alias
+            );
+            let kind = match message.content {
+                ClipboardContent::Text { .. } => "text",
+                ClipboardContent::Image { .. } => "image",
+            };
+            let synced = ClipboardSynced {
+                sender_alias: message.sender_alias,
+                kind,
+            };
+            let _ = state.events.emit("clipboard-synced", synced);
+            StatusCode::OK
+        }
+        // Already there, e.g. it came from another sync device first
+        Ok(false) => StatusCode::OK,
+        Err(e) => {
+            error!("Failed to write synced clipboard content: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    const ALICE: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 2));
+    const BOB: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 3));
+
+    fn upload(sender: IpAddr, size: usize) -> PartialUpload {
+        PartialUpload {
+            sender,
+            file_name: "big.bin".to_string(),
+            data: vec![0; size],
+            meter: TransferMeter::new("big.bin_1".to_string(), None, None),
+            updated: Instant::now(),
+        }
+    }
+
+    #[test]
+    fn only_the_sender_can_resume() {
+        let uploads = PartialUploads::default();
+        assert!(uploads.insert("key".to_string(), upload(ALICE, 10)));
+
+        assert_eq!(uploads.received("key", BOB), None);
+        assert!(uploads.take("key", BOB).is_none());
+
+        let mapped = IpAddr::V6(Ipv4Addr::new(192, 168, 1, 2).to_ipv6_mapped());
+        assert_eq!(uploads.received("key", mapped), Some(10));
+        assert!(uploads.take("key", ALICE).is_some());
+        assert!(uploads.take("key", ALICE).is_none());
+    }
+
+    #[test]
+    fn oldest_uploads_make_room_for_new_ones() {
+        let uploads = PartialUploads::with_limit(100);
+        assert!(uploads.insert("first".to_string(), upload(ALICE, 40)));
+        assert!(uploads.insert("second".to_string(), upload(BOB, 40)));
+        assert!(uploads.insert("third".to_string(), upload(ALICE, 40)));
+
+        assert_eq!(uploads.received("first", ALICE), None);
+        assert_eq!(uploads.received("second", BOB), Some(40));
+        assert_eq!(uploads.received("third", ALICE), Some(40));
+
+        // Too big to keep at all, and the others stay
+        assert!(!uploads.insert("huge".to_string(), upload(BOB, 101)));
+        assert_eq!(uploads.received("huge", BOB), None);
+        assert_eq!(uploads.received("second", BOB), Some(40));
+    }
 }
@@ -0,0 +1,150 @@
+use crate::error::Error;
+use crate::transfer::FileSource;
+use log::{debug, warn};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::fs::{self, File};
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+
+/// A file the webview is handing over chunk by chunk.
+struct StagedFile {
+    name: String,
+    path: PathBuf,
+    file: File,
+    size: u64,
+}
+
+/// Files the webview can't give the backend a path for, e.g. ones it only
+/// has as a blob. They arrive in chunks over IPC and are spooled to a
+/// temporary file, so neither side ever holds the whole file in memory.
+pub struct StagedFiles {
+    dir: PathBuf,
+    /// Emptied once finished, so a late append can't write to a sent file
+    files: Mutex<HashMap<String, Arc<tokio::sync::Mutex<Option<StagedFile>>>>>,
+}
+
+impl StagedFiles {
+    /// Stage files in `dir`, clearing out whatever an earlier run left there.
+    pub fn new(dir: PathBuf) -> Self {
+        if dir.exists() {
+            if let Err(e) = std::fs::remove_dir_all(&dir) {
+                warn!(dir:? = dir; "Failed to clear staging directory: {}", e);
+            }
+        }
+        Self {
+            dir,
+            files: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Start staging a file called `name`, returning the id to append to.
+    pub async fn open(&self, name: String) -> Result<String, Error> {
+        fs::create_dir_all(&self.dir)
+            .await
+            .map_err(|e| Error::io("Failed to create staging directory", e))?;
+
+        let id = uuid::Uuid::new_v4().to_string();
+        let path = self.dir.join(&id);
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .await
+            .map_err(|e| Error::io("Failed to create staging file", e))?;
+        debug!(file = name.as_str(); "Staging file as {}", id);
+
+        let staged = StagedFile {
+            name,
+            path,
+            file,
+            size: 0,
+        };
+        self.files
+            .lock()
+            .unwrap()
+            .insert(id.clone(), Arc::new(tokio::sync::Mutex::new(Some(staged))));
+        Ok(id)
+    }
+
+    /// Add `chunk` to the end of a staged file, returning its size so far.
+    pub async fn append(&self, id: &str, chunk: &[u8]) -> Result<u64, Error> {
+        let staged = self.get(id)?;
+        let mut staged = staged.lock().await;
+        let staged = staged.as_mut().ok_or_else(|| not_found(id))?;
+        staged
+            .file
+            .write_all(chunk)
+            .await
+            .map_err(|e| Error::io("Failed to write staging file", e))?;
+        staged.size += chunk.len() as u64;
+        Ok(staged.size)
+    }
+
+    /// Stop staging a file and return it ready for sending, read from the
+    /// start. Unless this fails, the temporary file stays until [`remove`]
+    /// is called.
+    pub async fn finish(&self, id: &str) -> Result<(FileSource, PathBuf), Error> {
+        let staged = self
+            .files
+            .lock()
+            .unwrap()
+            .remove(id)
+            .ok_or_else(|| not_found(id))?;
+        // Waits for an append still in progress
+        let StagedFile {
+            name,
+            path,
+            mut file,
+            size,
+        } = staged.lock().await.take().ok_or_else(|| not_found(id))?;
+
+        let ready = async {
+            file.flush()
+                .await
+                .map_err(|e| Error::io("Failed to write staging file", e))?;
+            file.rewind()
+                .await
+                .map_err(|e| Error::io("Failed to read staging file", e))
+        }
+        .await;
+        if let Err(e) = ready {
+            drop(file);
+            remove(&path).await;
+            return Err(e);
+        }
+        Ok((FileSource { name, size, file }, path))
+    }
+
+    /// Drop a staged file without sending it.
+    pub async fn discard(&self, id: &str) {
+        let staged = self.files.lock().unwrap().remove(id);
+        if let Some(staged) = staged {
+            if let Some(staged) = staged.lock().await.take() {
+                drop(staged.file);
+                remove(&staged.path).await;
+            }
+        }
+    }
+
+    fn get(&self, id: &str) -> Result<Arc<tokio::sync::Mutex<Option<StagedFile>>>, Error> {
+        self.files
+            .lock()
+            .unwrap()
+            .get(id)
+            .cloned()
+            .ok_or_else(|| not_found(id))
+    }
+}
+
+fn not_found(id: &str) -> Error {
+    Error::NotFound(format!("No staged file {}", id))
+}
+
+/// Delete a staging file once it has been sent or given up on.
+pub async fn remove(path: &Path) {
+    if let Err(e) = fs::remove_file(path).await {
+        warn!(path:? = path; "Failed to remove staging file: {}", e);
+    }
+}
@@ -1,312 +1,984 @@
-use futures::stream::StreamExt;
-use serde::Serialize;
-use serde_json::json;
+use crate::bandwidth::{Bandwidth, Throttle};
+use crate::clipboard::{ClipboardContent, ClipboardMessage, ClipboardSyncMessage};
+use crate::content::{self, DEFAULT_MIME};
+use crate::error::Error;
+use crate::events::EventSink;
+use crate::message::{MessageBody, MessagePayload};
+use crate::progress::{Direction, TransferMeter};
+use crate::queue::TransferQueue;
+use crate::retry::{with_retry, RetriedTransfer, RetryPolicy};
+use crate::server::{DeviceInfo, UploadStatus};
+use futures::future;
+use futures::stream::{self, FuturesUnordered, Stream, StreamExt};
+use log::{debug, error, info, warn};
+use reqwest::{multipart, Body, Client, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::io;
+use std::net::IpAddr;
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
-use tauri::{AppHandle, Emitter};
-use tauri_plugin_http::reqwest::{multipart, Body, Client};
 use tokio::fs::File;
-use tokio_util::codec::{BytesCodec, FramedRead};
-
-#[cfg(target_os = "android")]
-use tauri_plugin_android_fs::{AndroidFsExt, FileUri};
-use tauri_plugin_fs::FilePath;
-
-/// Detects if a file is an APK and returns the correct MIME type
-/// APK files are ZIP archives, so we need to check for APK-specific content
-fn get_mime_type_for_file(file_name: &str, file_data: Option<&[u8]>) -> String {
-    // Check by extension first (fastest)
-    if file_name.to_lowercase().ends_with(".apk") {
-        return "application/vnd.android.package-archive".to_string();
+use tokio::net::TcpStream;
+use tokio_util::bytes::Bytes;
+
+/// MIME type to declare for a file being sent, from its extension or else
+/// its first bytes, so the receiver can tell what it is getting.
+async fn sniff_mime_type(file_name: &str, file: &mut File) -> &'static str {
+    match content::file_mime_type(file_name, file).await {
+        Ok(mime) => mime.unwrap_or(DEFAULT_MIME),
+        Err(e) => {
+            warn!(file = file_name; "Failed to detect content type: {}", e);
+            DEFAULT_MIME
+        }
+    }
+}
+
+/// How long to wait for a connection to one of a peer's addresses
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+/// How long an address gets to connect before the next one is tried
+/// alongside it
+const ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// Build a URL for a peer endpoint, bracketing IPv6 literals.
+pub fn peer_url(peer_ip: &str, peer_port: u16, path: &str) -> String {
+    match peer_ip.parse::<IpAddr>() {
+        Ok(IpAddr::V6(v6)) => format!("http://[{}]:{}{}", v6, peer_port, path),
+        _ => format!("http://{}:{}{}", peer_ip, peer_port, path),
+    }
+}
+
+/// `ip:port` of a peer by its preferred address, as in [`PeerTarget::key`].
+fn peer_key(peer_addresses: &[String], peer_port: u16) -> String {
+    let ip = peer_addresses
+        .first()
+        .map(String::as_str)
+        .unwrap_or_default();
+    format!("{}:{}", ip, peer_port)
+}
+
+/// Merge the primary IP and the full address list a command received into
+/// one ordered candidate list. The primary goes first unless the list
+/// already has it, in which case it keeps its place.
+pub fn candidate_addresses(peer_ip: String, peer_addresses: Option<Vec<String>>) -> Vec<String> {
+    let mut candidates = peer_addresses.unwrap_or_default();
+    if !candidates.contains(&peer_ip) {
+        candidates.insert(0, peer_ip);
+    }
+    candidates.retain(|addr| !addr.is_empty());
+    candidates
+}
+
+/// Client for requests to peers, giving up on an address that doesn't
+/// connect within `CONNECT_TIMEOUT`.
+fn peer_client() -> reqwest::ClientBuilder {
+    Client::builder().connect_timeout(CONNECT_TIMEOUT)
+}
+
+/// Make a request to a peer over the first of its addresses that accepts a
+/// connection. `send` prepares and sends the request to that address; an
+/// outer [`Error`] from it ends the request right away.
+///
+/// With several addresses, a TCP connection is raced to pick one before the
+/// request is sent: attempts start `ATTEMPT_DELAY` apart in order of
+/// preference, Happy Eyeballs style, and the first to connect wins. A dead
+/// preferred address therefore costs `ATTEMPT_DELAY` rather than a whole
+/// `CONNECT_TIMEOUT`, and the request itself goes out only once, so nothing
+/// is sent to two addresses.
+///
+/// A connection that breaks after that is a network error, which
+/// [`with_retry`] tries again, so the peer may get a request it already
+/// handled: messages and clipboard pushes carry an ID that stays the same
+/// across retries and the peer drops repeats; uploads continue from what the
+/// peer kept.
+async fn send_to_reachable<F, Fut>(
+    peer_addresses: &[String],
+    peer_port: u16,
+    context: &str,
+    send: F,
+) -> Result<Response, Error>
+where
+    F: FnOnce(String) -> Fut,
+    Fut: Future<Output = Result<Result<Response, reqwest::Error>, Error>>,
+{
+    let addr = match peer_addresses {
+        [] => {
+            return Err(Error::InvalidInput(
+                "Peer has no known addresses".to_string(),
+            ))
+        }
+        // Nothing to choose from, so the request's own connection will do
+        [addr] => addr.as_str(),
+        _ => first_to_connect(peer_addresses, peer_port)
+            .await
+            .map_err(|e| {
+                Error::Network(format!(
+                    "{}: peer unreachable on {}: {}",
+                    context,
+                    peer_addresses.join(", "),
+                    e
+                ))
+            })?,
+    };
+
+    send(addr.to_string())
+        .await?
+        .map_err(|e| Error::request(context, e))
+}
+
+/// The first of `peer_addresses` to accept a TCP connection on `peer_port`,
+/// starting an attempt every `ATTEMPT_DELAY`, or right away when the
+/// previous one fails, until one connects.
+async fn first_to_connect(peer_addresses: &[String], peer_port: u16) -> io::Result<&str> {
+    async fn connect(addr: &str, port: u16) -> (&str, io::Result<()>) {
+        let attempt = TcpStream::connect((addr, port));
+        let result = match tokio::time::timeout(CONNECT_TIMEOUT, attempt).await {
+            Ok(result) => result.map(drop),
+            Err(_) => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "connection timed out",
+            )),
+        };
+        (addr, result)
     }
 
-    // If we have file data, check for APK signature
-    if let Some(data) = file_data {
-        if data.len() > 30 && data.starts_with(&[0x50, 0x4B, 0x03, 0x04]) {
-            // ZIP signature found, check for AndroidManifest
-            let preview = &data[..data.len().min(8192)];
-            if String::from_utf8_lossy(preview).contains("AndroidManifest") {
-                return "application/vnd.android.package-archive".to_string();
+    let mut remaining = peer_addresses.iter();
+    let mut attempts = FuturesUnordered::new();
+    let mut last_error = None;
+    loop {
+        if let Some(addr) = remaining.next() {
+            attempts.push(connect(addr, peer_port));
+        }
+        let finished = if remaining.len() > 0 {
+            match tokio::time::timeout(ATTEMPT_DELAY, attempts.next()).await {
+                Ok(finished) => finished,
+                // Still trying; start the next one alongside
+                Err(_) => continue,
+            }
+        } else {
+            attempts.next().await
+        };
+
+        match finished {
+            Some((addr, Ok(()))) => return Ok(addr),
+            Some((addr, Err(e))) => {
+                debug!("Address {} is unreachable: {}", addr, e);
+                last_error = Some(e);
+            }
+            None => {
+                return Err(last_error
+                    .unwrap_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no addresses")))
             }
         }
     }
+}
+
+/// A file opened for sending, with the name and size to announce to the peer.
+pub struct FileSource {
+    pub name: String,
+    pub size: u64,
+    pub file: File,
+}
+
+/// Open a regular filesystem path for sending.
+pub async fn open_file(file_path: &str) -> Result<FileSource, Error> {
+    let path = PathBuf::from(file_path);
+    debug!(path:? = path; "Opening file");
+
+    let name = path
+        .file_name()
+        .ok_or_else(|| Error::InvalidInput(format!("Invalid file name: {}", file_path)))?
+        .to_string_lossy()
+        .to_string();
+    let file = File::open(&path).await.map_err(|e| {
+        error!(path:? = path; "Failed to open file: {}", e);
+        Error::io("Failed to open file", e)
+    })?;
+
+    let size = file
+        .metadata()
+        .await
+        .map_err(|e| Error::io("Failed to read file metadata", e))?
+        .len();
+    Ok(FileSource { name, size, file })
+}
+
+/// Bytes read from a file per chunk of an upload
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+
+/// A file being sent, opened once and shared by every upload of it. Uploads
+/// read at their own offsets instead of through the handle's cursor, so
+/// several peers and their retries can read the same handle at once.
+#[derive(Clone)]
+struct SharedSource {
+    name: String,
+    size: u64,
+    mime_type: &'static str,
+    file: Arc<std::fs::File>,
+}
+
+impl SharedSource {
+    async fn new(source: FileSource) -> Self {
+        let FileSource {
+            name,
+            size,
+            mut file,
+        } = source;
+        let mime_type = sniff_mime_type(&name, &mut file).await;
+        Self {
+            name,
+            size,
+            mime_type,
+            file: Arc::new(file.into_std().await),
+        }
+    }
+
+    /// The file's contents from `offset` on.
+    fn read_from(&self, offset: u64) -> impl Stream<Item = io::Result<Bytes>> + Send + Sync {
+        let file = self.file.clone();
+        stream::try_unfold(offset, move |offset| {
+            let file = file.clone();
+            async move {
+                let chunk = tokio::task::spawn_blocking(move || {
+                    let mut chunk = vec![0; READ_CHUNK_SIZE];
+                    let read = read_at(&file, &mut chunk, offset)?;
+                    chunk.truncate(read);
+                    Ok::<_, io::Error>(chunk)
+                })
+                .await
+                .map_err(io::Error::other)??;
+
+                if chunk.is_empty() {
+                    return Ok(None);
+                }
+                let next = offset + chunk.len() as u64;
+                Ok(Some((Bytes::from(chunk), next)))
+            }
+        })
+    }
+}
 
-    // Default to octet-stream
-    "application/octet-stream".to_string()
+#[cfg(unix)]
+fn read_at(file: &std::fs::File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    std::os::unix::fs::FileExt::read_at(file, buf, offset)
 }
 
-#[derive(Serialize, Clone)]
-struct ProgressPayload {
-    transfer_id: String,
-    current_bytes: u64,
-    total_bytes: u64,
+/// Moves the cursor too, which nothing else relies on.
+#[cfg(windows)]
+fn read_at(file: &std::fs::File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    std::os::windows::fs::FileExt::seek_read(file, buf, offset)
 }
 
+/// Send a file, retrying on network errors as `retry` allows. Retries
+/// continue where the peer's copy stopped if it kept one. The upload goes no
+/// faster than `throttle` allows.
 pub async fn send_file(
-    app: AppHandle,
-    peer_ip: String,
+    events: Arc<dyn EventSink>,
+    peer_addresses: Vec<String>,
+    peer_port: u16,
+    source: FileSource,
+    retry: &RetryPolicy,
+    throttle: Throttle,
+) -> Result<(), Error> {
+    send_shared(
+        events,
+        peer_addresses,
+        peer_port,
+        SharedSource::new(source).await,
+        retry,
+        throttle,
+        None,
+    )
+    .await
+}
+
+/// [`send_file`] for a source that may be shared with uploads to other
+/// peers, with progress and summary events carrying `peer` when it is.
+async fn send_shared(
+    events: Arc<dyn EventSink>,
+    peer_addresses: Vec<String>,
     peer_port: u16,
-    file_path: String,
-) -> Result<(), String> {
-    eprintln!(
-        "send_file called with: {} -> {}:{}",
-        file_path, peer_ip, peer_port
+    source: SharedSource,
+    retry: &RetryPolicy,
+    throttle: Throttle,
+    peer: Option<String>,
+) -> Result<(), Error> {
+    info!(
+        file = source.name.as_str(), bytes = source.size;
+        "Sending file to {:?}:{}", peer_addresses, peer_port
     );
 
-    let client = Client::builder()
-        .timeout(Duration::from_secs(300)) // 5 minute timeout
-        .build()
-        .map_err(|e| format!("Failed to create client: {}", e))?;
-
-    let url = format!("http://{}:{}/upload", peer_ip, peer_port);
-    eprintln!("Upload URL: {}", url);
-
-    let (file_name, file_size, stream) = {
-        // Handle Android content URIs differently
-        #[cfg(target_os = "android")]
-        if file_path.starts_with("content://") {
-            let api = app.android_fs_async();
-
-            // Convert String content URI to FileUri via FilePath
-            let url = url::Url::parse(&file_path)
-                .map_err(|e| format!("Failed to parse content URI: {}", e))?;
-            let fs_path = FilePath::Url(url);
-            let uri: FileUri = fs_path.into();
-
-            // Get file name from Android FS API
-            let name = api
-                .get_name(&uri)
-                .await
-                .map_err(|e| format!("Failed to get file name: {}", e))?;
-            eprintln!("File name from Android FS: {}", name);
+    let (file_name, file_size, mime_type) = (source.name.clone(), source.size, source.mime_type);
+    let resume_key = uuid::Uuid::new_v4().to_string();
+    let transfer_id = uuid::Uuid::new_v4().to_string();
+    let key = peer
+        .clone()
+        .unwrap_or_else(|| peer_key(&peer_addresses, peer_port));
+    let retried = RetriedTransfer {
+        transfer_id: &transfer_id,
+        name: &file_name,
+        peer: &key,
+    };
+    let meter = Arc::new(Mutex::new(TransferMeter::new(
+        file_name.clone(),
+        peer,
+        Some(file_size),
+    )));
+
+    let (events_ref, source_ref, file_name_ref) = (&events, &source, &file_name);
+    let (peer_addresses, resume_key, meter_ref) = (&peer_addresses, resume_key.as_str(), &meter);
+    let (throttle, idle_timeout) = (&throttle, retry.idle_timeout());
+    with_retry(retry, &events, retried, |attempt| async move {
+        let res = send_to_reachable(
+            peer_addresses,
+            peer_port,
+            "Request failed",
+            |peer_ip| async move {
+                let offset = if attempt > 1 {
+                    resume_offset(&peer_ip, peer_port, resume_key)
+                        .await
+                        .min(file_size)
+                } else {
+                    0
+                };
+
+                let stream = source_ref.read_from(offset);
+                meter_ref.lock().unwrap().seek(offset);
+                let activity = Arc::new(Mutex::new(Instant::now()));
+                let body = with_progress(
+                    watched(throttled(stream, throttle.clone()), activity.clone()),
+                    events_ref.clone(),
+                    meter_ref.clone(),
+                );
 
-            // Get file size using metadata
-            let metadata = api
-                .get_metadata(&uri)
-                .await
-                .map_err(|e| format!("Failed to get file metadata: {}", e))?;
-            let size = metadata.len();
-            eprintln!("File size: {}", size);
+                let upload = upload_stream(
+                    &peer_ip,
+                    peer_port,
+                    file_name_ref,
+                    file_size,
+                    mime_type,
+                    Some((resume_key, offset)),
+                    body,
+                );
+                tokio::select! {
+                    res = upload => res,
+                    _ = stalled(&activity, idle_timeout) => Err(Error::Network(format!(
+                        "The peer took none of the upload for {:?}",
+                        idle_timeout
+                    ))),
+                }
+            },
+        )
+        .await?;
 
-            // Open file for reading using Android FS API (returns std::fs::File)
-            let std_file = api
-                .open_file_readable(&uri)
-                .await
-                .map_err(|e| format!("Failed to open file: {}", e))?;
+        debug!("Response status: {}", res.status());
+        if res.status().is_success() {
+            Ok(())
+        } else {
+            Err(upload_error(res.status()))
+        }
+    })
+    .await?;
 
-            // Convert std::fs::File to tokio::fs::File for async operations
-            let tokio_file = tokio::fs::File::from_std(std_file);
+    report_sent(&events, &meter);
+    Ok(())
+}
 
-            // Create a stream from the file
-            let stream = FramedRead::new(tokio_file, BytesCodec::new());
+/// Bytes of an interrupted upload the peer still holds, or 0 if it has none
+/// or doesn't support resuming.
+async fn resume_offset(peer_ip: &str, peer_port: u16, resume_key: &str) -> u64 {
+    let client = match peer_client().timeout(Duration::from_secs(5)).build() {
+        Ok(client) => client,
+        Err(_) => return 0,
+    };
 
-            (name, size, stream)
-        } else {
-            // Regular file path on Android
-            let path = PathBuf::from(&file_path);
-            eprintln!("Opening file: {:?}", path);
+    let url = peer_url(peer_ip, peer_port, &format!("/upload/{}", resume_key));
+    let status = match client.get(&url).send().await {
+        Ok(res) if res.status().is_success() => res.json::<UploadStatus>().await.ok(),
+        _ => None,
+    };
 
-            let name = path
-                .file_name()
-                .ok_or("Invalid file name")?
-                .to_string_lossy()
-                .to_string();
-            eprintln!("File name: {}", name);
+    let offset = status.map(|status| status.received).unwrap_or(0);
+    debug!("Peer kept {} bytes of the interrupted upload", offset);
+    offset
+}
 
-            let file = File::open(&path).await.map_err(|e| {
-                eprintln!("Failed to open file: {}", e);
-                format!("Failed to open file: {}", e)
-            })?;
+/// A peer to send to, as the frontend knows it.
+#[derive(Debug, Clone, Deserialize)]
+pub struct PeerTarget {
+    pub ip: String,
+    pub port: u16,
+    #[serde(default)]
+    pub addresses: Vec<String>,
+}
 
-            let size = file.metadata().await.map_err(|e| e.to_string())?.len();
-            eprintln!("File size: {}", size);
+impl PeerTarget {
+    /// `ip:port`, used to tell peers apart in events and results.
+    pub fn key(&self) -> String {
+        format!("{}:{}", self.ip, self.port)
+    }
+}
 
-            // Create a stream from the file
-            let stream = FramedRead::new(file, BytesCodec::new());
+/// Send files to several peers at once. Each peer works through the files
+/// in order at its own pace, so a slow or hesitant peer doesn't hold up the
+/// others. Every upload is a transfer of its own in `queue`, retried as
+/// `retry` allows and throttled per peer like [`send_file`]; a failure only
+/// affects that file for that peer. Each file is opened once and all peers
+/// read it through that handle. Progress is reported per peer.
+pub async fn send_files_to_peers(
+    events: Arc<dyn EventSink>,
+    queue: &TransferQueue,
+    peers: &[PeerTarget],
+    sources: Vec<FileSource>,
+    retry: &RetryPolicy,
+    bandwidth: &Bandwidth,
+) -> FanOutSummary {
+    info!("Sending {} files to {} peers", sources.len(), peers.len());
+    let mut shared = Vec::with_capacity(sources.len());
+    for source in sources {
+        shared.push(SharedSource::new(source).await);
+    }
 
-            (name, size, stream)
+    let (shared, events) = (&shared, &events);
+    let per_peer = future::join_all(peers.iter().map(|peer| async move {
+        let key = peer.key();
+        let addresses = candidate_addresses(peer.ip.clone(), Some(peer.addresses.clone()));
+        let throttle = bandwidth.throttle(Direction::Send, &key);
+        let mut results = Vec::with_capacity(shared.len());
+        for source in shared {
+            let name = source.name.clone();
+            let (events, addresses, port) = (events.clone(), addresses.clone(), peer.port);
+            let (retry, source, throttle, peer) =
+                (*retry, source.clone(), throttle.clone(), key.clone());
+            let result = queue
+                .run(key.clone(), name.clone(), async move {
+                    send_shared(
+                        events,
+                        addresses,
+                        port,
+                        source,
+                        &retry,
+                        throttle,
+                        Some(peer),
+                    )
+                    .await
+                })
+                .await;
+            results.push((name, result));
+        }
+        (key, results)
+    }))
+    .await;
+
+    let mut summary = FanOutSummary::default();
+    for (peer, results) in per_peer {
+        for (file_name, result) in results {
+            summary.record(peer.clone(), file_name, result);
         }
+    }
+    summary
+}
 
-        #[cfg(not(target_os = "android"))]
-        {
-            // Regular file path for non-Android platforms
-            let path = PathBuf::from(&file_path);
-            eprintln!("Opening file: {:?}", path);
+/// How one file went to one peer in a multi-peer send.
+#[derive(Debug, Clone, Serialize)]
+pub struct PeerOutcome {
+    pub peer: String,
+    pub file_name: String,
+    pub error: Option<Error>,
+}
 
-            let name = path
-                .file_name()
-                .ok_or("Invalid file name")?
-                .to_string_lossy()
-                .to_string();
-            eprintln!("File name: {}", name);
+/// Everything a multi-peer send did, with totals for a quick summary.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct FanOutSummary {
+    pub outcomes: Vec<PeerOutcome>,
+    pub succeeded: usize,
+    pub failed: usize,
+}
 
-            let file = File::open(&path).await.map_err(|e| {
-                eprintln!("Failed to open file: {}", e);
-                format!("Failed to open file: {}", e)
-            })?;
+impl FanOutSummary {
+    pub fn record(&mut self, peer: String, file_name: String, result: Result<(), Error>) {
+        match result {
+            Ok(()) => self.succeeded += 1,
+            Err(_) => self.failed += 1,
+        }
+        self.outcomes.push(PeerOutcome {
+            peer,
+            file_name,
+            error: result.err(),
+        });
+    }
+}
 
-            let size = file.metadata().await.map_err(|e| e.to_string())?.len();
-            eprintln!("File size: {}", size);
+/// Send a text message to several peers at once. Each peer gets its own
+/// [`send_text`] with its own retries, so an unreachable one doesn't hold up
+/// the rest. Results are in the order of `peers`.
+pub async fn broadcast_text(
+    events: Arc<dyn EventSink>,
+    peers: &[PeerTarget],
+    text: &str,
+    sender_alias: &str,
+    retry: &RetryPolicy,
+) -> Vec<Result<(), Error>> {
+    info!(bytes = text.len(); "Broadcasting message to {} peers", peers.len());
+    future::join_all(peers.iter().map(|peer| {
+        send_text(
+            events.clone(),
+            candidate_addresses(peer.ip.clone(), Some(peer.addresses.clone())),
+            peer.port,
+            text.to_string(),
+            sender_alias.to_string(),
+            retry,
+        )
+    }))
+    .await
+}
 
-            // Create a stream from the file
-            let stream = FramedRead::new(file, BytesCodec::new());
+/// How a broadcast went for one peer.
+#[derive(Debug, Clone, Serialize)]
+pub struct BroadcastOutcome {
+    /// `ip:port`, as in [`PeerTarget::key`]
+    pub peer: String,
+    pub alias: String,
+    /// Why the message didn't arrive, if it didn't
+    pub error: Option<Error>,
+}
 
-            (name, size, stream)
-        }
-    };
+/// Everything a broadcast did, with totals for a quick summary.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BroadcastSummary {
+    pub outcomes: Vec<BroadcastOutcome>,
+    pub delivered: usize,
+    pub failed: usize,
+}
 
-    // Progress tracking
-    let uploaded = Arc::new(Mutex::new(0u64));
-    let last_emit = Arc::new(Mutex::new(Instant::now()));
-    let uploaded_clone = uploaded.clone();
-    let app_handle = app.clone();
-    let transfer_id = file_name.clone(); // Use filename as ID for sender tracking
+impl BroadcastSummary {
+    pub fn record(&mut self, peer: String, alias: String, result: Result<(), Error>) {
+        match result {
+            Ok(()) => self.delivered += 1,
+            Err(_) => self.failed += 1,
+        }
+        self.outcomes.push(BroadcastOutcome {
+            peer,
+            alias,
+            error: result.err(),
+        });
+    }
+}
 
+/// Report `transfer-progress` for a body stream as `meter` allows. The meter
+/// is shared so that retries keep measuring the same transfer.
+fn with_progress<S>(stream: S, events: Arc<dyn EventSink>, meter: Arc<Mutex<TransferMeter>>) -> Body
+where
+    S: Stream<Item = io::Result<Bytes>> + Send + Sync + 'static,
+{
     let progress_stream = stream.map(move |chunk| {
         if let Ok(ref bytes) = chunk {
-            let len = bytes.len() as u64;
-            let mut uploaded_val = uploaded_clone.lock().unwrap();
-            *uploaded_val += len;
-
-            let mut last = last_emit.lock().unwrap();
-            if last.elapsed().as_millis() > 100 {
-                // Throttle updates to every 100ms
-                *last = Instant::now();
-                let _ = app_handle.emit(
-                    "transfer-progress",
-                    ProgressPayload {
-                        transfer_id: transfer_id.clone(),
-                        current_bytes: *uploaded_val,
-                        total_bytes: file_size,
-                    },
-                );
+            let progress = meter.lock().unwrap().advance(bytes.len() as u64);
+            if let Some(progress) = progress {
+                let _ = events.emit("transfer-progress", progress);
             }
         }
         chunk
     });
 
-    let body = Body::wrap_stream(progress_stream);
+    Body::wrap_stream(progress_stream)
+}
 
-    // Determine MIME type based on filename
-    let mime_type = get_mime_type_for_file(&file_name, None);
+/// Hold back each chunk of `stream` until `throttle` lets it through.
+fn throttled<S>(
+    stream: S,
+    throttle: Throttle,
+) -> impl Stream<Item = io::Result<Bytes>> + Send + Sync
+where
+    S: Stream<Item = io::Result<Bytes>> + Send + Sync + 'static,
+{
+    stream.then(move |chunk| {
+        let throttle = throttle.clone();
+        async move {
+            if let Ok(ref bytes) = chunk {
+                throttle.acquire(bytes.len() as u64).await;
+            }
+            chunk
+        }
+    })
+}
 
-    let part = multipart::Part::stream(body)
-        .file_name(file_name.clone())
-        .mime_str(&mime_type)
-        .map_err(|e| e.to_string())?;
-
-    let form = multipart::Form::new()
-        .text("size", file_size.to_string())
-        .part("file", part);
-
-    eprintln!("Sending multipart request...");
-    let res = client
-        .post(&url)
-        .multipart(form)
-        .send()
-        .await
-        .map_err(|e| {
-            eprintln!("Request failed: {}", e);
-            format!("Request failed: {}", e)
-        })?;
-
-    eprintln!("Response status: {}", res.status());
-    if res.status().is_success() {
-        // Emit 100% progress
-        let _ = app.emit(
-            "transfer-progress",
-            ProgressPayload {
-                transfer_id: file_name,
-                current_bytes: file_size,
-                total_bytes: file_size,
-            },
-        );
-        Ok(())
-    } else {
-        Err(format!("Upload failed with status: {}", res.status()))
+/// Note in `activity` whenever `stream` is asked for more. The HTTP client
+/// only asks while the peer keeps taking what was sent, and keeps asking
+/// while a chunk waits for the throttle, so a quiet `activity` means the
+/// upload stalled.
+fn watched<S>(
+    stream: S,
+    activity: Arc<Mutex<Instant>>,
+) -> impl Stream<Item = io::Result<Bytes>> + Send + Sync
+where
+    S: Stream<Item = io::Result<Bytes>> + Send + Sync + 'static,
+{
+    let mut stream = Box::pin(stream);
+    stream::poll_fn(move |cx| {
+        *activity.lock().unwrap() = Instant::now();
+        stream.as_mut().poll_next(cx)
+    })
+}
+
+/// Resolve once `activity` has been quiet for `idle`.
+async fn stalled(activity: &Mutex<Instant>, idle: Duration) {
+    loop {
+        let deadline = *activity.lock().unwrap() + idle;
+        if Instant::now() >= deadline {
+            return;
+        }
+        tokio::time::sleep_until(deadline.into()).await;
     }
 }
 
-pub async fn send_file_bytes(
-    peer_ip: String,
-    peer_port: u16,
-    file_name: String,
-    file_data: Vec<u8>,
-) -> Result<(), String> {
-    eprintln!(
-        "send_file_bytes called: {} ({} bytes) -> {}:{}",
-        file_name,
-        file_data.len(),
-        peer_ip,
-        peer_port
+/// Emit the final 100% `transfer-progress` and the `transfer-summary` of a
+/// completed send.
+fn report_sent(events: &Arc<dyn EventSink>, meter: &Mutex<TransferMeter>) {
+    let mut meter = meter.lock().unwrap();
+    let _ = events.emit("transfer-progress", meter.complete());
+
+    let summary = meter.summary(Direction::Send);
+    info!(
+        transfer_id = summary.transfer_id.as_str(), bytes = summary.bytes;
+        "Sent in {} ms, {} bytes/s on average", summary.elapsed_ms, summary.average_bytes_per_second
     );
+    let _ = events.emit("transfer-summary", summary);
+}
 
-    let client = Client::builder()
-        .timeout(Duration::from_secs(300)) // 5 minute timeout
+/// POST a file body to a peer's `/upload`, for [`send_to_reachable`]. With
+/// `resume`, the upload is identified by its key so the peer can keep it if
+/// the connection breaks, and `body` holds the file from the given offset on.
+async fn upload_stream(
+    peer_ip: &str,
+    peer_port: u16,
+    file_name: &str,
+    file_size: u64,
+    mime_type: &str,
+    resume: Option<(&str, u64)>,
+    body: Body,
+) -> Result<Result<Response, reqwest::Error>, Error> {
+    // No overall timeout: a throttled upload may rightly take hours. The
+    // caller gives up on one that stalls instead.
+    let client = peer_client()
         .build()
-        .map_err(|e| format!("Failed to create client: {}", e))?;
-
-    let url = format!("http://{}:{}/upload", peer_ip, peer_port);
-    eprintln!("Upload URL: {}", url);
-
-    let file_size = file_data.len() as u64;
-
-    // Determine MIME type based on filename and content
-    let mime_type = get_mime_type_for_file(&file_name, Some(&file_data));
+        .map_err(|e| Error::request("Failed to create client", e))?;
 
-    let part = multipart::Part::bytes(file_data)
-        .file_name(file_name.clone())
-        .mime_str(&mime_type)
-        .map_err(|e| e.to_string())?;
+    let url = peer_url(peer_ip, peer_port, "/upload");
+    debug!("Upload URL: {}", url);
 
-    let form = multipart::Form::new()
-        .text("size", file_size.to_string())
-        .part("file", part);
+    let part = multipart::Part::stream(body)
+        .file_name(file_name.to_string())
+        .mime_str(mime_type)
+        .map_err(|e| Error::request("Invalid MIME type", e))?;
+
+    let mut form = multipart::Form::new().text("size", file_size.to_string());
+    if let Some((resume_key, offset)) = resume {
+        form = form
+            .text("resume_key", resume_key.to_string())
+            .text("offset", offset.to_string());
+    }
+    let form = form.part("file", part);
 
-    eprintln!("Sending multipart request with filename: {}", file_name);
-    let res = client
-        .post(&url)
-        .multipart(form)
-        .send()
-        .await
-        .map_err(|e| {
-            eprintln!("Request failed: {}", e);
-            format!("Request failed: {}", e)
-        })?;
-
-    eprintln!("Response status: {}", res.status());
-    if res.status().is_success() {
-        Ok(())
-    } else {
-        Err(format!("Upload failed with status: {}", res.status()))
+    let res = client.post(&url).multipart(form).send().await;
+    if let Err(ref e) = res {
+        error!("Request failed: {}", e);
     }
+    Ok(res)
 }
 
+/// Send a text message, retrying on network errors as `retry` allows.
 pub async fn send_text(
-    peer_ip: String,
+    events: Arc<dyn EventSink>,
+    peer_addresses: Vec<String>,
     peer_port: u16,
     text: String,
     sender_alias: String,
-) -> Result<(), String> {
-    let client = Client::new();
-    let url = format!("http://{}:{}/message", peer_ip, peer_port);
+    retry: &RetryPolicy,
+) -> Result<(), Error> {
+    let message = MessagePayload::new(sender_alias, MessageBody::text(text));
+    send_message(events, peer_addresses, peer_port, &message, retry).await
+}
 
-    let payload = json!({
-        "sender_alias": sender_alias,
-        "content": text
-    });
+/// Send a message of any type, retrying on network errors as `retry`
+/// allows.
+pub async fn send_message(
+    events: Arc<dyn EventSink>,
+    peer_addresses: Vec<String>,
+    peer_port: u16,
+    message: &MessagePayload,
+    retry: &RetryPolicy,
+) -> Result<(), Error> {
+    message.body.validate()?;
+
+    let client = peer_client()
+        .build()
+        .map_err(|e| Error::request("Failed to create client", e))?;
+    let key = peer_key(&peer_addresses, peer_port);
+    let retried = RetriedTransfer {
+        transfer_id: &message.id,
+        name: "message",
+        peer: &key,
+    };
+    let (peer_addresses, client) = (&peer_addresses, &client);
+    with_retry(retry, &events, retried, |_| async move {
+        let res = send_to_reachable(
+            peer_addresses,
+            peer_port,
+            "Failed to send message",
+            |peer_ip| {
+                let request = client
+                    .post(peer_url(&peer_ip, peer_port, "/message"))
+                    .json(message);
+                async move { Ok(request.send().await) }
+            },
+        )
+        .await?;
+
+        match res.status() {
+            status if status.is_success() => Ok(()),
+            StatusCode::PAYLOAD_TOO_LARGE | StatusCode::UNPROCESSABLE_ENTITY => Err(
+                Error::InvalidInput("The peer refused the message".to_string()),
+            ),
+            status => Err(Error::Network(format!(
+                "Message failed with status: {}",
+                status
+            ))),
+        }
+    })
+    .await
+}
+
+/// Send clipboard content to a peer, which shows it or puts it into its own
+/// clipboard depending on its settings.
+pub async fn send_clipboard(
+    events: Arc<dyn EventSink>,
+    peer_addresses: Vec<String>,
+    peer_port: u16,
+    content: ClipboardContent,
+    sender_alias: String,
+    device_id: String,
+    retry: &RetryPolicy,
+) -> Result<(), Error> {
+    let message = ClipboardMessage {
+        id: uuid::Uuid::new_v4().to_string(),
+        sender_alias,
+        device_id: Some(device_id),
+        content,
+    };
+
+    let key = peer_key(&peer_addresses, peer_port);
+    let retried = RetriedTransfer {
+        transfer_id: &message.id,
+        name: "clipboard",
+        peer: &key,
+    };
+    let (peer_addresses, message) = (&peer_addresses, &message);
+    with_retry(retry, &events, retried, |_| async move {
+        post_clipboard(peer_addresses, peer_port, "/clipboard", message).await
+    })
+    .await
+}
 
-    let res = client
-        .post(&url)
-        .json(&payload)
-        .send()
+/// Push a clipboard change to a peer we sync with. Not retried: by the time
+/// a retry would succeed, the clipboard may have changed again.
+pub async fn sync_clipboard(
+    peer_addresses: Vec<String>,
+    peer_port: u16,
+    message: &ClipboardSyncMessage,
+) -> Result<(), Error> {
+    post_clipboard(&peer_addresses, peer_port, "/clipboard/sync", message).await
+}
+
+async fn post_clipboard(
+    peer_addresses: &[String],
+    peer_port: u16,
+    path: &str,
+    body: &impl Serialize,
+) -> Result<(), Error> {
+    let client = peer_client()
+        .build()
+        .map_err(|e| Error::request("Failed to create client", e))?;
+    let res = send_to_reachable(
+        peer_addresses,
+        peer_port,
+        "Failed to send clipboard",
+        |peer_ip| {
+            let request = client.post(peer_url(&peer_ip, peer_port, path)).json(body);
+            async move { Ok(request.send().await) }
+        },
+    )
+    .await?;
+
+    match res.status() {
+        status if status.is_success() => Ok(()),
+        StatusCode::PAYLOAD_TOO_LARGE => Err(Error::InvalidInput(
+            "Clipboard content is too large for the peer".to_string(),
+        )),
+        StatusCode::FORBIDDEN => Err(Error::Rejected(
+            "The peer doesn't sync its clipboard with this device".to_string(),
+        )),
+        status => Err(Error::Network(format!(
+            "Clipboard failed with status: {}",
+            status
+        ))),
+    }
+}
+
+/// Ask a peer to describe itself via `GET /info`, over the first of its
+/// addresses that answers.
+/// Used to enrich peers whose mDNS TXT records are incomplete and to probe
+/// manually entered addresses.
+pub async fn fetch_device_info(
+    peer_addresses: &[String],
+    peer_port: u16,
+) -> Result<DeviceInfo, Error> {
+    let client = peer_client()
+        .timeout(Duration::from_secs(5))
+        .build()
+        .map_err(|e| Error::request("Failed to create client", e))?;
+
+    let res = send_to_reachable(peer_addresses, peer_port, "Request failed", |peer_ip| {
+        let request = client.get(peer_url(&peer_ip, peer_port, "/info"));
+        async move { Ok(request.send().await) }
+    })
+    .await?;
+
+    if !res.status().is_success() {
+        return Err(Error::Network(format!(
+            "Info request failed with status: {}",
+            res.status()
+        )));
+    }
+
+    res.json::<DeviceInfo>()
         .await
-        .map_err(|e| e.to_string())?;
+        .map_err(|e| Error::request("Invalid device info", e))
+}
+
+/// Map the status of a failed `/upload` to the reason the receiver gave.
+fn upload_error(status: StatusCode) -> Error {
+    match status {
+        StatusCode::FORBIDDEN => Error::Rejected("The receiver declined the file".to_string()),
+        StatusCode::REQUEST_TIMEOUT => {
+            Error::Timeout("The receiver didn't answer in time".to_string())
+        }
+        // Worth retrying: the next attempt asks what the peer kept and starts over if nothing
+        StatusCode::CONFLICT => {
+            Error::Network("The receiver no longer has the interrupted upload".to_string())
+        }
+        // Not retried: the file was already accepted and the user would be
+        // asked again, only for saving to most likely fail the same way
+        status if status.is_server_error() => {
+            Error::Internal(format!("The receiver couldn't save the file: {}", status))
+        }
+        _ => Error::Network(format!("Upload failed with status: {}", status)),
+    }
+}
 
-    if res.status().is_success() {
-        Ok(())
-    } else {
-        Err(format!("Message failed with status: {}", res.status()))
+/// Check that a peer's server is still answering `GET /ping` on any of its
+/// addresses; the peer may have left one network but still be on another.
+pub async fn ping_peer(peer_addresses: &[String], peer_port: u16) -> bool {
+    let client = match peer_client().timeout(Duration::from_secs(3)).build() {
+        Ok(client) => client,
+        Err(_) => return false,
+    };
+
+    let res = send_to_reachable(peer_addresses, peer_port, "Ping failed", |peer_ip| {
+        let request = client.get(peer_url(&peer_ip, peer_port, "/ping"));
+        async move { Ok(request.send().await) }
+    })
+    .await;
+    res.is_ok_and(|res| res.status().is_success())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{routing::get, Router};
+    use socket2::{Domain, Socket, Type};
+
+    #[test]
+    fn ipv6_literals_are_bracketed_in_urls() {
+        assert_eq!(
+            peer_url("192.168.1.20", 53317, "/ping"),
+            "http://192.168.1.20:53317/ping"
+        );
+        assert_eq!(
+            peer_url("fd00::20", 53317, "/info"),
+            "http://[fd00::20]:53317/info"
+        );
+        assert_eq!(
+            peer_url("::ffff:192.168.1.20", 80, "/upload"),
+            "http://[::ffff:192.168.1.20]:80/upload"
+        );
+        // Names are passed through
+        assert_eq!(
+            peer_url("laptop.local", 3030, "/ping"),
+            "http://laptop.local:3030/ping"
+        );
+    }
+
+    #[test]
+    fn primary_address_is_added_once_to_the_candidates() {
+        assert_eq!(
+            candidate_addresses(
+                "192.168.1.20".to_string(),
+                Some(vec!["fd00::20".to_string(), String::new()])
+            ),
+            vec!["192.168.1.20", "fd00::20"]
+        );
+        // A primary that is already listed keeps its place
+        assert_eq!(
+            candidate_addresses(
+                "fd00::20".to_string(),
+                Some(vec!["192.168.1.20".to_string(), "fd00::20".to_string()])
+            ),
+            vec!["192.168.1.20", "fd00::20"]
+        );
+        assert_eq!(
+            candidate_addresses("192.168.1.20".to_string(), None),
+            vec!["192.168.1.20"]
+        );
+    }
+
+    /// A listener on `ip:port` that never accepts and whose queue is already
+    /// full, so connecting to it hangs the way it does to a dead address.
+    fn unresponsive(ip: &str, port: u16) -> (Socket, std::net::TcpStream) {
+        let addr: std::net::SocketAddr = format!("{}:{}", ip, port).parse().unwrap();
+        let socket = Socket::new(Domain::IPV4, Type::STREAM, None).unwrap();
+        socket.bind(&addr.into()).unwrap();
+        socket.listen(0).unwrap();
+        let filler = std::net::TcpStream::connect(addr).unwrap();
+        (socket, filler)
+    }
+
+    #[tokio::test]
+    async fn dead_preferred_addresses_cost_little() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let app = Router::new().route("/ping", get(|| async { "pong" }));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        let _dead = unresponsive("127.0.0.2", port);
+
+        let started = Instant::now();
+        let addresses = ["127.0.0.2".to_string(), "127.0.0.1".to_string()];
+        assert!(ping_peer(&addresses, port).await);
+        assert!(
+            started.elapsed() < CONNECT_TIMEOUT / 2,
+            "took {:?}",
+            started.elapsed()
+        );
+
+        // Several dead addresses are waited for together, not one by one
+        let started = Instant::now();
+        let addresses = ["127.0.0.2".to_string(), "127.0.0.2".to_string()];
+        assert!(!ping_peer(&addresses, port).await);
+        assert!(
+            started.elapsed() < CONNECT_TIMEOUT + 2 * ATTEMPT_DELAY,
+            "took {:?}",
+            started.elapsed()
+        );
     }
 }
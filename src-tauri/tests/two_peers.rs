@@ -0,0 +1,1551 @@
+//! Two peers talking to each other over loopback, each with its own server on
+//! an ephemeral port, download directory and scripted confirmation answers.
+
+use futures::future::{self, BoxFuture, FutureExt};
+use local_share_lib::bandwidth::{Bandwidth, BandwidthLimits, Throttle};
+use local_share_lib::clipboard::{
+    self, Clipboard, ClipboardContent, ClipboardSyncMessage, LastClipboard, RgbaImage,
+    SystemContent, SYNC_POLL_INTERVAL,
+};
+use local_share_lib::config::AppConfig;
+use local_share_lib::confirmation::{ConfirmationProvider, TransferRequest};
+use local_share_lib::conflict::{ConflictResolution, ConflictStrategy, FileConflict};
+use local_share_lib::discovery::PeerDirectory;
+use local_share_lib::error::Error;
+use local_share_lib::events::{ChannelEventSink, Event, EventSink};
+use local_share_lib::message::{InlineImage, LinkOpener, MessageBody, MessageKind, MessagePayload};
+use local_share_lib::progress::Direction;
+use local_share_lib::queue::TransferQueue;
+use local_share_lib::retry::{with_retry, RetriedTransfer, RetryPolicy};
+use local_share_lib::server::{
+    bind, serve, Delivered, PartialUploads, ServerState, CAPABILITIES, PROTOCOL_VERSION,
+};
+use local_share_lib::staging::{self, StagedFiles};
+use local_share_lib::transfer::{
+    broadcast_text, fetch_device_info, open_file, send_clipboard, send_file, send_files_to_peers,
+    send_message, send_text, sync_clipboard, PeerTarget,
+};
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::net::TcpListener;
+use tokio::runtime::Handle;
+use tokio::sync::mpsc::UnboundedReceiver;
+
+#[derive(Clone, Copy)]
+enum Answer {
+    Accept,
+    Reject,
+    /// Never answer, so the server times out
+    Ignore,
+}
+
+/// Answers transfer requests from a fixed script, rejecting once it runs out.
+/// Name conflicts are answered from their own script, keeping both files
+/// once that runs out.
+struct ScriptedConfirmation {
+    answers: Mutex<VecDeque<Answer>>,
+    conflicts: Mutex<VecDeque<ConflictResolution>>,
+}
+
+impl ConfirmationProvider for ScriptedConfirmation {
+    fn confirm(&self, _request: TransferRequest) -> BoxFuture<'static, bool> {
+        match self.answers.lock().unwrap().pop_front() {
+            Some(Answer::Accept) => future::ready(true).boxed(),
+            Some(Answer::Reject) | None => future::ready(false).boxed(),
+            Some(Answer::Ignore) => future::pending().boxed(),
+        }
+    }
+
+    fn resolve_conflict(&self, _conflict: FileConflict) -> BoxFuture<'static, ConflictResolution> {
+        let resolution = self.conflicts.lock().unwrap().pop_front();
+        future::ready(resolution.unwrap_or(ConflictResolution::Rename)).boxed()
+    }
+}
+
+/// A clipboard that only lives in memory, holding images decoded like a
+/// system clipboard does.
+#[derive(Default)]
+struct MemoryClipboard(Mutex<Option<SystemContent>>);
+
+impl Clipboard for MemoryClipboard {
+    fn read_raw(&self) -> Result<SystemContent, Error> {
+        self.0
+            .lock()
+            .unwrap()
+            .clone()
+            .ok_or_else(|| Error::NotFound("The clipboard is empty".to_string()))
+    }
+
+    fn write(&self, content: &ClipboardContent) -> Result<(), Error> {
+        *self.0.lock().unwrap() = Some(SystemContent::decode(content)?);
+        Ok(())
+    }
+}
+
+/// Remembers the links it is asked to open.
+#[derive(Default)]
+struct RecordingOpener(Mutex<Vec<String>>);
+
+impl LinkOpener for RecordingOpener {
+    fn open(&self, url: &str) -> Result<(), Error> {
+        self.0.lock().unwrap().push(url.to_string());
+        Ok(())
+    }
+}
+
+/// Devices "discovered" by a test, by device ID and address.
+#[derive(Default)]
+struct KnownPeers(Mutex<Vec<(String, IpAddr)>>);
+
+impl PeerDirectory for KnownPeers {
+    fn is_device_at(&self, device_id: &str, ip: IpAddr) -> bool {
+        let known = self.0.lock().unwrap();
+        let mut claiming = known.iter().filter(|(id, _)| id == device_id).peekable();
+        claiming.peek().is_some() && claiming.all(|(_, addr)| *addr == ip)
+    }
+}
+
+/// A directory under the system temp dir, removed on drop.
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(prefix: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("{}-{}", prefix, uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+struct TestPeer {
+    alias: String,
+    port: u16,
+    downloads: TempDir,
+    sink: Arc<dyn EventSink>,
+    events: UnboundedReceiver<Event>,
+    /// Limits for the peer's server and for sends made through [`TestPeer::send`]
+    bandwidth: Bandwidth,
+    config: Arc<Mutex<AppConfig>>,
+    confirmation: Arc<ScriptedConfirmation>,
+    clipboard: Arc<MemoryClipboard>,
+    last_clipboard: LastClipboard,
+    opened_links: Arc<RecordingOpener>,
+    known_peers: Arc<KnownPeers>,
+}
+
+impl TestPeer {
+    async fn start(alias: &str, answers: &[Answer]) -> Self {
+        Self::start_with_timeout(alias, answers, Duration::from_secs(5)).await
+    }
+
+    async fn start_with_timeout(alias: &str, answers: &[Answer], timeout: Duration) -> Self {
+        let (sink, events) = ChannelEventSink::new();
+        let sink: Arc<dyn EventSink> = Arc::new(sink);
+        let downloads = TempDir::new(alias);
+
+        // Dual-stack, like the app's server
+        let listener = bind(0).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let bandwidth = Bandwidth::new(BandwidthLimits::default());
+
+        let config = AppConfig {
+            alias: alias.to_string(),
+            port,
+            ..AppConfig::default()
+        };
+        let config = Arc::new(Mutex::new(config));
+        let confirmation = Arc::new(ScriptedConfirmation {
+            answers: Mutex::new(answers.iter().copied().collect()),
+            conflicts: Mutex::new(VecDeque::new()),
+        });
+        let clipboard = Arc::new(MemoryClipboard::default());
+        let last_clipboard = LastClipboard::default();
+        let opened_links = Arc::new(RecordingOpener::default());
+        let known_peers = Arc::new(KnownPeers::default());
+        let state = ServerState {
+            events: sink.clone(),
+            confirmation: confirmation.clone(),
+            confirmation_timeout: timeout,
+            download_dir: downloads.0.clone(),
+            config: config.clone(),
+            partial_uploads: PartialUploads::default(),
+            delivered: Delivered::default(),
+            bandwidth: bandwidth.clone(),
+            clipboard: Some(clipboard.clone()),
+            last_clipboard: last_clipboard.clone(),
+            link_opener: Some(opened_links.clone()),
+            peers: known_peers.clone(),
+        };
+        tokio::spawn(serve(state, listener));
+
+        Self {
+            alias: alias.to_string(),
+            port,
+            downloads,
+            sink,
+            events,
+            bandwidth,
+            config,
+            confirmation,
+            clipboard,
+            last_clipboard,
+            opened_links,
+            known_peers,
+        }
+    }
+
+    /// Let this peer find `other` at `ip`, as discovery would.
+    fn discover(&self, other: &TestPeer, ip: IpAddr) {
+        let device_id = other.config.lock().unwrap().device_id.clone();
+        self.known_peers.0.lock().unwrap().push((device_id, ip));
+    }
+
+    fn addresses(&self) -> Vec<String> {
+        vec!["127.0.0.1".to_string()]
+    }
+
+    fn target(&self) -> PeerTarget {
+        PeerTarget {
+            ip: "127.0.0.1".to_string(),
+            port: self.port,
+            addresses: self.addresses(),
+        }
+    }
+
+    /// Send a file with the given name and contents to `receiver`.
+    async fn send(&self, receiver: &TestPeer, name: &str, contents: &[u8]) -> Result<(), Error> {
+        let outgoing = TempDir::new(&format!("{}-out", self.alias));
+        let path = outgoing.0.join(name);
+        std::fs::write(&path, contents).unwrap();
+
+        let source = open_file(&path.to_string_lossy()).await?;
+        send_file(
+            self.sink.clone(),
+            receiver.addresses(),
+            receiver.port,
+            source,
+            &RetryPolicy::never(),
+            self.bandwidth
+                .throttle(Direction::Send, &receiver.target().key()),
+        )
+        .await
+    }
+
+    /// Send `contents` to `receiver` the way the app sends files the webview
+    /// stages, under any name, even one no local file could have.
+    async fn send_staged(
+        &self,
+        receiver: &TestPeer,
+        name: &str,
+        contents: &[u8],
+    ) -> Result<(), Error> {
+        let staging_dir = TempDir::new(&format!("{}-staged", self.alias));
+        let staged = StagedFiles::new(staging_dir.0.clone());
+        let id = staged.open(name.to_string()).await?;
+        staged.append(&id, contents).await?;
+        let (source, path) = staged.finish(&id).await?;
+
+        let result = send_file(
+            self.sink.clone(),
+            receiver.addresses(),
+            receiver.port,
+            source,
+            &RetryPolicy::never(),
+            Throttle::unlimited(),
+        )
+        .await;
+        staging::remove(&path).await;
+        result
+    }
+
+    /// Wait for the next event called `name`, skipping any others.
+    async fn expect_event(&mut self, name: &str) -> Value {
+        let wait = async {
+            loop {
+                let event = self.events.recv().await.expect("event sink closed");
+                if event.name == name {
+                    return event.payload;
+                }
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), wait)
+            .await
+            .unwrap_or_else(|_| panic!("no {} event", name))
+    }
+
+    fn downloaded_files(&self) -> Vec<String> {
+        let mut names: Vec<String> = std::fs::read_dir(&self.downloads.0)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        names
+    }
+
+    fn read(&self, name: &str) -> Vec<u8> {
+        std::fs::read(self.downloads.0.join(name)).unwrap()
+    }
+}
+
+fn file_exists(dir: &Path, name: &str) -> bool {
+    dir.join(name).exists()
+}
+
+/// Smallest valid ZIP archive holding one stored entry.
+fn zip_with_entry(name: &str, contents: &[u8]) -> Vec<u8> {
+    let mut zip = Vec::new();
+    let name = name.as_bytes();
+
+    // Local file header
+    zip.extend_from_slice(&0x04034b50u32.to_le_bytes());
+    zip.extend_from_slice(&[20, 0, 0, 0, 0, 0, 0, 0, 0, 0]); // version, flags, method, time, date
+    zip.extend_from_slice(&0u32.to_le_bytes()); // CRC-32, not checked by readers here
+    zip.extend_from_slice(&(contents.len() as u32).to_le_bytes());
+    zip.extend_from_slice(&(contents.len() as u32).to_le_bytes());
+    zip.extend_from_slice(&(name.len() as u16).to_le_bytes());
+    zip.extend_from_slice(&0u16.to_le_bytes());
+    zip.extend_from_slice(name);
+    zip.extend_from_slice(contents);
+
+    // Central directory
+    let central_offset = zip.len();
+    zip.extend_from_slice(&0x02014b50u32.to_le_bytes());
+    zip.extend_from_slice(&[20, 0, 20, 0, 0, 0, 0, 0, 0, 0, 0, 0]); // versions, flags, method, time, date
+    zip.extend_from_slice(&0u32.to_le_bytes());
+    zip.extend_from_slice(&(contents.len() as u32).to_le_bytes());
+    zip.extend_from_slice(&(contents.len() as u32).to_le_bytes());
+    zip.extend_from_slice(&(name.len() as u16).to_le_bytes());
+    zip.extend_from_slice(&[0; 12]); // extra, comment, disk, attributes
+    zip.extend_from_slice(&0u32.to_le_bytes()); // local header offset
+    zip.extend_from_slice(name);
+    let central_size = zip.len() - central_offset;
+
+    // End of central directory
+    zip.extend_from_slice(&0x06054b50u32.to_le_bytes());
+    zip.extend_from_slice(&[0, 0, 0, 0, 1, 0, 1, 0]); // disks, entry counts
+    zip.extend_from_slice(&(central_size as u32).to_le_bytes());
+    zip.extend_from_slice(&(central_offset as u32).to_le_bytes());
+    zip.extend_from_slice(&0u16.to_le_bytes());
+
+    zip
+}
+
+#[tokio::test]
+async fn accepted_file_is_saved() {
+    let mut alice = TestPeer::start("alice", &[]).await;
+    let mut bob = TestPeer::start("bob", &[Answer::Accept]).await;
+
+    alice.send(&bob, "hello.txt", b"hello bob").await.unwrap();
+
+    let received = bob.expect_event("transfer-summary").await;
+    let complete = bob.expect_event("file-receive-complete").await;
+    assert_eq!(complete["file_name"], "hello.txt");
+    assert_eq!(bob.read("hello.txt"), b"hello bob");
+
+    let progress = alice.expect_event("transfer-progress").await;
+    assert_eq!(progress["current_bytes"], 9);
+    assert_eq!(progress["total_bytes"], 9);
+    assert_eq!(progress["eta_ms"], 0);
+    assert!(progress["elapsed_ms"].is_u64());
+
+    let sent = alice.expect_event("transfer-summary").await;
+    assert_eq!(sent["direction"], "send");
+    assert_eq!(sent["bytes"], 9);
+    assert_eq!(received["direction"], "receive");
+    assert_eq!(received["transfer_id"], complete["transfer_id"]);
+    assert!(received["average_bytes_per_second"].is_u64());
+}
+
+#[tokio::test]
+async fn file_is_uploaded_over_ipv6() {
+    let alice = TestPeer::start("alice", &[]).await;
+    let mut bob = TestPeer::start("bob", &[Answer::Accept]).await;
+
+    let outgoing = TempDir::new("alice-out");
+    let path = outgoing.0.join("v6.txt");
+    std::fs::write(&path, b"over ::1").unwrap();
+    let source = open_file(&path.to_string_lossy()).await.unwrap();
+    send_file(
+        alice.sink.clone(),
+        vec!["::1".to_string()],
+        bob.port,
+        source,
+        &RetryPolicy::never(),
+        Throttle::unlimited(),
+    )
+    .await
+    .unwrap();
+
+    let complete = bob.expect_event("file-receive-complete").await;
+    assert_eq!(complete["file_name"], "v6.txt");
+    assert_eq!(bob.read("v6.txt"), b"over ::1");
+}
+
+#[tokio::test]
+async fn unreachable_addresses_are_skipped() {
+    let alice = TestPeer::start("alice", &[]).await;
+    let mut bob = TestPeer::start("bob", &[Answer::Accept]).await;
+
+    let outgoing = TempDir::new("alice-out");
+    let path = outgoing.0.join("fallback.txt");
+    std::fs::write(&path, b"second time lucky").unwrap();
+    let source = open_file(&path.to_string_lossy()).await.unwrap();
+    // Documentation range, nothing answers there
+    let addresses = vec!["192.0.2.1".to_string(), "127.0.0.1".to_string()];
+    send_file(
+        alice.sink.clone(),
+        addresses,
+        bob.port,
+        source,
+        &RetryPolicy::never(),
+        Throttle::unlimited(),
+    )
+    .await
+    .unwrap();
+
+    bob.expect_event("file-receive-complete").await;
+    assert_eq!(bob.read("fallback.txt"), b"second time lucky");
+}
+
+#[tokio::test]
+async fn peers_describe_themselves() {
+    let bob = TestPeer::start("bob", &[]).await;
+
+    // Asked the way a manually entered or poorly advertised peer is
+    let addresses = vec!["192.0.2.1".to_string(), "127.0.0.1".to_string()];
+    let info = fetch_device_info(&addresses, bob.port).await.unwrap();
+    assert_eq!(info.alias, "bob");
+    assert_eq!(info.port, bob.port);
+    assert_eq!(info.device_id, bob.config.lock().unwrap().device_id);
+    assert_eq!(info.app_version, env!("CARGO_PKG_VERSION"));
+    assert_eq!(info.protocol_version, PROTOCOL_VERSION);
+    assert_eq!(info.capabilities, CAPABILITIES);
+
+    // Renaming shows right away
+    bob.config.lock().unwrap().alias = "Bob's laptop".to_string();
+    let info = fetch_device_info(&bob.addresses(), bob.port).await.unwrap();
+    assert_eq!(info.alias, "Bob's laptop");
+}
+
+#[tokio::test]
+async fn rejected_file_is_reported_to_sender() {
+    let alice = TestPeer::start("alice", &[]).await;
+    let mut bob = TestPeer::start("bob", &[Answer::Reject]).await;
+
+    let result = alice.send(&bob, "secret.txt", b"nope").await;
+
+    assert!(matches!(result, Err(Error::Rejected(_))), "{:?}", result);
+    assert_eq!(
+        bob.expect_event("file-transfer-rejected").await,
+        "secret.txt"
+    );
+    assert!(!file_exists(&bob.downloads.0, "secret.txt"));
+}
+
+#[tokio::test]
+async fn unanswered_file_times_out() {
+    let alice = TestPeer::start("alice", &[]).await;
+    let mut bob =
+        TestPeer::start_with_timeout("bob", &[Answer::Ignore], Duration::from_millis(200)).await;
+
+    let result = alice.send(&bob, "slow.txt", b"anyone?").await;
+
+    assert!(matches!(result, Err(Error::Timeout(_))), "{:?}", result);
+    bob.expect_event("file-transfer-timeout").await;
+    assert!(bob.downloaded_files().is_empty());
+}
+
+#[tokio::test]
+async fn colliding_names_are_made_unique() {
+    let alice = TestPeer::start("alice", &[]).await;
+    let bob = TestPeer::start("bob", &[Answer::Accept, Answer::Accept]).await;
+
+    alice.send(&bob, "report.txt", b"first").await.unwrap();
+    alice.send(&bob, "report.txt", b"second").await.unwrap();
+
+    assert_eq!(bob.downloaded_files(), vec!["report (1).txt", "report.txt"]);
+    assert_eq!(bob.read("report.txt"), b"first");
+    assert_eq!(bob.read("report (1).txt"), b"second");
+}
+
+#[tokio::test]
+async fn conflict_strategy_decides_about_taken_names() {
+    let alice = TestPeer::start("alice", &[]).await;
+    let mut bob = TestPeer::start("bob", &[Answer::Accept; 6]).await;
+    let strategy = |strategy| bob.config.lock().unwrap().conflict_strategy = strategy;
+
+    alice.send(&bob, "photo.jpg", b"original").await.unwrap();
+
+    strategy(ConflictStrategy::Overwrite);
+    alice.send(&bob, "photo.jpg", b"edited").await.unwrap();
+    assert_eq!(bob.downloaded_files(), vec!["photo.jpg"]);
+    assert_eq!(bob.read("photo.jpg"), b"edited");
+
+    // The same content isn't saved twice, different content is kept too
+    strategy(ConflictStrategy::SkipIdentical);
+    alice.send(&bob, "photo.jpg", b"edited").await.unwrap();
+    assert_eq!(bob.downloaded_files(), vec!["photo.jpg"]);
+    alice.send(&bob, "photo.jpg", b"retouched").await.unwrap();
+    assert_eq!(bob.downloaded_files(), vec!["photo (1).jpg", "photo.jpg"]);
+    assert_eq!(bob.read("photo (1).jpg"), b"retouched");
+
+    strategy(ConflictStrategy::Ask);
+    bob.confirmation
+        .conflicts
+        .lock()
+        .unwrap()
+        .extend([ConflictResolution::Skip, ConflictResolution::Overwrite]);
+    alice.send(&bob, "photo.jpg", b"unwanted").await.unwrap();
+    alice.send(&bob, "photo.jpg", b"wanted").await.unwrap();
+    assert_eq!(bob.downloaded_files(), vec!["photo (1).jpg", "photo.jpg"]);
+    assert_eq!(bob.read("photo.jpg"), b"wanted");
+
+    let mut skipped = 0;
+    while let Ok(event) = bob.events.try_recv() {
+        if event.name == "file-receive-complete" && event.payload["skipped"] == true {
+            skipped += 1;
+        }
+    }
+    assert_eq!(skipped, 2);
+}
+
+#[tokio::test]
+async fn names_cant_escape_the_download_directory() {
+    let alice = TestPeer::start("alice", &[]).await;
+    let bob = TestPeer::start("bob", &[Answer::Accept, Answer::Accept]).await;
+
+    alice
+        .send_staged(&bob, "../escape.txt", b"up")
+        .await
+        .unwrap();
+    alice.send_staged(&bob, "CON.txt", b"device").await.unwrap();
+
+    let files = bob.downloaded_files();
+    assert_eq!(files.len(), 2, "{:?}", files);
+    assert_eq!(files[0], "_CON.txt");
+    assert!(files[1].ends_with("escape.txt"), "{:?}", files);
+    assert_eq!(bob.read(&files[1]), b"up");
+    let parent = bob.downloads.0.parent().unwrap();
+    assert!(!file_exists(parent, "escape.txt"));
+}
+
+#[tokio::test]
+async fn messages_are_delivered_both_ways() {
+    let mut alice = TestPeer::start("alice", &[]).await;
+    let mut bob = TestPeer::start("bob", &[]).await;
+
+    send_text(
+        alice.sink.clone(),
+        bob.addresses(),
+        bob.port,
+        "hi bob".into(),
+        "alice".into(),
+        &RetryPolicy::never(),
+    )
+    .await
+    .unwrap();
+    send_text(
+        bob.sink.clone(),
+        alice.addresses(),
+        alice.port,
+        "hi alice".into(),
+        "bob".into(),
+        &RetryPolicy::never(),
+    )
+    .await
+    .unwrap();
+
+    let to_bob = bob.expect_event("message-received").await;
+    assert_eq!(to_bob["sender_alias"], "alice");
+    assert_eq!(to_bob["content"], "hi bob");
+
+    let to_alice = alice.expect_event("message-received").await;
+    assert_eq!(to_alice["sender_alias"], "bob");
+    assert_eq!(to_alice["content"], "hi alice");
+}
+
+#[tokio::test]
+async fn retried_messages_are_handled_once() {
+    let alice = TestPeer::start("alice", &[]).await;
+    let mut bob = TestPeer::start("bob", &[]).await;
+
+    // A retry after the first response got lost sends the same message again
+    let message = MessagePayload::new("alice".into(), MessageBody::text("just once".into()));
+    for _ in 0..2 {
+        send_message(
+            alice.sink.clone(),
+            bob.addresses(),
+            bob.port,
+            &message,
+            &RetryPolicy::never(),
+        )
+        .await
+        .unwrap();
+    }
+    let other = MessagePayload::new("alice".into(), MessageBody::text("just once".into()));
+    send_message(
+        alice.sink.clone(),
+        bob.addresses(),
+        bob.port,
+        &other,
+        &RetryPolicy::never(),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(bob.expect_event("message-received").await["id"], message.id);
+    assert_eq!(bob.expect_event("message-received").await["id"], other.id);
+    assert!(bob.events.try_recv().is_err(), "unexpected event");
+}
+
+#[tokio::test]
+async fn messages_carry_images_and_replies() {
+    use reqwest::{Client, StatusCode};
+
+    let mut alice = TestPeer::start("alice", &[]).await;
+    let mut bob = TestPeer::start("bob", &[]).await;
+
+    send_text(
+        alice.sink.clone(),
+        bob.addresses(),
+        bob.port,
+        "send me the photo".into(),
+        "alice".into(),
+        &RetryPolicy::never(),
+    )
+    .await
+    .unwrap();
+    let question = bob.expect_event("message-received").await;
+    assert_eq!(question["type"], "text");
+    let question_id = question["id"].as_str().unwrap().to_string();
+
+    let png = ClipboardContent::image(1, 1, &[0, 0, 0, 255]).unwrap();
+    let ClipboardContent::Image { png } = png else {
+        unreachable!()
+    };
+    let image = InlineImage {
+        mime_type: "image/png".to_string(),
+        data: png,
+    };
+    let reply = MessagePayload::new(
+        "bob".to_string(),
+        MessageBody {
+            kind: MessageKind::Image,
+            content: "here it is".to_string(),
+            image: Some(image.clone()),
+            reply_to: Some(question_id.clone()),
+            ..MessageBody::default()
+        },
+    );
+    send_message(
+        bob.sink.clone(),
+        alice.addresses(),
+        alice.port,
+        &reply,
+        &RetryPolicy::never(),
+    )
+    .await
+    .unwrap();
+    let received: MessagePayload =
+        serde_json::from_value(alice.expect_event("message-received").await).unwrap();
+    assert_eq!(received, reply);
+    assert_eq!(received.body.reply_to, Some(question_id));
+
+    // Peers that only know the old shape are still understood
+    let url = format!("http://127.0.0.1:{}/message", bob.port);
+    let response = Client::new()
+        .post(&url)
+        .json(&serde_json::json!({ "sender_alias": "old", "content": "hello" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let old = bob.expect_event("message-received").await;
+    assert_eq!(old["content"], "hello");
+    assert_eq!(old["type"], "text");
+    assert!(old["id"].is_string());
+
+    // Messages missing what their type needs are refused
+    let response = Client::new()
+        .post(&url)
+        .json(&serde_json::json!({
+            "sender_alias": "broken",
+            "type": "image",
+            "content": "no image here"
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+#[tokio::test]
+async fn links_open_only_from_chosen_devices() {
+    let alice = TestPeer::start("alice", &[]).await;
+    let mut bob = TestPeer::start("bob", &[]).await;
+    let alice_id = alice.config.lock().unwrap().device_id.clone();
+    let (addresses, port) = (bob.addresses(), bob.port);
+    let send = |text: &str| {
+        let message = MessagePayload {
+            device_id: Some(alice_id.clone()),
+            ..MessagePayload::new("alice".to_string(), MessageBody::text(text.to_string()))
+        };
+        let (sink, addresses) = (alice.sink.clone(), addresses.clone());
+        async move { send_message(sink, addresses, port, &message, &RetryPolicy::never()).await }
+    };
+
+    // Marked as a link, but only shown
+    send(" https://example.com/page \n").await.unwrap();
+    let received = bob.expect_event("message-received").await;
+    assert_eq!(received["type"], "url");
+    assert_eq!(received["content"], "https://example.com/page");
+    assert_eq!(received["opened"], false);
+
+    // Chosen, but not discovered where the message comes from
+    bob.config.lock().unwrap().open_links_from = vec![alice_id.clone()];
+    bob.discover(&alice, IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)));
+    send("https://example.com/page").await.unwrap();
+    assert_eq!(bob.expect_event("message-received").await["opened"], false);
+
+    bob.known_peers.0.lock().unwrap().clear();
+    bob.discover(&alice, IpAddr::V4(Ipv4Addr::LOCALHOST));
+    send("https://example.com/page").await.unwrap();
+    assert_eq!(bob.expect_event("message-received").await["opened"], true);
+
+    // Text around a link keeps it a text message
+    send("have a look: https://example.com").await.unwrap();
+    let received = bob.expect_event("message-received").await;
+    assert_eq!(received["type"], "text");
+    assert_eq!(received["opened"], false);
+
+    // Messages without a device ID can't be told apart
+    send_text(
+        alice.sink.clone(),
+        bob.addresses(),
+        bob.port,
+        "https://example.com/other".into(),
+        "alice".into(),
+        &RetryPolicy::never(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(bob.expect_event("message-received").await["opened"], false);
+
+    assert_eq!(
+        *bob.opened_links.0.lock().unwrap(),
+        vec!["https://example.com/page".to_string()]
+    );
+}
+
+#[tokio::test]
+async fn missing_extension_is_inferred_from_content() {
+    let alice = TestPeer::start("alice", &[]).await;
+    let bob = TestPeer::start("bob", &[Answer::Accept, Answer::Accept, Answer::Accept]).await;
+
+    let png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR\0\0\0\x01\0\0\0\x01\x08\x06\0\0\0";
+    alice.send(&bob, "photo", png).await.unwrap();
+
+    let apk = zip_with_entry("AndroidManifest.xml", b"<manifest/>");
+    alice.send(&bob, "package", &apk).await.unwrap();
+
+    // Files that already have an extension are left alone
+    alice.send(&bob, "notes.md", png).await.unwrap();
+
+    assert_eq!(
+        bob.downloaded_files(),
+        vec!["notes.md", "package.apk", "photo.png"]
+    );
+}
+
+#[tokio::test]
+async fn declared_mime_type_is_trusted() {
+    use reqwest::{multipart, Client};
+
+    let alice = TestPeer::start("alice", &[]).await;
+    let mut bob = TestPeer::start("bob", &[Answer::Accept, Answer::Accept]).await;
+
+    // The sender looks at the content of files whose name says nothing
+    let outgoing = TempDir::new("alice-out");
+    let path = outgoing.0.join("scan");
+    std::fs::write(&path, b"%PDF-1.7\n%%EOF\n").unwrap();
+    let source = open_file(&path.to_string_lossy()).await.unwrap();
+    send_file(
+        alice.sink.clone(),
+        bob.addresses(),
+        bob.port,
+        source,
+        &RetryPolicy::never(),
+        Throttle::unlimited(),
+    )
+    .await
+    .unwrap();
+    let complete = bob.expect_event("file-receive-complete").await;
+    assert_eq!(complete["mime_type"], "application/pdf");
+
+    // and the receiver goes with what it was told
+    let form = multipart::Form::new().part(
+        "file",
+        multipart::Part::bytes(vec![1, 2, 3])
+            .file_name("capture.bin")
+            .mime_str("image/heic")
+            .unwrap(),
+    );
+    let response = Client::new()
+        .post(format!("http://127.0.0.1:{}/upload", bob.port))
+        .multipart(form)
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success(), "{}", response.status());
+    let complete = bob.expect_event("file-receive-complete").await;
+    assert_eq!(complete["mime_type"], "image/heic");
+}
+
+#[tokio::test]
+async fn one_file_fans_out_to_several_peers() {
+    let mut alice = TestPeer::start("alice", &[]).await;
+    let bob = TestPeer::start("bob", &[Answer::Accept]).await;
+    let carol = TestPeer::start("carol", &[Answer::Reject]).await;
+    let dave = TestPeer::start("dave", &[Answer::Accept]).await;
+
+    let outgoing = TempDir::new("alice-out");
+    let path = outgoing.0.join("build.zip");
+    let contents: Vec<u8> = (0..200_000u32).map(|i| i as u8).collect();
+    std::fs::write(&path, &contents).unwrap();
+
+    let queue = TransferQueue::new(
+        alice.sink.clone(),
+        Handle::current(),
+        AppConfig::default().queue_limits(),
+    );
+    let source = open_file(&path.to_string_lossy()).await.unwrap();
+    let summary = send_files_to_peers(
+        alice.sink.clone(),
+        &queue,
+        &[bob.target(), carol.target(), dave.target()],
+        vec![source],
+        &RetryPolicy::default(),
+        &alice.bandwidth,
+    )
+    .await;
+
+    assert_eq!((summary.succeeded, summary.failed), (2, 1));
+    let outcomes = &summary.outcomes;
+    assert_eq!(outcomes[0].peer, bob.target().key());
+    assert!(outcomes[0].error.is_none(), "{:?}", outcomes[0]);
+    assert!(
+        matches!(outcomes[1].error, Some(Error::Rejected(_))),
+        "{:?}",
+        outcomes[1]
+    );
+    assert!(outcomes[2].error.is_none(), "{:?}", outcomes[2]);
+    assert_eq!(bob.read("build.zip"), contents);
+    assert_eq!(dave.read("build.zip"), contents);
+    assert!(carol.downloaded_files().is_empty());
+
+    // Each upload is a transfer of its own in the queue
+    let queued = alice.expect_event("queue-update").await;
+    assert_eq!(queued[0]["peer"], bob.target().key(), "{}", queued);
+    assert!(queue.items().is_empty());
+
+    // Progress is reported separately for each peer
+    let progress = alice.expect_event("transfer-progress").await;
+    assert_eq!(progress["transfer_id"], "build.zip");
+    assert!(progress["peer"].is_string(), "{}", progress);
+}
+
+#[tokio::test]
+async fn peers_go_through_the_files_at_their_own_pace() {
+    let alice = TestPeer::start("alice", &[]).await;
+    let mut bob = TestPeer::start("bob", &[Answer::Accept, Answer::Accept]).await;
+    // Keeps alice waiting on the first file
+    let carol = TestPeer::start_with_timeout(
+        "carol",
+        &[Answer::Ignore, Answer::Accept],
+        Duration::from_secs(2),
+    )
+    .await;
+
+    let outgoing = TempDir::new("alice-out");
+    let mut sources = Vec::new();
+    for name in ["one.txt", "two.txt"] {
+        let path = outgoing.0.join(name);
+        std::fs::write(&path, name).unwrap();
+        sources.push(open_file(&path.to_string_lossy()).await.unwrap());
+    }
+    let queue = TransferQueue::new(
+        alice.sink.clone(),
+        Handle::current(),
+        AppConfig::default().queue_limits(),
+    );
+
+    let (sink, peers, bandwidth) = (
+        alice.sink.clone(),
+        vec![bob.target(), carol.target()],
+        alice.bandwidth.clone(),
+    );
+    let started = Instant::now();
+    let sending = tokio::spawn(async move {
+        send_files_to_peers(
+            sink,
+            &queue,
+            &peers,
+            sources,
+            &RetryPolicy::never(),
+            &bandwidth,
+        )
+        .await
+    });
+
+    // Bob has both files while carol still hasn't answered about the first
+    bob.expect_event("file-receive-complete").await;
+    bob.expect_event("file-receive-complete").await;
+    assert_eq!(bob.read("two.txt"), b"two.txt");
+    assert!(started.elapsed() < Duration::from_secs(2));
+    assert!(!sending.is_finished());
+    assert!(carol.downloaded_files().is_empty());
+
+    let summary = sending.await.unwrap();
+    assert_eq!((summary.succeeded, summary.failed), (3, 1));
+    assert_eq!(carol.read("two.txt"), b"two.txt");
+}
+
+#[tokio::test]
+async fn broadcast_reports_each_peer() {
+    let alice = TestPeer::start("alice", &[]).await;
+    let mut bob = TestPeer::start("bob", &[]).await;
+    let mut carol = TestPeer::start("carol", &[]).await;
+
+    let closed_port = {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        listener.local_addr().unwrap().port()
+    };
+    let gone = PeerTarget {
+        port: closed_port,
+        ..bob.target()
+    };
+
+    let results = broadcast_text(
+        alice.sink.clone(),
+        &[bob.target(), gone, carol.target()],
+        "build 1.4.2 is ready",
+        "alice",
+        &RetryPolicy::never(),
+    )
+    .await;
+
+    assert!(results[0].is_ok(), "{:?}", results[0]);
+    assert!(
+        matches!(results[1], Err(Error::Network(_))),
+        "{:?}",
+        results[1]
+    );
+    assert!(results[2].is_ok(), "{:?}", results[2]);
+    for peer in [&mut bob, &mut carol] {
+        let message = peer.expect_event("message-received").await;
+        assert_eq!(message["sender_alias"], "alice");
+        assert_eq!(message["content"], "build 1.4.2 is ready");
+    }
+}
+
+#[tokio::test]
+async fn network_errors_are_retried() {
+    let mut alice = TestPeer::start("alice", &[]).await;
+
+    // Nothing listens on a port we just released
+    let closed_port = {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        listener.local_addr().unwrap().port()
+    };
+    let retry = RetryPolicy {
+        max_attempts: 3,
+        initial_delay_ms: 10,
+        max_delay_ms: 20,
+        multiplier: 2.0,
+        ..RetryPolicy::default()
+    };
+
+    let result = send_text(
+        alice.sink.clone(),
+        alice.addresses(),
+        closed_port,
+        "anyone?".into(),
+        "alice".into(),
+        &retry,
+    )
+    .await;
+
+    assert!(matches!(result, Err(Error::Network(_))), "{:?}", result);
+    for attempt in 2..=3 {
+        let retrying = alice.expect_event("transfer-retrying").await;
+        assert_eq!(retrying["attempt"], attempt);
+        assert_eq!(retrying["error"]["kind"], "network");
+        assert_eq!(retrying["name"], "message");
+        assert_eq!(retrying["peer"], format!("127.0.0.1:{}", closed_port));
+    }
+}
+
+#[tokio::test]
+async fn parallel_retries_are_told_apart() {
+    let mut alice = TestPeer::start("alice", &[]).await;
+
+    let mut gone = Vec::new();
+    for _ in 0..2 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        gone.push(PeerTarget {
+            ip: "127.0.0.1".to_string(),
+            port: listener.local_addr().unwrap().port(),
+            addresses: Vec::new(),
+        });
+    }
+    let outgoing = TempDir::new("alice-out");
+    let path = outgoing.0.join("twice.txt");
+    std::fs::write(&path, b"to nobody").unwrap();
+    let source = open_file(&path.to_string_lossy()).await.unwrap();
+    let queue = TransferQueue::new(
+        alice.sink.clone(),
+        Handle::current(),
+        AppConfig::default().queue_limits(),
+    );
+    let retry = RetryPolicy {
+        max_attempts: 2,
+        initial_delay_ms: 10,
+        ..RetryPolicy::default()
+    };
+
+    send_files_to_peers(
+        alice.sink.clone(),
+        &queue,
+        &gone,
+        vec![source],
+        &retry,
+        &alice.bandwidth,
+    )
+    .await;
+
+    let first = alice.expect_event("transfer-retrying").await;
+    let second = alice.expect_event("transfer-retrying").await;
+    assert_eq!(first["name"], "twice.txt");
+    assert_eq!(second["name"], "twice.txt");
+    assert_ne!(first["transfer_id"], second["transfer_id"]);
+    let mut peers = vec![first["peer"].clone(), second["peer"].clone()];
+    peers.sort_by_key(|peer| peer.to_string());
+    let mut expected: Vec<Value> = gone.iter().map(|peer| peer.key().into()).collect();
+    expected.sort_by_key(|peer| peer.to_string());
+    assert_eq!(peers, expected);
+}
+
+#[tokio::test]
+async fn timed_out_requests_are_retried() {
+    let mut alice = TestPeer::start("alice", &[]).await;
+
+    // Accepts connections but never answers
+    let silent = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/ping", silent.local_addr().unwrap());
+    tokio::spawn(async move {
+        let mut held = Vec::new();
+        while let Ok((stream, _)) = silent.accept().await {
+            held.push(stream);
+        }
+    });
+    let retry = RetryPolicy {
+        max_attempts: 3,
+        initial_delay_ms: 10,
+        max_delay_ms: 20,
+        multiplier: 2.0,
+        ..RetryPolicy::default()
+    };
+
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_millis(200))
+        .build()
+        .unwrap();
+    let attempts = Mutex::new(0);
+    let transfer = RetriedTransfer {
+        transfer_id: "silent-1",
+        name: "silent",
+        peer: "127.0.0.1",
+    };
+    let result = with_retry(&retry, &alice.sink, transfer, |_| {
+        *attempts.lock().unwrap() += 1;
+        let request = client.get(&url).send();
+        async move {
+            request
+                .await
+                .map(|_| ())
+                .map_err(|e| Error::request("Request failed", e))
+        }
+    })
+    .await;
+
+    assert!(matches!(result, Err(Error::Network(_))), "{:?}", result);
+    assert_eq!(*attempts.lock().unwrap(), 3);
+    let retrying = alice.expect_event("transfer-retrying").await;
+    assert_eq!(retrying["error"]["kind"], "network");
+}
+
+#[tokio::test]
+async fn rejection_is_not_retried() {
+    let mut alice = TestPeer::start("alice", &[]).await;
+    let bob = TestPeer::start("bob", &[Answer::Reject, Answer::Accept]).await;
+
+    let outgoing = TempDir::new("alice-out");
+    let path = outgoing.0.join("once.txt");
+    std::fs::write(&path, b"no means no").unwrap();
+    let source = open_file(&path.to_string_lossy()).await.unwrap();
+    let result = send_file(
+        alice.sink.clone(),
+        bob.addresses(),
+        bob.port,
+        source,
+        &RetryPolicy::default(),
+        Throttle::unlimited(),
+    )
+    .await;
+
+    assert!(matches!(result, Err(Error::Rejected(_))), "{:?}", result);
+    assert!(bob.downloaded_files().is_empty());
+    assert!(alice.events.try_recv().is_err(), "unexpected event");
+}
+
+#[tokio::test]
+async fn save_failure_is_not_retried() {
+    let alice = TestPeer::start("alice", &[]).await;
+    let bob = TestPeer::start("bob", &[Answer::Accept, Answer::Accept]).await;
+
+    // Nothing can be saved where a file is in the way
+    std::fs::remove_dir_all(&bob.downloads.0).unwrap();
+    std::fs::write(&bob.downloads.0, b"").unwrap();
+
+    let outgoing = TempDir::new("alice-out");
+    let path = outgoing.0.join("doomed.txt");
+    std::fs::write(&path, b"nowhere to go").unwrap();
+    let source = open_file(&path.to_string_lossy()).await.unwrap();
+    let result = send_file(
+        alice.sink.clone(),
+        bob.addresses(),
+        bob.port,
+        source,
+        &RetryPolicy::default(),
+        Throttle::unlimited(),
+    )
+    .await;
+
+    std::fs::remove_file(&bob.downloads.0).unwrap();
+    std::fs::create_dir(&bob.downloads.0).unwrap();
+
+    assert!(matches!(result, Err(Error::Internal(_))), "{:?}", result);
+    // Asked once, not again for every retry
+    assert_eq!(bob.confirmation.answers.lock().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn interrupted_upload_is_resumed() {
+    use futures::{stream, StreamExt};
+    use reqwest::{multipart, Body, Client};
+
+    let mut bob = TestPeer::start("bob", &[Answer::Accept, Answer::Accept]).await;
+    let contents: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+    let upload_url = format!("http://127.0.0.1:{}/upload", bob.port);
+
+    let form = |body: Body, offset: usize| {
+        multipart::Form::new()
+            .text("size", contents.len().to_string())
+            .text("resume_key", "resume-test")
+            .text("offset", offset.to_string())
+            .part("file", multipart::Part::stream(body).file_name("big.bin"))
+    };
+
+    // The connection breaks half way through, once bob is receiving
+    let (started_tx, started_rx) = futures::channel::oneshot::channel::<()>();
+    let half = contents[..50_000].to_vec();
+    let broken = stream::iter(vec![Ok(half)]).chain(stream::once(async move {
+        let _ = started_rx.await;
+        Err(std::io::Error::other("cable pulled"))
+    }));
+    let client = Client::new();
+    let (first, _) = tokio::join!(
+        client
+            .post(&upload_url)
+            .multipart(form(Body::wrap_stream(broken), 0))
+            .send(),
+        async {
+            bob.expect_event("file-receive-start").await;
+            let _ = started_tx.send(());
+        }
+    );
+    assert!(first.is_err() || !first.unwrap().status().is_success());
+    bob.expect_event("file-receive-interrupted").await;
+
+    // Whatever arrived is kept for the sender to continue from
+    let status: Value = client
+        .get(format!("{}/resume-test", upload_url))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let received = status["received"].as_u64().unwrap() as usize;
+    assert!(received <= 50_000, "{}", received);
+
+    // Resuming skips confirmation, so nobody else can
+    let impostor = Client::builder()
+        .local_address(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2)))
+        .build()
+        .unwrap();
+    let peek = impostor
+        .get(format!("{}/resume-test", upload_url))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(peek.status(), reqwest::StatusCode::NOT_FOUND);
+    let hijack = impostor
+        .post(&upload_url)
+        .multipart(form(Body::from(vec![0u8; 10]), received))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(hijack.status(), reqwest::StatusCode::CONFLICT);
+
+    let rest = contents[received..].to_vec();
+    let second = client
+        .post(&upload_url)
+        .multipart(form(Body::from(rest), received))
+        .send()
+        .await
+        .unwrap();
+    assert!(second.status().is_success(), "{}", second.status());
+
+    assert_eq!(bob.downloaded_files(), vec!["big.bin"]);
+    assert_eq!(bob.read("big.bin"), contents);
+}
+
+#[tokio::test]
+async fn downloads_are_rate_limited() {
+    let alice = TestPeer::start("alice", &[]).await;
+    let mut bob = TestPeer::start("bob", &[Answer::Accept]).await;
+    bob.bandwidth.set_limits(BandwidthLimits {
+        download: 200_000,
+        ..BandwidthLimits::default()
+    });
+
+    // About a second and a half at the limit
+    let contents = vec![7u8; 300_000];
+    let started = Instant::now();
+    alice.send(&bob, "slow.bin", &contents).await.unwrap();
+    bob.expect_event("file-receive-complete").await;
+
+    assert!(
+        started.elapsed() >= Duration::from_secs(1),
+        "{:?}",
+        started.elapsed()
+    );
+    assert_eq!(bob.read("slow.bin"), contents);
+}
+
+#[tokio::test]
+async fn throttled_upload_may_outlast_the_idle_timeout() {
+    let alice = TestPeer::start("alice", &[]).await;
+    let mut bob = TestPeer::start("bob", &[Answer::Accept]).await;
+    alice.bandwidth.set_limits(BandwidthLimits {
+        upload_per_peer: 100_000,
+        ..BandwidthLimits::default()
+    });
+
+    // About two seconds at the limit, four times the idle timeout
+    let contents = vec![7u8; 300_000];
+    let outgoing = TempDir::new("alice-out");
+    let path = outgoing.0.join("patient.bin");
+    std::fs::write(&path, &contents).unwrap();
+    let source = open_file(&path.to_string_lossy()).await.unwrap();
+    let retry = RetryPolicy {
+        idle_timeout_ms: 500,
+        ..RetryPolicy::never()
+    };
+
+    let started = Instant::now();
+    send_file(
+        alice.sink.clone(),
+        bob.addresses(),
+        bob.port,
+        source,
+        &retry,
+        alice
+            .bandwidth
+            .throttle(Direction::Send, &bob.target().key()),
+    )
+    .await
+    .unwrap();
+
+    bob.expect_event("file-receive-complete").await;
+    assert!(
+        started.elapsed() >= Duration::from_secs(1),
+        "{:?}",
+        started.elapsed()
+    );
+    assert_eq!(bob.read("patient.bin"), contents);
+}
+
+#[tokio::test]
+async fn raising_a_limit_speeds_up_a_running_send() {
+    let alice = TestPeer::start("alice", &[]).await;
+    let mut bob = TestPeer::start("bob", &[Answer::Accept]).await;
+    alice.bandwidth.set_limits(BandwidthLimits {
+        upload_per_peer: 10_000,
+        ..BandwidthLimits::default()
+    });
+
+    // Would take about 20 seconds at the initial limit
+    let contents = vec![7u8; 200_000];
+    let started = Instant::now();
+    let (result, _) = tokio::join!(alice.send(&bob, "big.bin", &contents), async {
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        alice.bandwidth.set_limits(BandwidthLimits::default());
+    });
+
+    result.unwrap();
+    bob.expect_event("file-receive-complete").await;
+    assert!(
+        started.elapsed() < Duration::from_secs(5),
+        "{:?}",
+        started.elapsed()
+    );
+    assert_eq!(bob.read("big.bin"), contents);
+}
+
+#[tokio::test]
+async fn staged_file_is_sent_in_one_piece() {
+    let alice = TestPeer::start("alice", &[]).await;
+    let mut bob = TestPeer::start("bob", &[Answer::Accept]).await;
+    let staging_dir = TempDir::new("alice-staged");
+    let staged = StagedFiles::new(staging_dir.0.clone());
+
+    let id = staged.open("notes.txt".into()).await.unwrap();
+    assert_eq!(staged.append(&id, b"first chunk, ").await.unwrap(), 13);
+    assert_eq!(staged.append(&id, b"second chunk").await.unwrap(), 25);
+    let (source, path) = staged.finish(&id).await.unwrap();
+    assert_eq!(source.size, 25);
+
+    // Finished files can't be appended to anymore
+    assert!(matches!(
+        staged.append(&id, b"late").await,
+        Err(Error::NotFound(_))
+    ));
+
+    send_file(
+        alice.sink.clone(),
+        bob.addresses(),
+        bob.port,
+        source,
+        &RetryPolicy::never(),
+        Throttle::unlimited(),
+    )
+    .await
+    .unwrap();
+    staging::remove(&path).await;
+
+    bob.expect_event("file-receive-complete").await;
+    assert_eq!(bob.read("notes.txt"), b"first chunk, second chunk");
+    assert!(!path.exists());
+}
+
+#[tokio::test]
+async fn clipboard_is_shown_or_written_as_configured() {
+    let alice = TestPeer::start("alice", &[]).await;
+    let mut bob = TestPeer::start("bob", &[]).await;
+    let (sink, addresses, port) = (bob.sink.clone(), bob.addresses(), bob.port);
+    let device_id = alice.config.lock().unwrap().device_id.clone();
+    let retry = RetryPolicy::never();
+    let send = |content: ClipboardContent| {
+        send_clipboard(
+            sink.clone(),
+            addresses.clone(),
+            port,
+            content,
+            "alice".to_string(),
+            device_id.clone(),
+            &retry,
+        )
+    };
+    let text = ClipboardContent::Text {
+        text: "https://example.com".to_string(),
+    };
+
+    // Only shown by default
+    send(text.clone()).await.unwrap();
+    let received = bob.expect_event("clipboard-received").await;
+    assert_eq!(received["sender_alias"], "alice");
+    assert_eq!(received["kind"], "text");
+    assert_eq!(received["text"], "https://example.com");
+    assert_eq!(received["written"], false);
+    assert!(bob.clipboard.read().is_err());
+
+    // Not written while alice hasn't been discovered, since anyone on the
+    // network could be sending
+    bob.config.lock().unwrap().clipboard.write_received = true;
+    send(text.clone()).await.unwrap();
+    assert_eq!(
+        bob.expect_event("clipboard-received").await["written"],
+        false
+    );
+    assert!(bob.clipboard.read().is_err());
+
+    bob.discover(&alice, "127.0.0.1".parse().unwrap());
+    send(text.clone()).await.unwrap();
+    assert_eq!(
+        bob.expect_event("clipboard-received").await["written"],
+        true
+    );
+    assert_eq!(bob.clipboard.read().unwrap(), text);
+
+    // Images arrive pixel for pixel
+    let rgba = vec![255, 0, 0, 255, 0, 0, 255, 128];
+    send(ClipboardContent::image(2, 1, &rgba).unwrap())
+        .await
+        .unwrap();
+    let received = bob.expect_event("clipboard-received").await;
+    assert_eq!(received["kind"], "image");
+    let ClipboardContent::Image { png } = bob.clipboard.read().unwrap() else {
+        panic!("no image on the clipboard");
+    };
+    let image = RgbaImage::from_png(&png).unwrap();
+    assert_eq!((image.width, image.height), (2, 1));
+    assert_eq!(image.rgba, rgba);
+}
+
+#[tokio::test]
+async fn clipboard_syncs_only_from_sync_devices() {
+    let alice = TestPeer::start("alice", &[]).await;
+    let mut bob = TestPeer::start("bob", &[]).await;
+    let (addresses, port) = (bob.addresses(), bob.port);
+    let text = |text: &str| ClipboardContent::Text {
+        text: text.to_string(),
+    };
+    let sync = |from: &TestPeer, content: ClipboardContent| {
+        let message = ClipboardSyncMessage {
+            device_id: from.config.lock().unwrap().device_id.clone(),
+            sender_alias: from.alias.clone(),
+            content,
+        };
+        let addresses = addresses.clone();
+        async move { sync_clipboard(addresses, port, &message).await }
+    };
+
+    // Sync is off
+    let err = sync(&alice, text("hello")).await.unwrap_err();
+    assert!(matches!(err, Error::Rejected(_)), "{:?}", err);
+    assert!(bob.clipboard.read().is_err());
+
+    {
+        let mut config = bob.config.lock().unwrap();
+        config.clipboard.sync = true;
+        config.clipboard.sync_peers = vec![alice.config.lock().unwrap().device_id.clone()];
+    }
+    // Not discovered yet, so anyone could be claiming alice's ID
+    let err = sync(&alice, text("hello")).await.unwrap_err();
+    assert!(matches!(err, Error::Rejected(_)), "{:?}", err);
+    assert!(bob.clipboard.read().is_err());
+
+    bob.discover(&alice, IpAddr::V4(Ipv4Addr::LOCALHOST));
+    sync(&alice, text("hello")).await.unwrap();
+    let synced = bob.expect_event("clipboard-synced").await;
+    assert_eq!(synced["sender_alias"], "alice");
+    assert_eq!(synced["kind"], "text");
+    let hello = text("hello");
+    assert_eq!(bob.clipboard.read().unwrap(), hello);
+
+    // What came in isn't taken for a local change
+    assert_eq!(
+        bob.last_clipboard
+            .read_changed(bob.clipboard.as_ref())
+            .unwrap(),
+        None
+    );
+    let image = ClipboardContent::image(1, 2, &[0, 255, 0, 255, 0, 0, 255, 64]).unwrap();
+    sync(&alice, image.clone()).await.unwrap();
+    assert_eq!(bob.expect_event("clipboard-synced").await["kind"], "image");
+    assert_eq!(
+        bob.last_clipboard
+            .read_changed(bob.clipboard.as_ref())
+            .unwrap(),
+        None
+    );
+    // Nor written again when it arrives a second time
+    sync(&alice, image).await.unwrap();
+    sync(&alice, hello.clone()).await.unwrap();
+    assert_eq!(bob.expect_event("clipboard-synced").await["kind"], "text");
+
+    // Other devices are refused
+    let carol = TestPeer::start("carol", &[]).await;
+    let err = sync(&carol, text("from carol")).await.unwrap_err();
+    assert!(matches!(err, Error::Rejected(_)), "{:?}", err);
+    assert_eq!(bob.clipboard.read().unwrap(), hello);
+
+    bob.config.lock().unwrap().clipboard.sync_max_bytes = 4;
+    let err = sync(&alice, text("too long")).await.unwrap_err();
+    assert!(matches!(err, Error::InvalidInput(_)), "{:?}", err);
+    assert_eq!(bob.clipboard.read().unwrap(), hello);
+}
+
+#[tokio::test]
+async fn clipboard_watcher_pushes_local_changes_only() {
+    let alice = TestPeer::start("alice", &[]).await;
+    let text = |text: &str| ClipboardContent::Text {
+        text: text.to_string(),
+    };
+    alice.clipboard.write(&text("before sync")).unwrap();
+    {
+        let mut config = alice.config.lock().unwrap();
+        config.clipboard.sync = true;
+        config.clipboard.sync_peers = vec!["bob-id".to_string()];
+        config.clipboard.sync_max_bytes = 16;
+    }
+
+    let (pushed_tx, mut pushed) = tokio::sync::mpsc::unbounded_channel();
+    tokio::spawn(clipboard::watch(
+        alice.clipboard.clone(),
+        alice.last_clipboard.clone(),
+        alice.config.clone(),
+        move |content, device_ids| {
+            pushed_tx.send((content, device_ids)).unwrap();
+            future::ready(())
+        },
+    ));
+    let settle = || tokio::time::sleep(SYNC_POLL_INTERVAL * 2);
+
+    // What was there before sync started isn't pushed
+    settle().await;
+    assert!(pushed.try_recv().is_err());
+
+    alice.clipboard.write(&text("copied")).unwrap();
+    settle().await;
+    assert_eq!(
+        pushed.try_recv().unwrap(),
+        (text("copied"), vec!["bob-id".to_string()])
+    );
+    assert!(pushed.try_recv().is_err());
+
+    // Content from a peer isn't pushed back
+    alice
+        .last_clipboard
+        .write(alice.clipboard.as_ref(), &text("from bob"))
+        .unwrap();
+    // Too large to sync
+    settle().await;
+    alice
+        .clipboard
+        .write(&text("a longer piece of text"))
+        .unwrap();
+    settle().await;
+    assert!(pushed.try_recv().is_err());
+}
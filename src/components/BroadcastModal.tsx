@@ -0,0 +1,133 @@
+import { useEffect, useState } from "react";
+import {
+  Modal,
+  Button,
+  Select,
+  Stack,
+  Text,
+  Textarea,
+  Group,
+} from "@mantine/core";
+import { IconSpeakerphone } from "@tabler/icons-react";
+import { invoke } from "@tauri-apps/api/core";
+import { notifications } from "@mantine/notifications";
+import { AppError, errorMessage } from "../errors";
+
+// Mirrors `BroadcastSummary` in src-tauri/src/transfer.rs
+interface BroadcastSummary {
+  outcomes: { peer: string; alias: string; error: AppError | null }[];
+  delivered: number;
+  failed: number;
+}
+
+interface BroadcastModalProps {
+  opened: boolean;
+  onClose: () => void;
+}
+
+// Select can't hold null as an option value
+const ALL_PEERS = "";
+
+export default function BroadcastModal({
+  opened,
+  onClose,
+}: BroadcastModalProps) {
+  const [text, setText] = useState("");
+  const [group, setGroup] = useState(ALL_PEERS);
+  const [groups, setGroups] = useState<string[]>([]);
+  const [sending, setSending] = useState(false);
+  const [summary, setSummary] = useState<BroadcastSummary | null>(null);
+
+  // Groups are edited in the settings, so pick up changes on every open
+  useEffect(() => {
+    if (!opened) return;
+    setSummary(null);
+    invoke<{ peer_groups: Record<string, string[]> }>("get_settings")
+      .then((settings) => setGroups(Object.keys(settings.peer_groups)))
+      .catch((e) => console.error("Failed to load groups:", e));
+  }, [opened]);
+
+  const handleBroadcast = async () => {
+    setSending(true);
+    setSummary(null);
+    try {
+      const result = await invoke<BroadcastSummary>("broadcast_text", {
+        text,
+        group: group === ALL_PEERS ? null : group,
+      });
+      setSummary(result);
+      notifications.show({
+        title: result.failed ? "Sent with errors" : "Sent",
+        message: `${result.delivered} delivered, ${result.failed} failed`,
+        color: result.failed ? "yellow" : "green",
+      });
+      if (!result.failed) {
+        setText("");
+      }
+    } catch (e) {
+      notifications.show({
+        title: "Error",
+        message: `Failed to broadcast: ${errorMessage(e)}`,
+        color: "red",
+      });
+    } finally {
+      setSending(false);
+    }
+  };
+
+  return (
+    <Modal
+      opened={opened}
+      onClose={onClose}
+      title={
+        <Text size="sm" c="dimmed" tt="uppercase" fw={600}>
+          Message Everyone
+        </Text>
+      }
+      centered
+    >
+      <Stack gap="md">
+        <Select
+          label="Send to"
+          data={[
+            { value: ALL_PEERS, label: "All nearby devices" },
+            ...groups.map((name) => ({ value: name, label: name })),
+          ]}
+          value={group}
+          onChange={(val) => setGroup(val ?? ALL_PEERS)}
+          allowDeselect={false}
+        />
+        <Textarea
+          placeholder="Build 1.4.2 is ready"
+          value={text}
+          onChange={(e) => setText(e.currentTarget.value)}
+          minRows={3}
+          autosize
+        />
+
+        {summary && summary.failed > 0 && (
+          <Stack gap={4}>
+            {summary.outcomes
+              .filter((outcome) => outcome.error)
+              .map((outcome) => (
+                <Text key={outcome.peer} size="sm" c="red">
+                  {outcome.alias}: {errorMessage(outcome.error)}
+                </Text>
+              ))}
+          </Stack>
+        )}
+
+        <Group justify="flex-end">
+          <Button
+            leftSection={<IconSpeakerphone size={18} />}
+            onClick={handleBroadcast}
+            loading={sending}
+            disabled={!text.trim()}
+          >
+            Send to All
+          </Button>
+        </Group>
+      </Stack>
+    </Modal>
+  );
+}
@@ -0,0 +1,96 @@
+import { Modal, Text, Button, Stack } from "@mantine/core";
+import { IconCopy } from "@tabler/icons-react";
+
+// Mirrors `ConflictResolution` in src-tauri/src/conflict.rs
+export type ConflictResolution = "rename" | "overwrite" | "skip";
+
+interface FileConflictModalProps {
+  opened: boolean;
+  onResolve: (resolution: ConflictResolution) => void;
+  fileName: string;
+  identical: boolean;
+}
+
+export default function FileConflictModal({
+  opened,
+  onResolve,
+  fileName,
+  identical,
+}: FileConflictModalProps) {
+  return (
+    <Modal
+      opened={opened}
+      onClose={() => onResolve("rename")}
+      title={
+        <Text fw={700} size="1.25rem" style={{ color: "var(--text-primary)" }}>
+          File Already Exists
+        </Text>
+      }
+      centered
+      radius="lg"
+      closeOnClickOutside={false}
+      size="auto"
+      overlayProps={{
+        backgroundOpacity: 0.75,
+      }}
+      styles={{
+        content: {
+          backgroundColor: "var(--bg-light)",
+          border: "1px solid var(--border-subtle)",
+          maxWidth: "min(90vw, 500px)",
+          boxShadow: "var(--shadow-l)",
+        },
+        header: {
+          background: "var(--bg-light)",
+        },
+      }}
+    >
+      <Stack gap="lg">
+        <Stack gap="xs" align="center">
+          <IconCopy size={40} color="var(--accent-primary-light)" />
+          <Text
+            fw={600}
+            ta="center"
+            style={{ wordBreak: "break-word", overflowWrap: "break-word" }}
+          >
+            {fileName}
+          </Text>
+          <Text size="sm" c="dimmed" ta="center">
+            {identical
+              ? "An identical file is already in your downloads."
+              : "A different file with this name is already in your downloads."}
+          </Text>
+        </Stack>
+
+        <Stack gap="sm">
+          <Button
+            size="md"
+            fullWidth
+            className="depth-button-primary"
+            onClick={() => onResolve("rename")}
+          >
+            Keep Both
+          </Button>
+          <Button
+            size="md"
+            fullWidth
+            variant="light"
+            color="orange"
+            onClick={() => onResolve("overwrite")}
+          >
+            Replace
+          </Button>
+          <Button
+            size="md"
+            fullWidth
+            variant="light"
+            className="depth-button-secondary"
+            onClick={() => onResolve("skip")}
+          >
+            Skip
+          </Button>
+        </Stack>
+      </Stack>
+    </Modal>
+  );
+}
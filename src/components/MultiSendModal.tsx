@@ -0,0 +1,156 @@
+import { useEffect, useState } from "react";
+import {
+  Modal,
+  Button,
+  Checkbox,
+  Stack,
+  Text,
+  Progress,
+  Group,
+} from "@mantine/core";
+import { IconFile } from "@tabler/icons-react";
+import { invoke } from "@tauri-apps/api/core";
+import { listen } from "@tauri-apps/api/event";
+import { notifications } from "@mantine/notifications";
+import { AppError, errorMessage } from "../errors";
+
+interface Peer {
+  ip: string;
+  addresses: string[];
+  port: number;
+  alias: string;
+}
+
+// Mirrors `FanOutSummary` in src-tauri/src/transfer.rs
+interface FanOutSummary {
+  outcomes: { peer: string; file_name: string; error: AppError | null }[];
+  succeeded: number;
+  failed: number;
+}
+
+interface MultiSendModalProps {
+  opened: boolean;
+  onClose: () => void;
+  peers: Peer[];
+  pickFiles: () => Promise<string[]>;
+}
+
+const peerKey = (peer: Peer) => `${peer.ip}:${peer.port}`;
+
+export default function MultiSendModal({
+  opened,
+  onClose,
+  peers,
+  pickFiles,
+}: MultiSendModalProps) {
+  const [selected, setSelected] = useState<string[]>([]);
+  const [sending, setSending] = useState(false);
+  // Percent of the current file per peer key
+  const [progress, setProgress] = useState<Record<string, number>>({});
+  const [summary, setSummary] = useState<FanOutSummary | null>(null);
+
+  useEffect(() => {
+    const unlisten = listen("transfer-progress", (event: any) => {
+      const { peer, current_bytes, total_bytes } = event.payload;
+      if (!peer) return;
+      const percent = total_bytes
+        ? Math.round((current_bytes / total_bytes) * 100)
+        : 0;
+      setProgress((prev) => ({ ...prev, [peer]: percent }));
+    });
+    return () => {
+      unlisten.then((f) => f());
+    };
+  }, []);
+
+  const handleSend = async () => {
+    const filePaths = await pickFiles();
+    if (filePaths.length === 0) return;
+
+    setSending(true);
+    setSummary(null);
+    setProgress({});
+    try {
+      const result = await invoke<FanOutSummary>("send_files_to_peers", {
+        filePaths,
+        peers: peers.filter((peer) => selected.includes(peerKey(peer))),
+      });
+      setSummary(result);
+      notifications.show({
+        title: result.failed ? "Sent with errors" : "Sent",
+        message: `${result.succeeded} delivered, ${result.failed} failed`,
+        color: result.failed ? "yellow" : "green",
+      });
+    } catch (e) {
+      notifications.show({
+        title: "Error",
+        message: `Failed to send: ${errorMessage(e)}`,
+        color: "red",
+      });
+    } finally {
+      setSending(false);
+    }
+  };
+
+  const aliasFor = (key: string) =>
+    peers.find((peer) => peerKey(peer) === key)?.alias ?? key;
+
+  return (
+    <Modal
+      opened={opened}
+      onClose={onClose}
+      title={
+        <Text size="sm" c="dimmed" tt="uppercase" fw={600}>
+          Send to Several Devices
+        </Text>
+      }
+      centered
+    >
+      <Stack gap="md">
+        <Checkbox.Group value={selected} onChange={setSelected}>
+          <Stack gap="xs">
+            {peers.map((peer) => {
+              const key = peerKey(peer);
+              return (
+                <div key={key}>
+                  <Checkbox value={key} label={peer.alias} />
+                  {sending && selected.includes(key) && (
+                    <Progress value={progress[key] ?? 0} mt={4} size="sm" />
+                  )}
+                </div>
+              );
+            })}
+          </Stack>
+        </Checkbox.Group>
+
+        {summary && summary.failed > 0 && (
+          <Stack gap={4}>
+            {summary.outcomes
+              .filter((outcome) => outcome.error)
+              .map((outcome) => (
+                <Text
+                  key={`${outcome.peer}/${outcome.file_name}`}
+                  size="sm"
+                  c="red"
+                >
+                  {outcome.file_name} → {aliasFor(outcome.peer)}:{" "}
+                  {errorMessage(outcome.error)}
+                </Text>
+              ))}
+          </Stack>
+        )}
+
+        <Group justify="flex-end">
+          <Button
+            leftSection={<IconFile size={18} />}
+            onClick={handleSend}
+            loading={sending}
+            disabled={selected.length === 0}
+          >
+            Choose Files
+          </Button>
+        </Group>
+      </Stack>
+    </Modal>
+  );
+}
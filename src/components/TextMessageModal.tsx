@@ -5,28 +5,68 @@ import {
   Stack,
   Text,
   CopyButton,
+  Group,
 } from "@mantine/core";
-import { IconCopy, IconCheck, IconExternalLink } from "@tabler/icons-react";
+import {
+  IconCopy,
+  IconCheck,
+  IconExternalLink,
+  IconArrowBackUp,
+  IconFile,
+} from "@tabler/icons-react";
 import { openUrl } from "@tauri-apps/plugin-opener";
 import { notifications } from "@mantine/notifications";
 
+// Mirrors `InlineImage` in src-tauri/src/message.rs; `data` is base64
+export interface InlineImage {
+  mime_type: string;
+  data: string;
+}
+
+// Mirrors `MessagePayload` in src-tauri/src/message.rs
+export interface Message {
+  id: string;
+  sender_alias: string;
+  device_id?: string;
+  type: "text" | "url" | "image" | "file";
+  content: string;
+  image?: InlineImage;
+  file?: { name: string; size: number; mime_type?: string };
+  thumbnail?: InlineImage;
+  reply_to?: string;
+}
+
+function imageSource(image: InlineImage): string {
+  return `data:${image.mime_type};base64,${image.data}`;
+}
+
+function formatFileSize(bytes: number): string {
+  if (bytes === 0) return "0 B";
+  const k = 1024;
+  const sizes = ["B", "KB", "MB", "GB", "TB"];
+  const i = Math.floor(Math.log(bytes) / Math.log(k));
+  return parseFloat((bytes / Math.pow(k, i)).toFixed(2)) + " " + sizes[i];
+}
+
 interface TextMessageModalProps {
   opened: boolean;
   onClose: () => void;
-  senderAlias: string;
-  content: string;
+  message: Message;
+  // Text of the message this one replies to, if it is still known
+  quoted?: string;
+  onReply: () => void;
 }
 
 export default function TextMessageModal({
   opened,
   onClose,
-  senderAlias,
-  content,
+  message,
+  quoted,
+  onReply,
 }: TextMessageModalProps) {
-  // Check if content is a URL (starts with http:// or https://)
-  const isUrl =
-    content.trim().startsWith("http://") ||
-    content.trim().startsWith("https://");
+  const { sender_alias: senderAlias, content } = message;
+  // Link-only text is marked as a link when it arrives
+  const isUrl = message.type === "url";
 
   const handleOpenUrl = async () => {
     try {
@@ -85,34 +125,89 @@ export default function TextMessageModal({
       }}
     >
       <Stack gap="lg">
-        <div className="depth-card" style={{ padding: "1rem" }}>
-          <Textarea
-            value={content}
-            readOnly
-            autosize
-            minRows={4}
-            maxRows={12}
-            styles={{
-              input: {
-                fontFamily: "'Fira Code', 'Consolas', monospace",
-                fontSize: "1.1rem",
-                lineHeight: "1.6",
-                background: "var(--bg)",
-                color: "var(--text-primary)",
-                border: "1px solid var(--border-subtle)",
-                borderRadius: "8px",
-                cursor: "text",
-                userSelect: "text",
-                padding: "1rem",
-                boxShadow: "var(--shadow-inset)",
-                "&:focus": {
-                  borderColor: "var(--accent-primary)",
-                  boxShadow: "var(--shadow-inset), 0 0 0 2px oklch(0.65 0.2 250 / 0.2)",
-                },
-              },
+        {message.reply_to && (
+          <Text
+            size="sm"
+            c="dimmed"
+            lineClamp={2}
+            style={{
+              borderLeft: "3px solid var(--accent-primary)",
+              paddingLeft: "0.75rem",
+            }}
+          >
+            In reply to: {quoted ?? "an earlier message"}
+          </Text>
+        )}
+        {message.image && (
+          <img
+            src={imageSource(message.image)}
+            alt={content || "Received image"}
+            style={{
+              maxWidth: "100%",
+              maxHeight: "50vh",
+              objectFit: "contain",
+              borderRadius: "8px",
             }}
           />
-        </div>
+        )}
+        {message.file && (
+          <Group gap="md" wrap="nowrap" className="depth-card p-4">
+            {message.thumbnail ? (
+              <img
+                src={imageSource(message.thumbnail)}
+                alt=""
+                style={{ width: 64, height: 64, objectFit: "cover" }}
+              />
+            ) : (
+              <IconFile size={40} color="var(--accent-primary-light)" />
+            )}
+            <div style={{ minWidth: 0 }}>
+              <Text fw={600} style={{ wordBreak: "break-word" }}>
+                {message.file.name}
+              </Text>
+              <Text size="sm" c="dimmed">
+                {formatFileSize(message.file.size)}
+              </Text>
+            </div>
+          </Group>
+        )}
+        {message.type === "url" && message.thumbnail && (
+          <img
+            src={imageSource(message.thumbnail)}
+            alt=""
+            style={{ maxWidth: "100%", borderRadius: "8px" }}
+          />
+        )}
+        {(content || message.type === "text") && (
+          <div className="depth-card" style={{ padding: "1rem" }}>
+            <Textarea
+              value={content}
+              readOnly
+              autosize
+              minRows={4}
+              maxRows={12}
+              styles={{
+                input: {
+                  fontFamily: "'Fira Code', 'Consolas', monospace",
+                  fontSize: "1.1rem",
+                  lineHeight: "1.6",
+                  background: "var(--bg)",
+                  color: "var(--text-primary)",
+                  border: "1px solid var(--border-subtle)",
+                  borderRadius: "8px",
+                  cursor: "text",
+                  userSelect: "text",
+                  padding: "1rem",
+                  boxShadow: "var(--shadow-inset)",
+                  "&:focus": {
+                    borderColor: "var(--accent-primary)",
+                    boxShadow: "var(--shadow-inset), 0 0 0 2px oklch(0.65 0.2 250 / 0.2)",
+                  },
+                },
+              }}
+            />
+          </div>
+        )}
 
         <Stack gap="sm">
           <CopyButton value={content} timeout={2000}>
@@ -180,6 +275,16 @@ export default function TextMessageModal({
               Open URL
             </Button>
           )}
+          <Button
+            leftSection={<IconArrowBackUp size={18} />}
+            variant="light"
+            onClick={onReply}
+            size="lg"
+            fullWidth
+            className="depth-button-secondary"
+          >
+            Reply
+          </Button>
           <Button
             variant="light"
             onClick={onClose}
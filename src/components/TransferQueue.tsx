@@ -0,0 +1,114 @@
+import { useEffect, useState } from "react";
+import { ActionIcon, Badge, Group, Stack, Text, Tooltip } from "@mantine/core";
+import {
+  IconArrowBarToUp,
+  IconPlayerPause,
+  IconPlayerPlay,
+} from "@tabler/icons-react";
+import { invoke } from "@tauri-apps/api/core";
+import { listen } from "@tauri-apps/api/event";
+import { notifications } from "@mantine/notifications";
+import { errorMessage } from "../errors";
+
+// Mirrors `QueueItem` in src-tauri/src/queue.rs
+export interface QueueItem {
+  id: string;
+  peer: string;
+  file_name: string;
+  priority: number;
+  status: "queued" | "paused" | "running";
+}
+
+const statusColors: Record<QueueItem["status"], string> = {
+  running: "blue",
+  queued: "gray",
+  paused: "yellow",
+};
+
+// Outgoing files waiting for a free transfer slot, in the order they'll run
+export default function TransferQueue() {
+  const [items, setItems] = useState<QueueItem[]>([]);
+
+  useEffect(() => {
+    invoke<QueueItem[]>("get_transfer_queue")
+      .then(setItems)
+      .catch((e) => console.error("Failed to load transfer queue:", e));
+
+    const unlisten = listen<QueueItem[]>("queue-update", (event) => {
+      setItems(event.payload);
+    });
+    return () => {
+      unlisten.then((f) => f());
+    };
+  }, []);
+
+  const run = async (command: string, args: Record<string, unknown>) => {
+    try {
+      await invoke(command, args);
+    } catch (e) {
+      notifications.show({
+        title: "Error",
+        message: errorMessage(e),
+        color: "red",
+      });
+    }
+  };
+
+  const moveToTop = (item: QueueItem) => {
+    const top = Math.max(...items.map((i) => i.priority));
+    return run("set_transfer_priority", { id: item.id, priority: top + 1 });
+  };
+
+  // Nothing worth showing while files go out one at a time
+  if (items.filter((item) => item.status !== "running").length === 0) {
+    return null;
+  }
+
+  return (
+    <Stack gap="xs" mt="md">
+      <Text size="sm" c="dimmed" tt="uppercase" fw={600}>
+        Queue
+      </Text>
+      {items.map((item) => (
+        <Group key={item.id} justify="space-between" wrap="nowrap">
+          <Group gap="xs" wrap="nowrap" style={{ minWidth: 0 }}>
+            <Badge color={statusColors[item.status]} variant="light">
+              {item.status}
+            </Badge>
+            <Text size="sm" truncate>
+              {item.file_name}
+            </Text>
+          </Group>
+          {item.status !== "running" && (
+            <Group gap={4} wrap="nowrap">
+              <Tooltip label="Send next">
+                <ActionIcon variant="subtle" onClick={() => moveToTop(item)}>
+                  <IconArrowBarToUp size={16} />
+                </ActionIcon>
+              </Tooltip>
+              {item.status === "paused" ? (
+                <Tooltip label="Resume">
+                  <ActionIcon
+                    variant="subtle"
+                    onClick={() => run("resume_transfer", { id: item.id })}
+                  >
+                    <IconPlayerPlay size={16} />
+                  </ActionIcon>
+                </Tooltip>
+              ) : (
+                <Tooltip label="Pause">
+                  <ActionIcon
+                    variant="subtle"
+                    onClick={() => run("pause_transfer", { id: item.id })}
+                  >
+                    <IconPlayerPause size={16} />
+                  </ActionIcon>
+                </Tooltip>
+              )}
+            </Group>
+          )}
+        </Group>
+      ))}
+    </Stack>
+  );
+}
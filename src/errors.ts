@@ -0,0 +1,48 @@
+// Mirrors the `Error` enum returned by the Rust commands
+export type AppErrorKind =
+  | "network"
+  | "rejected"
+  | "timeout"
+  | "io"
+  | "not_found"
+  | "permission"
+  | "invalid_input"
+  | "cancelled"
+  | "unsupported"
+  | "internal";
+
+export interface AppError {
+  kind: AppErrorKind;
+  message: string;
+}
+
+export function isAppError(e: unknown): e is AppError {
+  return (
+    typeof e === "object" &&
+    e !== null &&
+    typeof (e as AppError).kind === "string" &&
+    typeof (e as AppError).message === "string"
+  );
+}
+
+export function errorKind(e: unknown): AppErrorKind | undefined {
+  return isAppError(e) ? e.kind : undefined;
+}
+
+// Human-readable text for anything a command or plugin may throw
+export function errorMessage(e: unknown): string {
+  if (isAppError(e)) {
+    switch (e.kind) {
+      case "rejected":
+        return "The receiver declined the file";
+      // Only an unanswered confirmation prompt; socket timeouts are "network"
+      case "timeout":
+        return `No answer: ${e.message}`;
+      default:
+        return e.message;
+    }
+  }
+  if (typeof e === "string") return e;
+  if (e instanceof Error) return e.message;
+  return String(e);
+}
@@ -23,27 +23,43 @@ import {
   IconRefresh,
   IconX,
   IconClipboard,
+  IconUsers,
+  IconSpeakerphone,
 } from "@tabler/icons-react";
 import { invoke } from "@tauri-apps/api/core";
 import { listen } from "@tauri-apps/api/event";
 import { getCurrentWebview } from "@tauri-apps/api/webview";
 import { open } from "@tauri-apps/plugin-dialog";
-import { readFile } from "@tauri-apps/plugin-fs";
+import { errorKind, errorMessage } from "../errors";
+import { stageFile } from "../staging";
 import { readText } from "@tauri-apps/plugin-clipboard-manager";
 import { AndroidFs, isAndroid } from "tauri-plugin-android-fs-api";
-import TextMessageModal from "../components/TextMessageModal";
+import TextMessageModal, { Message } from "../components/TextMessageModal";
 import FileTransferConfirmModal from "../components/FileTransferConfirmModal";
+import FileConflictModal, {
+  ConflictResolution,
+} from "../components/FileConflictModal";
+import TransferQueue from "../components/TransferQueue";
+import MultiSendModal from "../components/MultiSendModal";
+import BroadcastModal from "../components/BroadcastModal";
 
 interface Peer {
   ip: string;
+  addresses: string[];
   port: number;
   alias: string;
   hostname: string;
 }
 
-interface ReceivedMessage {
-  senderAlias: string;
-  content: string;
+// Payload of `transfer-summary`, which comes just before a send returns or
+// `file-receive-complete` arrives
+interface TransferSummary {
+  transfer_id: string;
+  direction: "send" | "receive";
+  bytes: number;
+  elapsed_ms: number;
+  average_bytes_per_second: number;
+  peer?: string;
 }
 
 interface FileTransferRequest {
@@ -52,6 +68,28 @@ interface FileTransferRequest {
   file_size?: number;
 }
 
+// Payload of `message-received`
+type ReceivedMessage = Message & {
+  // Whether the link in it was opened right away
+  opened: boolean;
+};
+
+interface FileConflict {
+  transfer_id: string;
+  file_name: string;
+  identical: boolean;
+}
+
+// Mirrors `ClipboardContent` in src-tauri/src/clipboard.rs
+type ClipboardContent =
+  | { kind: "text"; text: string }
+  | { kind: "image"; png: string };
+
+type ClipboardReceived = ClipboardContent & {
+  sender_alias: string;
+  written: boolean;
+};
+
 function formatFileSize(bytes: number): string {
   if (bytes === 0) return "0 B";
   const k = 1024;
@@ -60,18 +98,131 @@ function formatFileSize(bytes: number): string {
   return parseFloat((bytes / Math.pow(k, i)).toFixed(2)) + " " + sizes[i];
 }
 
+function formatDuration(ms: number): string {
+  const seconds = Math.ceil(ms / 1000);
+  if (seconds < 60) return `${seconds}s`;
+  const minutes = Math.floor(seconds / 60);
+  if (minutes < 60) return `${minutes}m ${seconds % 60}s`;
+  return `${Math.floor(minutes / 60)}h ${minutes % 60}m`;
+}
+
+// How long a finished transfer took and how fast it went, e.g. " in 4s at
+// 2.5 MB/s"; nothing if its summary didn't arrive.
+function describeSummary(summary: TransferSummary | undefined): string {
+  if (!summary) return "";
+  return ` in ${formatDuration(summary.elapsed_ms)} at ${formatFileSize(
+    summary.average_bytes_per_second
+  )}/s`;
+}
+
+// Let the user pick files to send: content URIs on Android, paths elsewhere.
+// Returns nothing if the picker was cancelled or failed.
+async function pickFiles(): Promise<string[]> {
+  let filePaths: string[] = [];
+
+  // Use Android FS API on Android, dialog plugin on other platforms
+  if (isAndroid()) {
+    try {
+      const uris = await AndroidFs.showOpenFilePicker({
+        multiple: true,
+        mimeTypes: ["*/*"],
+      });
+      // Convert AndroidFsUri[] to string[] - properly convert URI objects to strings
+      filePaths = (uris || []).map((uri) => {
+        // The API returns URI objects - check various possible structures
+        if (typeof uri === "string") {
+          return uri;
+        }
+        // Try accessing the uri property directly (common structure)
+        if (uri && typeof uri === "object") {
+          // Check for common property names
+          if ("uri" in uri && typeof uri.uri === "string") {
+            return uri.uri;
+          }
+          // Check if it has a toString method
+          if (typeof uri.toString === "function") {
+            const str = uri.toString();
+            // Only use toString if it returns a valid URI string
+            if (str && str.startsWith("content://")) {
+              return str;
+            }
+          }
+          // Try JSON stringify and parse to extract URI
+          try {
+            const jsonStr = JSON.stringify(uri);
+            const parsed = JSON.parse(jsonStr);
+            if (parsed.uri && typeof parsed.uri === "string") {
+              return parsed.uri;
+            }
+          } catch {}
+        }
+        // Last resort: try String conversion
+        const str = String(uri);
+        if (str && str.startsWith("content://")) {
+          return str;
+        }
+        // If all else fails, log and throw
+        console.error("Failed to extract URI from:", uri);
+        throw new Error(`Invalid URI object: ${JSON.stringify(uri)}`);
+      });
+
+      if (filePaths.length === 0) {
+        return []; // User cancelled or no files selected
+      }
+
+      console.log("Selected file URIs:", filePaths);
+    } catch (e) {
+      console.error("Failed to open file picker on Android:", e);
+      notifications.show({
+        title: "Error",
+        message: `Failed to open file picker: ${e}`,
+        color: "red",
+      });
+      return [];
+    }
+  } else {
+    // Use dialog plugin on desktop
+    const selected = await open({
+      multiple: true,
+      directory: false,
+    });
+
+    if (!selected) {
+      return []; // User cancelled
+    }
+
+    // Convert to array if single file selected
+    filePaths = Array.isArray(selected) ? selected : [selected];
+  }
+
+  return filePaths;
+}
+
 export default function Home() {
   const [peers, setPeers] = useState<Peer[]>([]);
   const [selectedPeer, setSelectedPeer] = useState<Peer | null>(null);
   const [message, setMessage] = useState("");
   const [sending, setSending] = useState(false);
   const [refreshing, setRefreshing] = useState(false);
-  const [receivedMessage, setReceivedMessage] =
-    useState<ReceivedMessage | null>(null);
+  const [receivedMessage, setReceivedMessage] = useState<Message | null>(null);
+  // The message being answered by the next one sent
+  const [replyTo, setReplyTo] = useState<Message | null>(null);
+  const [activeTab, setActiveTab] = useState<string | null>("files");
   const [messageModalOpened, setMessageModalOpened] = useState(false);
   const [fileTransferRequest, setFileTransferRequest] =
     useState<FileTransferRequest | null>(null);
   const [transferModalOpened, setTransferModalOpened] = useState(false);
+  const [fileConflict, setFileConflict] = useState<FileConflict | null>(null);
+  const [multiSendOpened, setMultiSendOpened] = useState(false);
+  const [broadcastOpened, setBroadcastOpened] = useState(false);
+
+  // Text of messages sent and received by ID, to quote what replies answer
+  const messageTexts = useRef(new Map<string, string>());
+
+  // Summaries of finished transfers by ID, until their completion is shown
+  const summaries = useRef(new Map<string, TransferSummary>());
+  // The selected peer while it's gone, so it's selected again if it returns
+  const lostPeer = useRef<string | null>(null);
 
   // Use ref to access current selectedPeer in event handlers without re-subscribing
   const selectedPeerRef = useRef<Peer | null>(null);
@@ -103,6 +254,54 @@ export default function Home() {
       setPeers(uniquePeers);
     });
 
+    // Keep the selected peer current: take in new addresses or names, let go
+    // of it when it leaves and pick it up again when it comes back
+    const unlistenPeerAdded = listen<Peer>("peer-added", (event) => {
+      const peer = event.payload;
+      if (peer.hostname === lostPeer.current && !selectedPeerRef.current) {
+        lostPeer.current = null;
+        setSelectedPeer(peer);
+        notifications.show({
+          title: "Peer Is Back",
+          message: `${peer.alias} is available again`,
+          color: "green",
+          autoClose: 3000,
+        });
+      }
+    });
+
+    const unlistenPeerUpdated = listen<Peer>("peer-updated", (event) => {
+      const peer = event.payload;
+      setSelectedPeer((selected) =>
+        selected?.hostname === peer.hostname ? peer : selected
+      );
+    });
+
+    const unlistenPeerRemoved = listen<Peer>("peer-removed", (event) => {
+      const peer = event.payload;
+      if (selectedPeerRef.current?.hostname !== peer.hostname) return;
+      lostPeer.current = peer.hostname;
+      setSelectedPeer(null);
+      notifications.show({
+        title: "Peer Went Away",
+        message: `${peer.alias} is no longer available`,
+        color: "yellow",
+        autoClose: 5000,
+      });
+    });
+
+    const unlistenSummary = listen<TransferSummary>(
+      "transfer-summary",
+      (event) => {
+        const kept = summaries.current;
+        kept.set(event.payload.transfer_id, event.payload);
+        // Sends made elsewhere, e.g. to several peers, are never shown here
+        if (kept.size > 100) {
+          kept.delete(kept.keys().next().value as string);
+        }
+      }
+    );
+
     const unlistenFileStart = listen("file-receive-start", (event: any) => {
       const { transfer_id, file_name } = event.payload;
       notifications.show({
@@ -116,11 +315,17 @@ export default function Home() {
 
     const unlistenProgress = listen("transfer-progress", (event: any) => {
       const { transfer_id, current_bytes, total_bytes } = event.payload;
+      const { bytes_per_second, eta_ms } = event.payload;
       const percent = total_bytes
         ? Math.round((current_bytes / total_bytes) * 100)
         : 0;
       const sizeStr = total_bytes ? formatFileSize(total_bytes) : "Unknown";
       const currentStr = formatFileSize(current_bytes);
+      const speedStr = bytes_per_second
+        ? ` · ${formatFileSize(bytes_per_second)}/s`
+        : "";
+      const etaStr =
+        eta_ms && percent < 100 ? ` · ${formatDuration(eta_ms)} left` : "";
 
       // Try to update notification if it exists (for receiver or sender if ID matches)
       // Note: Mantine notifications.update does not create if missing?
@@ -129,7 +334,7 @@ export default function Home() {
       notifications.update({
         id: transfer_id,
         title: total_bytes ? `Transferring... ${percent}%` : "Transferring...",
-        message: `${currentStr} / ${sizeStr}`,
+        message: `${currentStr} / ${sizeStr}${speedStr}${etaStr}`,
         loading: true,
         autoClose: false,
       });
@@ -138,7 +343,21 @@ export default function Home() {
     const unlistenFileComplete = listen(
       "file-receive-complete",
       (event: any) => {
-        const { transfer_id, file_name, file_path } = event.payload;
+        const { transfer_id, file_name, file_path, skipped } = event.payload;
+        const summary = summaries.current.get(transfer_id);
+        summaries.current.delete(transfer_id);
+
+        if (skipped) {
+          notifications.update({
+            id: transfer_id,
+            title: "Already Received",
+            message: `${file_name} is already in your downloads`,
+            color: "blue",
+            loading: false,
+            autoClose: 5000,
+          });
+          return;
+        }
 
         // Check if we're on Windows and have a file path
         const isWindows = navigator.platform.toLowerCase().includes("win");
@@ -150,7 +369,10 @@ export default function Home() {
             <div
               style={{ display: "flex", flexDirection: "column", gap: "8px" }}
             >
-              <div>Successfully received {file_name}</div>
+              <div>
+                Successfully received {file_name}
+                {describeSummary(summary)}
+              </div>
               <Button
                 size="xs"
                 variant="light"
@@ -172,7 +394,7 @@ export default function Home() {
               </Button>
             </div>
           ) : (
-            `Successfully received ${file_name}`
+            `Successfully received ${file_name}${describeSummary(summary)}`
           );
 
         notifications.update({
@@ -186,13 +408,91 @@ export default function Home() {
       }
     );
 
-    const unlistenMessage = listen("message-received", (event: any) => {
-      setReceivedMessage({
-        senderAlias: event.payload.sender_alias,
-        content: event.payload.content,
-      });
-      setMessageModalOpened(true);
-    });
+    const unlistenMessage = listen<ReceivedMessage>(
+      "message-received",
+      (event) => {
+        const { opened, ...received } = event.payload;
+        messageTexts.current.set(
+          received.id,
+          received.content || received.file?.name || "an image"
+        );
+        if (opened) {
+          notifications.show({
+            title: `Link from ${received.sender_alias}`,
+            message: `Opened ${received.content}`,
+            color: "blue",
+            autoClose: 5000,
+          });
+          return;
+        }
+        setReceivedMessage(received);
+        setMessageModalOpened(true);
+      }
+    );
+
+    const unlistenClipboard = listen<ClipboardReceived>(
+      "clipboard-received",
+      (event) => {
+        const { sender_alias, written, ...content } = event.payload;
+        if (written) {
+          notifications.show({
+            title: "Clipboard Updated",
+            message: `${sender_alias} sent ${
+              content.kind === "image" ? "an image" : "text"
+            } to your clipboard`,
+            color: "green",
+            autoClose: 3000,
+          });
+          return;
+        }
+
+        const id = `clipboard-${Date.now()}`;
+        notifications.show({
+          id,
+          title: `Clipboard from ${sender_alias}`,
+          message: (
+            <Stack gap="xs">
+              {content.kind === "image" ? (
+                <img
+                  src={`data:image/png;base64,${content.png}`}
+                  alt="Received clipboard image"
+                  style={{ maxWidth: "100%", maxHeight: "160px" }}
+                />
+              ) : (
+                <Text
+                  size="sm"
+                  lineClamp={4}
+                  style={{ wordBreak: "break-word" }}
+                >
+                  {content.text}
+                </Text>
+              )}
+              <Button
+                size="xs"
+                variant="light"
+                leftSection={<IconClipboard size={14} />}
+                onClick={async () => {
+                  try {
+                    await invoke("copy_to_clipboard", { content });
+                    notifications.hide(id);
+                  } catch (e) {
+                    notifications.show({
+                      title: "Error",
+                      message: `Failed to copy: ${errorMessage(e)}`,
+                      color: "red",
+                    });
+                  }
+                }}
+                style={{ alignSelf: "flex-start" }}
+              >
+                Copy
+              </Button>
+            </Stack>
+          ),
+          autoClose: false,
+        });
+      }
+    );
 
     // Listen for file transfer requests
     const unlistenFileTransferRequest = listen<FileTransferRequest>(
@@ -204,6 +504,14 @@ export default function Home() {
       }
     );
 
+    // Asked when a received file's name is taken and the setting says to ask
+    const unlistenFileConflict = listen<FileConflict>(
+      "file-conflict",
+      (event) => {
+        setFileConflict(event.payload);
+      }
+    );
+
     // Listen for file transfer rejection
     const unlistenFileTransferRejected = listen(
       "file-transfer-rejected",
@@ -243,6 +551,44 @@ export default function Home() {
       });
     });
 
+    // A send failed on the network and will be tried again shortly. One
+    // notification per send, even when several go to different peers.
+    const unlistenRetrying = listen("transfer-retrying", (event: any) => {
+      const { transfer_id, name, peer, attempt, max_attempts, delay_ms } =
+        event.payload;
+      const notification = {
+        id: transfer_id,
+        title: "Connection Lost",
+        message: `Retrying ${name} to ${peer} in ${Math.ceil(
+          delay_ms / 1000
+        )}s (attempt ${attempt} of ${max_attempts})...`,
+        color: "yellow",
+        loading: true,
+        // Gone soon after the attempt starts; a failure is shown by the send
+        autoClose: delay_ms + 3000,
+      };
+      if (attempt === 2) {
+        notifications.show(notification);
+      } else {
+        notifications.update(notification);
+      }
+    });
+
+    // The sender dropped mid-file; what arrived is kept for it to resume
+    const unlistenInterrupted = listen<string>(
+      "file-receive-interrupted",
+      (event) => {
+        notifications.update({
+          id: event.payload,
+          title: "Connection Lost",
+          message: "Waiting for the sender to resume...",
+          color: "yellow",
+          loading: false,
+          autoClose: 10000,
+        });
+      }
+    );
+
     // Listen for media scan trigger on Android
     const unlistenMediaScan = listen<string>(
       "trigger-media-scan",
@@ -280,86 +626,91 @@ export default function Home() {
         setSending(true);
 
         try {
-          for (let filePath of filePaths) {
-            try {
-              // On Windows, Tauri might provide file:/// URLs, normalize them
-              if (filePath.startsWith("file:///")) {
-                filePath = filePath.replace("file:///", "");
-                // Decode URL encoding (e.g., %20 -> space)
-                filePath = decodeURIComponent(filePath);
-              }
+          // Queued in the backend, which decides how many run at once
+          await Promise.all(
+            filePaths.map(async (filePath) => {
+              try {
+                // On Windows, Tauri might provide file:/// URLs, normalize them
+                if (filePath.startsWith("file:///")) {
+                  filePath = filePath.replace("file:///", "");
+                  // Decode URL encoding (e.g., %20 -> space)
+                  filePath = decodeURIComponent(filePath);
+                }
 
-              let fileName = filePath.split(/[\\/]/).pop() || filePath;
+                let fileName = filePath.split(/[\\/]/).pop() || filePath;
 
-              // On Android content URIs, try to get better filename
-              if (filePath.startsWith("content://")) {
-                try {
-                  // Try Android FS API first
-                  if (isAndroid()) {
-                    try {
-                      fileName = await AndroidFs.getName(filePath);
-                    } catch {
-                      // Fallback to backend
+                // On Android content URIs, try to get better filename
+                if (filePath.startsWith("content://")) {
+                  try {
+                    // Try Android FS API first
+                    if (isAndroid()) {
+                      try {
+                        fileName = await AndroidFs.getName(filePath);
+                      } catch {
+                        // Fallback to backend
+                        fileName = await invoke<string>("get_file_name", {
+                          filePath,
+                        });
+                      }
+                    } else {
                       fileName = await invoke<string>("get_file_name", {
                         filePath,
                       });
                     }
-                  } else {
-                    fileName = await invoke<string>("get_file_name", {
-                      filePath,
-                    });
+                  } catch (e) {
+                    console.warn(
+                      "Could not get proper filename from content URI:",
+                      e
+                    );
+                    // Use a fallback name
+                    const uriParts = filePath.split("/");
+                    const lastPart = uriParts[uriParts.length - 1] || "";
+                    fileName = lastPart.split("?")[0] || "file";
                   }
-                } catch (e) {
-                  console.warn(
-                    "Could not get proper filename from content URI:",
-                    e
-                  );
-                  // Use a fallback name
-                  const uriParts = filePath.split("/");
-                  const lastPart = uriParts[uriParts.length - 1] || "";
-                  fileName = lastPart.split("?")[0] || "file";
                 }
-              }
 
-              console.log(`Attempting to send file: ${fileName} (${filePath})`);
-
-              // Pass file path/URI directly to backend - it handles both content URIs and regular paths
-              await invoke("send_file_to_peer", {
-                peerIp: currentPeer.ip,
-                peerPort: currentPeer.port,
-                filePath: filePath,
-              });
-
-              console.log("File sent successfully");
-
-              notifications.show({
-                title: "Sent",
-                message: `Sent ${fileName}`,
-                color: "green",
-              });
-            } catch (e) {
-              // Extract filename safely for error message
-              let errorFileName: string = "file";
-              if (typeof filePath === "string") {
-                if (filePath.startsWith("content://")) {
-                  const uriParts = filePath.split("/");
-                  const lastPart = uriParts[uriParts.length - 1] || "";
-                  errorFileName = lastPart.split("?")[0] || "file";
-                } else {
-                  errorFileName = filePath.split(/[\\/]/).pop() || filePath;
+                console.log(`Attempting to send file: ${fileName} (${filePath})`);
+
+                // Pass file path/URI directly to backend - it handles both content URIs and regular paths
+                await invoke("send_file_to_peer", {
+                  peerIp: currentPeer.ip,
+                  peerPort: currentPeer.port,
+                  peerAddresses: currentPeer.addresses,
+                  filePath: filePath,
+                });
+
+                console.log("File sent successfully");
+
+                const summary = summaries.current.get(fileName);
+                summaries.current.delete(fileName);
+                notifications.show({
+                  title: "Sent",
+                  message: `Sent ${fileName}${describeSummary(summary)}`,
+                  color: "green",
+                });
+              } catch (e) {
+                // Extract filename safely for error message
+                let errorFileName: string = "file";
+                if (typeof filePath === "string") {
+                  if (filePath.startsWith("content://")) {
+                    const uriParts = filePath.split("/");
+                    const lastPart = uriParts[uriParts.length - 1] || "";
+                    errorFileName = lastPart.split("?")[0] || "file";
+                  } else {
+                    errorFileName = filePath.split(/[\\/]/).pop() || filePath;
+                  }
                 }
-              }
 
-              const errorMsg =
-                typeof e === "string" ? e : e?.toString() || String(e);
-              console.error(`Failed to send ${errorFileName}:`, e);
-              notifications.show({
-                title: "Error",
-                message: `Failed to send ${errorFileName}: ${errorMsg}`,
-                color: "red",
-              });
-            }
-          }
+                const errorMsg = errorMessage(e);
+                console.error(`Failed to send ${errorFileName}:`, e);
+                notifications.show({
+                  title: "Error",
+                  message: `Failed to send ${errorFileName}: ${errorMsg}`,
+                  color: "red",
+                });
+              }
+            })
+          );
         } catch (e) {
           console.error("Unexpected error in drag-drop handler:", e);
           notifications.show({
@@ -376,13 +727,21 @@ export default function Home() {
 
     return () => {
       unlistenPeers.then((f) => f());
+      unlistenPeerAdded.then((f) => f());
+      unlistenPeerUpdated.then((f) => f());
+      unlistenPeerRemoved.then((f) => f());
+      unlistenSummary.then((f) => f());
       unlistenFileStart.then((f) => f());
       unlistenFileComplete.then((f) => f());
       unlistenMessage.then((f) => f());
+      unlistenClipboard.then((f) => f());
       unlistenFileTransferRequest.then((f) => f());
+      unlistenFileConflict.then((f) => f());
       unlistenFileTransferRejected.then((f) => f());
       unlistenFileTransferTimeout.then((f) => f());
       unlistenFileTransferError.then((f) => f());
+      unlistenRetrying.then((f) => f());
+      unlistenInterrupted.then((f) => f());
       unlistenMediaScan.then((f) => f());
       unlistenFileDrop.then((f) => f());
       unlistenProgress.then((f) => f());
@@ -400,217 +759,164 @@ export default function Home() {
     }
 
     try {
-      let filePaths: string[] = [];
-
-      // Use Android FS API on Android, dialog plugin on other platforms
-      if (isAndroid()) {
-        try {
-          const uris = await AndroidFs.showOpenFilePicker({
-            multiple: true,
-            mimeTypes: ["*/*"],
-          });
-          // Convert AndroidFsUri[] to string[] - properly convert URI objects to strings
-          filePaths = (uris || []).map((uri) => {
-            // The API returns URI objects - check various possible structures
-            if (typeof uri === "string") {
-              return uri;
-            }
-            // Try accessing the uri property directly (common structure)
-            if (uri && typeof uri === "object") {
-              // Check for common property names
-              if ("uri" in uri && typeof uri.uri === "string") {
-                return uri.uri;
-              }
-              // Check if it has a toString method
-              if (typeof uri.toString === "function") {
-                const str = uri.toString();
-                // Only use toString if it returns a valid URI string
-                if (str && str.startsWith("content://")) {
-                  return str;
-                }
-              }
-              // Try JSON stringify and parse to extract URI
-              try {
-                const jsonStr = JSON.stringify(uri);
-                const parsed = JSON.parse(jsonStr);
-                if (parsed.uri && typeof parsed.uri === "string") {
-                  return parsed.uri;
-                }
-              } catch {}
-            }
-            // Last resort: try String conversion
-            const str = String(uri);
-            if (str && str.startsWith("content://")) {
-              return str;
-            }
-            // If all else fails, log and throw
-            console.error("Failed to extract URI from:", uri);
-            throw new Error(`Invalid URI object: ${JSON.stringify(uri)}`);
-          });
-
-          if (filePaths.length === 0) {
-            return; // User cancelled or no files selected
-          }
-
-          console.log("Selected file URIs:", filePaths);
-        } catch (e) {
-          console.error("Failed to open file picker on Android:", e);
-          notifications.show({
-            title: "Error",
-            message: `Failed to open file picker: ${e}`,
-            color: "red",
-          });
-          return;
-        }
-      } else {
-        // Use dialog plugin on desktop
-        const selected = await open({
-          multiple: true,
-          directory: false,
-        });
-
-        if (!selected) {
-          return; // User cancelled
-        }
-
-        // Convert to array if single file selected
-        filePaths = Array.isArray(selected) ? selected : [selected];
+      const filePaths = await pickFiles();
+      if (filePaths.length === 0) {
+        return;
       }
 
       setSending(true);
-      for (const filePath of filePaths) {
-        try {
-          // Extract filename from path - handle both regular paths and content URIs
-          let fileName = filePath.split(/[\\/]/).pop() || filePath;
-
-          // On Android content URIs, get filename using Android FS API or backend
-          if (filePath.startsWith("content://")) {
-            try {
-              // Try Android FS API first
-              fileName = await AndroidFs.getName(filePath);
-              console.log("Got filename from Android FS API:", fileName);
-            } catch (e) {
-              console.warn(
-                "Could not get filename from Android FS API, trying backend:",
-                e
-              );
-              // Fallback to backend
+      // Queued in the backend, which decides how many run at once
+      await Promise.all(
+        filePaths.map(async (filePath) => {
+          try {
+            // Extract filename from path - handle both regular paths and content URIs
+            let fileName = filePath.split(/[\\/]/).pop() || filePath;
+
+            // On Android content URIs, get filename using Android FS API or backend
+            if (filePath.startsWith("content://")) {
               try {
-                fileName = await invoke<string>("get_file_name", {
-                  filePath,
-                });
-                console.log("Got filename from backend:", fileName);
-              } catch (backendError) {
+                // Try Android FS API first
+                fileName = await AndroidFs.getName(filePath);
+                console.log("Got filename from Android FS API:", fileName);
+              } catch (e) {
                 console.warn(
-                  "Could not get filename from backend:",
-                  backendError
+                  "Could not get filename from Android FS API, trying backend:",
+                  e
                 );
-                // Use a generic name as last resort
-                fileName = "file";
+                // Fallback to backend
+                try {
+                  fileName = await invoke<string>("get_file_name", {
+                    filePath,
+                  });
+                  console.log("Got filename from backend:", fileName);
+                } catch (backendError) {
+                  console.warn(
+                    "Could not get filename from backend:",
+                    backendError
+                  );
+                  // Use a generic name as last resort
+                  fileName = "file";
+                }
               }
             }
-          }
 
-          // Use a safe identifier for notifications (avoid [object Object])
-          const notificationId = fileName || `file-${Date.now()}`;
+            // Use a safe identifier for notifications (avoid [object Object])
+            const notificationId = fileName || `file-${Date.now()}`;
 
-          notifications.show({
-            id: notificationId,
-            title: `Sending ${fileName}`,
-            message: "Starting...",
-            loading: true,
-            autoClose: false,
-          });
+            notifications.show({
+              id: notificationId,
+              title: `Sending ${fileName}`,
+              message: "Queued...",
+              loading: true,
+              autoClose: false,
+            });
 
-          // On Android with content URIs, pass URI directly to Rust backend
-          // The Rust backend already handles content URIs properly using Android FS API
-          if (isAndroid() && filePath.startsWith("content://")) {
-            try {
-              console.log("Sending Android content URI to backend:", filePath);
-              // Pass the content URI directly to the Rust backend
-              // The backend will handle opening the file using Android FS API
-              await invoke("send_file_to_peer", {
-                peerIp: selectedPeer.ip,
-                peerPort: selectedPeer.port,
-                filePath: filePath,
-              });
-              console.log("File sent successfully via content URI");
-            } catch (sendError) {
-              console.error("Failed to send file via content URI:", sendError);
-              throw new Error(`Failed to send file: ${sendError}`);
-            }
-          } else {
-            // Desktop or regular file paths
-            try {
-              // Try direct path method first (Desktop optimization)
-              await invoke("send_file_to_peer", {
-                peerIp: selectedPeer.ip,
-                peerPort: selectedPeer.port,
-                filePath: filePath,
-              });
-            } catch (pathError) {
-              // Fall back to reading file bytes
+            // On Android with content URIs, pass URI directly to Rust backend
+            // The Rust backend already handles content URIs properly using Android FS API
+            if (isAndroid() && filePath.startsWith("content://")) {
               try {
-                console.log(
-                  "Path method failed, trying bytes method with filename:",
-                  fileName
-                );
-                // Try to read the file as bytes using the fs plugin
-                const fileData = await readFile(filePath);
-
-                // Send as bytes with the extracted/corrected filename
-                await invoke("send_file_bytes_to_peer", {
+                console.log("Sending Android content URI to backend:", filePath);
+                // Pass the content URI directly to the Rust backend
+                // The backend will handle opening the file using Android FS API
+                await invoke("send_file_to_peer", {
                   peerIp: selectedPeer.ip,
                   peerPort: selectedPeer.port,
-                  fileName: fileName,
-                  fileData: Array.from(fileData),
+                  peerAddresses: selectedPeer.addresses,
+                  filePath: filePath,
                 });
-              } catch (readError) {
-                throw new Error(
-                  `Failed to send via path or bytes: ${pathError} / ${readError}`
-                );
+                console.log("File sent successfully via content URI");
+              } catch (sendError) {
+                console.error("Failed to send file via content URI:", sendError);
+                throw sendError;
+              }
+            } else {
+              // Desktop or regular file paths
+              try {
+                // Try direct path method first (Desktop optimization)
+                await invoke("send_file_to_peer", {
+                  peerIp: selectedPeer.ip,
+                  peerPort: selectedPeer.port,
+                  peerAddresses: selectedPeer.addresses,
+                  filePath: filePath,
+                });
+              } catch (pathError) {
+                // Only retry when the file couldn't be opened; the peer
+                // declining or being unreachable won't change by staging
+                const kind = errorKind(pathError);
+                if (
+                  kind !== undefined &&
+                  !["io", "not_found", "permission", "invalid_input"].includes(
+                    kind
+                  )
+                ) {
+                  throw pathError;
+                }
+                // Fall back to handing the file over through the fs plugin
+                try {
+                  console.log(
+                    "Path method failed, staging file in chunks with filename:",
+                    fileName
+                  );
+                  const uploadId = await stageFile(filePath, fileName);
+
+                  // Sent with the extracted/corrected filename
+                  await invoke("send_staged_file", {
+                    uploadId,
+                    peerIp: selectedPeer.ip,
+                    peerPort: selectedPeer.port,
+                    peerAddresses: selectedPeer.addresses,
+                  });
+                } catch (readError) {
+                  throw new Error(
+                    `Failed to send via path or staging: ${errorMessage(
+                      pathError
+                    )} / ${errorMessage(readError)}`
+                  );
+                }
               }
             }
-          }
 
-          notifications.update({
-            id: notificationId,
-            title: "Sent",
-            message: `Successfully sent ${fileName}`,
-            color: "green",
-            loading: false,
-            autoClose: 2000,
-          });
-        } catch (e) {
-          // Extract filename safely for error message (synchronous only)
-          let errorFileName: string = "file";
-          if (typeof filePath === "string") {
-            if (filePath.startsWith("content://")) {
-              // For content URIs, use a generic name or try to extract from URI
-              // We can't use async operations here, so use a fallback
-              const uriParts = filePath.split("/");
-              const lastPart = uriParts[uriParts.length - 1] || "";
-              // Remove query parameters
-              const cleanPart = lastPart.split("?")[0];
-              errorFileName = cleanPart || "file";
-            } else {
-              errorFileName = filePath.split(/[\\/]/).pop() || filePath;
+            const summary = summaries.current.get(fileName);
+            summaries.current.delete(fileName);
+            notifications.update({
+              id: notificationId,
+              title: "Sent",
+              message: `Successfully sent ${fileName}${describeSummary(
+                summary
+              )}`,
+              color: "green",
+              loading: false,
+              autoClose: 2000,
+            });
+          } catch (e) {
+            // Extract filename safely for error message (synchronous only)
+            let errorFileName: string = "file";
+            if (typeof filePath === "string") {
+              if (filePath.startsWith("content://")) {
+                // For content URIs, use a generic name or try to extract from URI
+                // We can't use async operations here, so use a fallback
+                const uriParts = filePath.split("/");
+                const lastPart = uriParts[uriParts.length - 1] || "";
+                // Remove query parameters
+                const cleanPart = lastPart.split("?")[0];
+                errorFileName = cleanPart || "file";
+              } else {
+                errorFileName = filePath.split(/[\\/]/).pop() || filePath;
+              }
             }
-          }
 
-          // Ensure we have a string, not an object
-          const errorMsg =
-            typeof e === "string" ? e : e?.toString() || String(e);
+            const errorMsg = errorMessage(e);
 
-          notifications.show({
-            title: "Error",
-            message: `Failed to send ${errorFileName}: ${errorMsg}`,
-            color: "red",
-            autoClose: 5000,
-          });
-          console.error(`Failed to send ${errorFileName}:`, e);
-        }
-      }
+            notifications.show({
+              title: "Error",
+              message: `Failed to send ${errorFileName}: ${errorMsg}`,
+              color: "red",
+              autoClose: 5000,
+            });
+            console.error(`Failed to send ${errorFileName}:`, e);
+          }
+        })
+      );
     } catch (e) {
       notifications.show({
         title: "Error",
@@ -626,21 +932,63 @@ export default function Home() {
     if (!selectedPeer || !message.trim()) return;
     setSending(true);
     try {
-      await invoke("send_text_to_peer", {
+      const id = await invoke<string>("send_message_to_peer", {
         peerIp: selectedPeer.ip,
         peerPort: selectedPeer.port,
-        text: message,
+        peerAddresses: selectedPeer.addresses,
+        message: { type: "text", content: message, reply_to: replyTo?.id },
       });
+      messageTexts.current.set(id, message);
       notifications.show({
         title: "Sent",
         message: "Message sent",
         color: "green",
       });
       setMessage("");
+      setReplyTo(null);
+    } catch (e) {
+      notifications.show({
+        title: "Error",
+        message: `Failed to send message: ${errorMessage(e)}`,
+        color: "red",
+      });
+    } finally {
+      setSending(false);
+    }
+  };
+
+  const handleReply = () => {
+    if (!receivedMessage) return;
+    // Answer the sender if they're still around
+    const sender = peers.find(
+      (peer) => peer.alias === receivedMessage.sender_alias
+    );
+    if (sender) {
+      setSelectedPeer(sender);
+    }
+    setReplyTo(receivedMessage);
+    setActiveTab("text");
+    setMessageModalOpened(false);
+  };
+
+  const handleSendClipboard = async () => {
+    if (!selectedPeer) return;
+    setSending(true);
+    try {
+      await invoke("send_clipboard_to_peer", {
+        peerIp: selectedPeer.ip,
+        peerPort: selectedPeer.port,
+        peerAddresses: selectedPeer.addresses,
+      });
+      notifications.show({
+        title: "Sent",
+        message: `Clipboard sent to ${selectedPeer.alias}`,
+        color: "green",
+      });
     } catch (e) {
       notifications.show({
         title: "Error",
-        message: `Failed to send message: ${e}`,
+        message: `Failed to send clipboard: ${errorMessage(e)}`,
         color: "red",
       });
     } finally {
@@ -661,7 +1009,7 @@ export default function Home() {
     } catch (e) {
       notifications.show({
         title: "Error",
-        message: `Failed to refresh: ${e}`,
+        message: `Failed to refresh: ${errorMessage(e)}`,
         color: "red",
       });
     } finally {
@@ -683,9 +1031,28 @@ export default function Home() {
     } catch (e) {
       notifications.show({
         title: "Error",
-        message: `Failed to accept transfer: ${e}`,
+        message: `Failed to accept transfer: ${errorMessage(e)}`,
+        color: "red",
+      });
+    }
+  };
+
+  const handleResolveConflict = async (resolution: ConflictResolution) => {
+    if (!fileConflict) return;
+
+    try {
+      await invoke("respond_to_file_conflict", {
+        transferId: fileConflict.transfer_id,
+        resolution,
+      });
+    } catch (e) {
+      notifications.show({
+        title: "Error",
+        message: `Failed to answer: ${errorMessage(e)}`,
         color: "red",
       });
+    } finally {
+      setFileConflict(null);
     }
   };
 
@@ -707,7 +1074,7 @@ export default function Home() {
     } catch (e) {
       notifications.show({
         title: "Error",
-        message: `Failed to reject transfer: ${e}`,
+        message: `Failed to reject transfer: ${errorMessage(e)}`,
         color: "red",
       });
     }
@@ -758,46 +1125,100 @@ export default function Home() {
                 <Title order={3} className="responsive-title text-text-primary">
                   Nearby Peers
                 </Title>
-                <Tooltip label="Refresh discovery">
-                  <ActionIcon
-                    variant="light"
-                    color="blue"
-                    onClick={handleRefreshPeers}
-                    loading={refreshing}
-                    size="xl"
-                    className="responsive-icon-button text-text-primary"
-                    style={{
-                      width: "44px",
-                      height: "44px",
-                      background:
-                        "linear-gradient(to bottom, var(--bg-lighter), var(--bg-light))",
-                      border: "1px solid var(--border-subtle)",
-                      borderRadius: "8px",
-                      boxShadow: "var(--shadow-s)",
-                      transition: "var(--transition-fast)",
-                    }}
-                    onMouseEnter={(e) => {
-                      if (!refreshing) {
-                        e.currentTarget.style.transform = "translateY(-1px)";
-                        e.currentTarget.style.boxShadow = "var(--shadow-m)";
+                <Group gap="xs" wrap="nowrap">
+                  <Tooltip label="Send to several devices">
+                    <ActionIcon
+                      variant="light"
+                      color="blue"
+                      onClick={() => setMultiSendOpened(true)}
+                      disabled={peers.length < 2}
+                      size="xl"
+                      className="responsive-icon-button text-text-primary"
+                      style={{
+                        width: "44px",
+                        height: "44px",
+                        background:
+                          "linear-gradient(to bottom, var(--bg-lighter), var(--bg-light))",
+                        border: "1px solid var(--border-subtle)",
+                        borderRadius: "8px",
+                        boxShadow: "var(--shadow-s)",
+                        transition: "var(--transition-fast)",
+                      }}
+                    >
+                      <IconUsers
+                        size={24}
+                        stroke={2}
+                        className="responsive-icon"
+                      />
+                    </ActionIcon>
+                  </Tooltip>
+                  <Tooltip label="Message everyone">
+                    <ActionIcon
+                      variant="light"
+                      color="blue"
+                      onClick={() => setBroadcastOpened(true)}
+                      disabled={peers.length === 0}
+                      size="xl"
+                      className="responsive-icon-button text-text-primary"
+                      style={{
+                        width: "44px",
+                        height: "44px",
+                        background:
+                          "linear-gradient(to bottom, var(--bg-lighter), var(--bg-light))",
+                        border: "1px solid var(--border-subtle)",
+                        borderRadius: "8px",
+                        boxShadow: "var(--shadow-s)",
+                        transition: "var(--transition-fast)",
+                      }}
+                    >
+                      <IconSpeakerphone
+                        size={24}
+                        stroke={2}
+                        className="responsive-icon"
+                      />
+                    </ActionIcon>
+                  </Tooltip>
+                  <Tooltip label="Refresh discovery">
+                    <ActionIcon
+                      variant="light"
+                      color="blue"
+                      onClick={handleRefreshPeers}
+                      loading={refreshing}
+                      size="xl"
+                      className="responsive-icon-button text-text-primary"
+                      style={{
+                        width: "44px",
+                        height: "44px",
+                        background:
+                          "linear-gradient(to bottom, var(--bg-lighter), var(--bg-light))",
+                        border: "1px solid var(--border-subtle)",
+                        borderRadius: "8px",
+                        boxShadow: "var(--shadow-s)",
+                        transition: "var(--transition-fast)",
+                      }}
+                      onMouseEnter={(e) => {
+                        if (!refreshing) {
+                          e.currentTarget.style.transform = "translateY(-1px)";
+                          e.currentTarget.style.boxShadow = "var(--shadow-m)";
+                          e.currentTarget.style.background =
+                            "linear-gradient(to bottom, var(--bg-lighter), var(--bg-lighter))";
+                        }
+                      }}
+                      onMouseLeave={(e) => {
+                        e.currentTarget.style.transform = "translateY(0)";
+                        e.currentTarget.style.boxShadow = "var(--shadow-s)";
                         e.currentTarget.style.background =
-                          "linear-gradient(to bottom, var(--bg-lighter), var(--bg-lighter))";
-                      }
-                    }}
-                    onMouseLeave={(e) => {
-                      e.currentTarget.style.transform = "translateY(0)";
-                      e.currentTarget.style.boxShadow = "var(--shadow-s)";
-                      e.currentTarget.style.background =
-                        "linear-gradient(to bottom, var(--bg-lighter), var(--bg-light))";
-                    }}
-                  >
-                    <IconRefresh
-                      size={24}
-                      stroke={2}
-                      className="responsive-icon"
-                    />
-                  </ActionIcon>
-                </Tooltip>
+                          "linear-gradient(to bottom, var(--bg-lighter), var(--bg-light))";
+                      }}
+                    >
+                      <IconRefresh
+                        size={24}
+                        stroke={2}
+                        className="responsive-icon"
+                      />
+                    </ActionIcon>
+                  </Tooltip>
+                </Group>
               </Group>
 
               {peers.length === 0 ? (
@@ -981,7 +1402,7 @@ export default function Home() {
                   </Tooltip>
                 </Group>
 
-                <Tabs defaultValue="files">
+                <Tabs value={activeTab} onChange={setActiveTab}>
                   <Tabs.List
                     mb="lg"
                     className="responsive-tabs-list"
@@ -1084,6 +1505,7 @@ export default function Home() {
                         Select Files
                       </Button>
                     </div>
+                    <TransferQueue />
                   </Tabs.Panel>
 
                   <Tabs.Panel
@@ -1096,6 +1518,32 @@ export default function Home() {
                       className="responsive-stack"
                       style={{ height: "100%", overflow: "auto" }}
                     >
+                      {replyTo && (
+                        <Group
+                          gap="xs"
+                          wrap="nowrap"
+                          justify="space-between"
+                          style={{
+                            borderLeft: "3px solid var(--accent-primary)",
+                            paddingLeft: "0.75rem",
+                          }}
+                        >
+                          <Text size="sm" c="dimmed" lineClamp={1}>
+                            Replying to {replyTo.sender_alias}:{" "}
+                            {replyTo.content ||
+                              replyTo.file?.name ||
+                              "an image"}
+                          </Text>
+                          <ActionIcon
+                            variant="subtle"
+                            color="gray"
+                            onClick={() => setReplyTo(null)}
+                            aria-label="Cancel reply"
+                          >
+                            <IconX size={16} />
+                          </ActionIcon>
+                        </Group>
+                      )}
                       <div
                         style={{
                           position: "relative",
@@ -1216,6 +1664,17 @@ export default function Home() {
                       >
                         Send Message
                       </Button>
+                      <Button
+                        leftSection={<IconClipboard size={16} />}
+                        onClick={handleSendClipboard}
+                        loading={sending}
+                        variant="light"
+                        size="md"
+                        className="depth-button-secondary responsive-button"
+                        fullWidth
+                      >
+                        Send My Clipboard
+                      </Button>
                     </Stack>
                   </Tabs.Panel>
                 </Tabs>
@@ -1264,8 +1723,34 @@ export default function Home() {
         <TextMessageModal
           opened={messageModalOpened}
           onClose={() => setMessageModalOpened(false)}
-          senderAlias={receivedMessage.senderAlias}
-          content={receivedMessage.content}
+          message={receivedMessage}
+          quoted={
+            receivedMessage.reply_to
+              ? messageTexts.current.get(receivedMessage.reply_to)
+              : undefined
+          }
+          onReply={handleReply}
+        />
+      )}
+
+      <MultiSendModal
+        opened={multiSendOpened}
+        onClose={() => setMultiSendOpened(false)}
+        peers={peers}
+        pickFiles={pickFiles}
+      />
+
+      <BroadcastModal
+        opened={broadcastOpened}
+        onClose={() => setBroadcastOpened(false)}
+      />
+
+      {fileConflict && (
+        <FileConflictModal
+          opened
+          onResolve={handleResolveConflict}
+          fileName={fileConflict.file_name}
+          identical={fileConflict.identical}
         />
       )}
 
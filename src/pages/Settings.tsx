@@ -5,23 +5,129 @@ import {
   Text,
   TextInput,
   NumberInput,
+  MultiSelect,
+  Select,
   Button,
   Stack,
   Paper,
   Group,
+  Checkbox,
+  ActionIcon,
 } from "@mantine/core";
 import { notifications } from "@mantine/notifications";
 import { invoke } from "@tauri-apps/api/core";
-import { IconDice } from "@tabler/icons-react";
+import { IconDice, IconTrash } from "@tabler/icons-react";
+import { isAndroid } from "tauri-plugin-android-fs-api";
+import { errorMessage } from "../errors";
 
 interface AppConfig {
   alias: string;
   port: number;
+  interfaces: string[];
+  max_concurrent_transfers: number;
+  max_transfers_per_peer: number;
+  // Mirrors `RetryPolicy` in src-tauri/src/retry.rs
+  retry: {
+    max_attempts: number;
+    initial_delay_ms: number;
+    max_delay_ms: number;
+    multiplier: number;
+    idle_timeout_ms: number;
+  };
+  // Mirrors `BandwidthLimits` in src-tauri/src/bandwidth.rs; bytes per
+  // second, 0 for unlimited
+  bandwidth: {
+    upload: number;
+    download: number;
+    upload_per_peer: number;
+    download_per_peer: number;
+  };
+  // Mirrors `ConflictStrategy` in src-tauri/src/conflict.rs
+  conflict_strategy: "rename" | "overwrite" | "skip_identical" | "ask";
+  // Mirrors `ClipboardSettings` in src-tauri/src/clipboard.rs
+  clipboard: {
+    write_received: boolean;
+    sync: boolean;
+    sync_peers: string[];
+    sync_max_bytes: number;
+  };
+  open_links_from: string[];
+  // Group name to the device IDs in it
+  peer_groups: Record<string, string[]>;
+}
+
+const conflictStrategies: {
+  value: AppConfig["conflict_strategy"];
+  label: string;
+}[] = [
+  { value: "rename", label: "Keep both (photo (1).jpg)" },
+  { value: "overwrite", label: "Replace the existing file" },
+  {
+    value: "skip_identical",
+    label: "Skip identical files, keep both otherwise",
+  },
+  { value: "ask", label: "Ask every time" },
+];
+
+const bandwidthFields: {
+  key: keyof AppConfig["bandwidth"];
+  label: string;
+}[] = [
+  { key: "upload", label: "Upload Limit" },
+  { key: "upload_per_peer", label: "Upload Limit per Device" },
+  { key: "download", label: "Download Limit" },
+  { key: "download_per_peer", label: "Download Limit per Device" },
+];
+
+interface NetworkInterface {
+  name: string;
+  addresses: string[];
+}
+
+interface Peer {
+  alias: string;
+  hostname: string;
+  device_id?: string;
 }
 
 export default function Settings() {
-  const [config, setConfig] = useState<AppConfig>({ alias: "", port: 3030 });
+  const [config, setConfig] = useState<AppConfig>({
+    alias: "",
+    port: 3030,
+    interfaces: [],
+    max_concurrent_transfers: 3,
+    max_transfers_per_peer: 1,
+    retry: {
+      max_attempts: 4,
+      initial_delay_ms: 1000,
+      max_delay_ms: 30000,
+      multiplier: 2,
+      idle_timeout_ms: 120000,
+    },
+    bandwidth: {
+      upload: 0,
+      download: 0,
+      upload_per_peer: 0,
+      download_per_peer: 0,
+    },
+    conflict_strategy: "rename",
+    clipboard: {
+      write_received: false,
+      sync: false,
+      sync_peers: [],
+      sync_max_bytes: 4 * 1024 * 1024,
+    },
+    open_links_from: [],
+    peer_groups: {},
+  });
+  const [networkInterfaces, setNetworkInterfaces] = useState<
+    NetworkInterface[]
+  >([]);
+  const [peers, setPeers] = useState<Peer[]>([]);
+  const [newGroup, setNewGroup] = useState("");
   const [loading, setLoading] = useState(false);
+  const [redactFileNames, setRedactFileNames] = useState(true);
+  const [exporting, setExporting] = useState(false);
 
   useEffect(() => {
     loadSettings();
@@ -31,6 +137,10 @@ export default function Settings() {
     try {
       const settings = await invoke<AppConfig>("get_settings");
       setConfig(settings);
+      setNetworkInterfaces(
+        await invoke<NetworkInterface[]>("list_network_interfaces")
+      );
+      setPeers(await invoke<Peer[]>("get_peers"));
     } catch (e) {
       console.error(e);
       notifications.show({
@@ -54,7 +164,7 @@ export default function Settings() {
       console.error(e);
       notifications.show({
         title: "Error",
-        message: "Failed to generate random name: " + String(e),
+        message: "Failed to generate random name: " + errorMessage(e),
         color: "red",
       });
     }
@@ -73,7 +183,7 @@ export default function Settings() {
       console.error(e);
       notifications.show({
         title: "Error",
-        message: "Failed to save settings: " + String(e),
+        message: "Failed to save settings: " + errorMessage(e),
         color: "red",
       });
     } finally {
@@ -81,6 +191,67 @@ export default function Settings() {
     }
   };
 
+  const handleExportLogs = async () => {
+    setExporting(true);
+    try {
+      const path = await invoke<string>("export_logs", {
+        redactFileNames,
+      });
+      notifications.show({
+        title: "Logs Exported",
+        message: `Saved to ${path}`,
+        color: "green",
+      });
+    } catch (e) {
+      console.error(e);
+      notifications.show({
+        title: "Error",
+        message: "Failed to export logs: " + errorMessage(e),
+        color: "red",
+      });
+    } finally {
+      setExporting(false);
+    }
+  };
+
+  const setGroupMembers = (name: string, members: string[]) =>
+    setConfig({
+      ...config,
+      peer_groups: { ...config.peer_groups, [name]: members },
+    });
+
+  const handleAddGroup = () => {
+    const name = newGroup.trim();
+    if (!name || name in config.peer_groups) return;
+    setGroupMembers(name, []);
+    setNewGroup("");
+  };
+
+  const handleRemoveGroup = (name: string) =>
+    setConfig({
+      ...config,
+      peer_groups: Object.fromEntries(
+        Object.entries(config.peer_groups).filter(([other]) => other !== name)
+      ),
+    });
+
+  // Devices that aren't around right now are still listed once chosen
+  const deviceOptions = (chosen: string[]) => [
+    ...peers.flatMap((peer) =>
+      peer.device_id
+        ? [
+            {
+              value: peer.device_id,
+              label: `${peer.alias} (${peer.hostname})`,
+            },
+          ]
+        : []
+    ),
+    ...chosen
+      .filter((id) => !peers.some((peer) => peer.device_id === id))
+      .map((id) => ({ value: id, label: `Not nearby (${id.slice(0, 8)})` })),
+  ];
+
   return (
     <Container
       size="100%"
@@ -281,6 +452,470 @@ export default function Settings() {
                 e.currentTarget.style.borderColor = "var(--border-subtle)";
               }}
             />
+            <MultiSelect
+              mt="md"
+              label="Network Interfaces"
+              description="Interfaces to advertise on and accept transfers from. Leave empty to use all of them."
+              placeholder="All interfaces"
+              data={networkInterfaces.map((iface) => ({
+                value: iface.name,
+                label: `${iface.name} (${iface.addresses.join(", ")})`,
+              }))}
+              value={config.interfaces}
+              onChange={(interfaces) => setConfig({ ...config, interfaces })}
+              clearable
+              size="md"
+              styles={{
+                label: {
+                  fontWeight: 600,
+                  fontSize: "1rem",
+                  marginBottom: "0.5rem",
+                  color: "var(--text-primary)",
+                },
+                description: {
+                  fontSize: "0.875rem",
+                  marginTop: "0.5rem",
+                  color: "var(--text-secondary)",
+                },
+              }}
+            />
+          </div>
+
+          <div
+            className="responsive-settings-card rounded-xl p-6"
+            style={{
+              background: "var(--bg-dark)",
+              border: "1px solid var(--border-subtle)",
+              borderRadius: "12px",
+              boxShadow: "var(--shadow-inset)",
+              transition: "var(--transition-normal)",
+            }}
+          >
+            <Text size="md" fw={600} mb="md" c="dimmed" tt="uppercase">
+              Transfers
+            </Text>
+            <NumberInput
+              label="Simultaneous Transfers"
+              description="Files sent at the same time; the rest wait in the queue"
+              value={config.max_concurrent_transfers}
+              onChange={(val) =>
+                setConfig({ ...config, max_concurrent_transfers: Number(val) })
+              }
+              allowNegative={false}
+              allowDecimal={false}
+              min={1}
+              max={16}
+              size="md"
+              styles={{
+                label: {
+                  fontWeight: 600,
+                  fontSize: "1rem",
+                  marginBottom: "0.5rem",
+                  color: "var(--text-primary)",
+                },
+                description: {
+                  fontSize: "0.875rem",
+                  marginTop: "0.5rem",
+                  color: "var(--text-secondary)",
+                },
+              }}
+            />
+            <NumberInput
+              mt="md"
+              label="Simultaneous Transfers per Device"
+              description="Files sent to the same device at the same time"
+              value={config.max_transfers_per_peer}
+              onChange={(val) =>
+                setConfig({ ...config, max_transfers_per_peer: Number(val) })
+              }
+              allowNegative={false}
+              allowDecimal={false}
+              min={1}
+              max={16}
+              size="md"
+              styles={{
+                label: {
+                  fontWeight: 600,
+                  fontSize: "1rem",
+                  marginBottom: "0.5rem",
+                  color: "var(--text-primary)",
+                },
+                description: {
+                  fontSize: "0.875rem",
+                  marginTop: "0.5rem",
+                  color: "var(--text-secondary)",
+                },
+              }}
+            />
+            <NumberInput
+              mt="md"
+              label="Send Attempts"
+              description="How often a send is tried when the connection drops; interrupted files continue where they stopped"
+              value={config.retry.max_attempts}
+              onChange={(val) =>
+                setConfig({
+                  ...config,
+                  retry: { ...config.retry, max_attempts: Number(val) },
+                })
+              }
+              allowNegative={false}
+              allowDecimal={false}
+              min={1}
+              max={10}
+              size="md"
+              styles={{
+                label: {
+                  fontWeight: 600,
+                  fontSize: "1rem",
+                  marginBottom: "0.5rem",
+                  color: "var(--text-primary)",
+                },
+                description: {
+                  fontSize: "0.875rem",
+                  marginTop: "0.5rem",
+                  color: "var(--text-secondary)",
+                },
+              }}
+            />
+            {bandwidthFields.map(({ key, label }) => (
+              <NumberInput
+                key={key}
+                mt="md"
+                label={label}
+                description="In KB/s, 0 for unlimited; applies to running transfers too"
+                value={Math.round(config.bandwidth[key] / 1024)}
+                onChange={(val) =>
+                  setConfig({
+                    ...config,
+                    bandwidth: {
+                      ...config.bandwidth,
+                      [key]: Number(val) * 1024,
+                    },
+                  })
+                }
+                allowNegative={false}
+                allowDecimal={false}
+                min={0}
+                size="md"
+                styles={{
+                  label: {
+                    fontWeight: 600,
+                    fontSize: "1rem",
+                    marginBottom: "0.5rem",
+                    color: "var(--text-primary)",
+                  },
+                  description: {
+                    fontSize: "0.875rem",
+                    marginTop: "0.5rem",
+                    color: "var(--text-secondary)",
+                  },
+                }}
+              />
+            ))}
+            <Select
+              mt="md"
+              label="When a File Already Exists"
+              description={
+                isAndroid()
+                  ? "Android always keeps both files, adding a number to the new one's name"
+                  : "What to do with a received file whose name is taken"
+              }
+              data={conflictStrategies}
+              disabled={isAndroid()}
+              value={config.conflict_strategy}
+              onChange={(val) =>
+                val &&
+                setConfig({
+                  ...config,
+                  conflict_strategy: val as AppConfig["conflict_strategy"],
+                })
+              }
+              allowDeselect={false}
+              size="md"
+              styles={{
+                label: {
+                  fontWeight: 600,
+                  fontSize: "1rem",
+                  marginBottom: "0.5rem",
+                  color: "var(--text-primary)",
+                },
+                description: {
+                  fontSize: "0.875rem",
+                  marginTop: "0.5rem",
+                  color: "var(--text-secondary)",
+                },
+              }}
+            />
+          </div>
+
+          <div
+            className="responsive-settings-card rounded-xl p-6"
+            style={{
+              background: "var(--bg-dark)",
+              border: "1px solid var(--border-subtle)",
+              borderRadius: "12px",
+              boxShadow: "var(--shadow-inset)",
+              transition: "var(--transition-normal)",
+            }}
+          >
+            <Text size="md" fw={600} mb="md" c="dimmed" tt="uppercase">
+              Messages
+            </Text>
+            <MultiSelect
+              label="Open Links Automatically"
+              description="Links these devices send open in your browser right away instead of waiting for you"
+              placeholder="Choose devices"
+              data={deviceOptions(config.open_links_from)}
+              value={config.open_links_from}
+              onChange={(open_links_from) =>
+                setConfig({ ...config, open_links_from })
+              }
+              clearable
+              size="md"
+              styles={{
+                label: {
+                  fontWeight: 600,
+                  fontSize: "1rem",
+                  marginBottom: "0.5rem",
+                  color: "var(--text-primary)",
+                },
+                description: {
+                  fontSize: "0.875rem",
+                  marginTop: "0.5rem",
+                  color: "var(--text-secondary)",
+                },
+              }}
+            />
+          </div>
+
+          <div
+            className="responsive-settings-card rounded-xl p-6"
+            style={{
+              background: "var(--bg-dark)",
+              border: "1px solid var(--border-subtle)",
+              borderRadius: "12px",
+              boxShadow: "var(--shadow-inset)",
+              transition: "var(--transition-normal)",
+            }}
+          >
+            <Text size="md" fw={600} mb="md" c="dimmed" tt="uppercase">
+              Groups
+            </Text>
+            <Text size="sm" c="dimmed">
+              Devices you message together, e.g. everyone in the lab.
+            </Text>
+            {Object.entries(config.peer_groups).map(([name, members]) => (
+              <Group key={name} mt="md" align="flex-end" wrap="nowrap">
+                <MultiSelect
+                  label={name}
+                  placeholder="Choose devices"
+                  data={deviceOptions(members)}
+                  value={members}
+                  onChange={(members) => setGroupMembers(name, members)}
+                  size="md"
+                  style={{ flex: 1 }}
+                  styles={{
+                    label: {
+                      fontWeight: 600,
+                      fontSize: "1rem",
+                      marginBottom: "0.5rem",
+                      color: "var(--text-primary)",
+                    },
+                  }}
+                />
+                <ActionIcon
+                  variant="light"
+                  color="red"
+                  size="xl"
+                  onClick={() => handleRemoveGroup(name)}
+                  aria-label={`Remove group ${name}`}
+                >
+                  <IconTrash size={18} />
+                </ActionIcon>
+              </Group>
+            ))}
+            <Group mt="md" align="flex-end" wrap="nowrap">
+              <TextInput
+                placeholder="New group name"
+                value={newGroup}
+                onChange={(e) => setNewGroup(e.currentTarget.value)}
+                onKeyDown={(e) => e.key === "Enter" && handleAddGroup()}
+                size="md"
+                style={{ flex: 1 }}
+              />
+              <Button
+                variant="light"
+                size="md"
+                onClick={handleAddGroup}
+                disabled={
+                  !newGroup.trim() || newGroup.trim() in config.peer_groups
+                }
+                className="depth-button-secondary"
+              >
+                Add Group
+              </Button>
+            </Group>
+          </div>
+
+          <div
+            className="responsive-settings-card rounded-xl p-6"
+            style={{
+              background: "var(--bg-dark)",
+              border: "1px solid var(--border-subtle)",
+              borderRadius: "12px",
+              boxShadow: "var(--shadow-inset)",
+              transition: "var(--transition-normal)",
+            }}
+          >
+            <Text size="md" fw={600} mb="md" c="dimmed" tt="uppercase">
+              Clipboard
+            </Text>
+            <Text size="sm" c="dimmed" mb="md">
+              Text and images devices send from their clipboard are shown in a
+              notification you can copy them from.
+            </Text>
+            <Checkbox
+              label="Put received clipboard contents into my clipboard"
+              description="Only from devices found on the network"
+              checked={config.clipboard.write_received}
+              onChange={(event) =>
+                setConfig({
+                  ...config,
+                  clipboard: {
+                    ...config.clipboard,
+                    write_received: event.currentTarget.checked,
+                  },
+                })
+              }
+              size="md"
+            />
+            <Checkbox
+              mt="md"
+              label="Keep my clipboard in sync with my devices"
+              description="What you copy here is pasted on them and the other way around"
+              checked={config.clipboard.sync}
+              onChange={(event) =>
+                setConfig({
+                  ...config,
+                  clipboard: {
+                    ...config.clipboard,
+                    sync: event.currentTarget.checked,
+                  },
+                })
+              }
+              size="md"
+            />
+            <MultiSelect
+              mt="md"
+              label="Sync Devices"
+              description="Only choose your own devices: they can change your clipboard without asking"
+              placeholder="Choose devices"
+              data={deviceOptions(config.clipboard.sync_peers)}
+              value={config.clipboard.sync_peers}
+              onChange={(sync_peers) =>
+                setConfig({
+                  ...config,
+                  clipboard: { ...config.clipboard, sync_peers },
+                })
+              }
+              disabled={!config.clipboard.sync}
+              clearable
+              size="md"
+              styles={{
+                label: {
+                  fontWeight: 600,
+                  fontSize: "1rem",
+                  marginBottom: "0.5rem",
+                  color: "var(--text-primary)",
+                },
+                description: {
+                  fontSize: "0.875rem",
+                  marginTop: "0.5rem",
+                  color: "var(--text-secondary)",
+                },
+              }}
+            />
+            <NumberInput
+              mt="md"
+              label="Largest Synced Content"
+              description="In KB; larger text or images stay on this device"
+              value={Math.round(config.clipboard.sync_max_bytes / 1024)}
+              onChange={(val) =>
+                setConfig({
+                  ...config,
+                  clipboard: {
+                    ...config.clipboard,
+                    sync_max_bytes: Number(val) * 1024,
+                  },
+                })
+              }
+              disabled={!config.clipboard.sync}
+              allowNegative={false}
+              allowDecimal={false}
+              min={1}
+              size="md"
+              styles={{
+                label: {
+                  fontWeight: 600,
+                  fontSize: "1rem",
+                  marginBottom: "0.5rem",
+                  color: "var(--text-primary)",
+                },
+                description: {
+                  fontSize: "0.875rem",
+                  marginTop: "0.5rem",
+                  color: "var(--text-secondary)",
+                },
+              }}
+            />
+          </div>
+
+          <div
+            className="responsive-settings-card rounded-xl p-6"
+            style={{
+              background: "var(--bg-dark)",
+              border: "1px solid var(--border-subtle)",
+              borderRadius: "12px",
+              boxShadow: "var(--shadow-inset)",
+              transition: "var(--transition-normal)",
+            }}
+          >
+            <Text size="md" fw={600} mb="md" c="dimmed" tt="uppercase">
+              Diagnostics
+            </Text>
+            <Text size="sm" c="dimmed" mb="md">
+              Save the app logs to your Downloads folder to attach to a bug
+              report.
+            </Text>
+            <Checkbox
+              label="Hide file names"
+              description="Hides the file names and paths recorded alongside log entries. Names quoted in error messages are kept."
+              checked={redactFileNames}
+              onChange={(event) =>
+                setRedactFileNames(event.currentTarget.checked)
+              }
+              size="md"
+            />
+            <Group mt="md">
+              <Button
+                variant="light"
+                size="md"
+                loading={exporting}
+                onClick={handleExportLogs}
+                className="depth-button-secondary w-full sm:w-auto text-sm sm:text-base font-medium"
+                style={{
+                  background:
+                    "linear-gradient(to bottom, var(--bg-lighter), var(--bg-light))",
+                  border: "1px solid var(--border-subtle)",
+                  borderRadius: "8px",
+                  boxShadow: "var(--shadow-s)",
+                  transition: "var(--transition-normal)",
+                  color: "var(--text-primary)",
+                }}
+              >
+                Export Logs
+              </Button>
+            </Group>
           </div>
 
           <Button
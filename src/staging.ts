@@ -0,0 +1,35 @@
+import { invoke } from "@tauri-apps/api/core";
+import { open } from "@tauri-apps/plugin-fs";
+
+// Size of the pieces a file is handed to the backend in
+const CHUNK_SIZE = 1024 * 1024;
+
+// Hand a file the backend can't open by path over in chunks, so it never has
+// to be held in memory as a whole. Returns the id to pass to
+// `send_staged_file`. Mirrors `StagedFiles` in src-tauri/src/staging.rs.
+export async function stageFile(
+  filePath: string,
+  fileName: string
+): Promise<string> {
+  const uploadId = await invoke<string>("stage_file", { fileName });
+  try {
+    const file = await open(filePath, { read: true });
+    try {
+      const buffer = new Uint8Array(CHUNK_SIZE);
+      for (;;) {
+        const read = await file.read(buffer);
+        if (!read) break;
+        // Sent as the raw request body rather than a JSON array of numbers
+        await invoke("append_staged_chunk", buffer.slice(0, read), {
+          headers: { "upload-id": uploadId },
+        });
+      }
+    } finally {
+      await file.close();
+    }
+  } catch (e) {
+    await invoke("discard_staged_file", { uploadId }).catch(() => {});
+    throw e;
+  }
+  return uploadId;
+}